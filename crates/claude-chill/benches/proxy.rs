@@ -0,0 +1,180 @@
+//! Benchmarks for the output path: history, query filtering, sync block
+//! handling and diff rendering.
+//!
+//! Inputs are synthetic full redraws shaped like Claude's: a sync block that
+//! clears the screen and reprints the whole conversation, thousands of lines
+//! long, with a spinner and the newest lines changing between frames.
+//!
+//! Run with `cargo bench`; save a baseline with `-- --save-baseline main` and
+//! compare a change against it with `-- --baseline main`.
+
+use claude_chill::escape_filter::TerminalQueryFilter;
+use claude_chill::escape_sequences::{CLEAR_SCREEN, CURSOR_HOME, SYNC_END, SYNC_START};
+use claude_chill::line_buffer::LineBuffer;
+use claude_chill::proxy_core::{ProxyConfig, ProxyCore};
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const REDRAW_LINES: usize = 5000;
+/// Typical size of a PTY read.
+const READ_SIZE: usize = 4096;
+const SIZES: [(u16, u16); 3] = [(24, 80), (50, 120), (80, 240)];
+const SPINNER: [&str; 4] = ["·", "✢", "✳", "✻"];
+
+/// One full redraw of a conversation `lines` long, as frame `frame` of an
+/// ongoing response.
+fn redraw_frame(lines: usize, frame: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(lines * 80);
+    out.extend_from_slice(SYNC_START);
+    out.extend_from_slice(CLEAR_SCREEN);
+    out.extend_from_slice(CURSOR_HOME);
+    for line in 0..lines {
+        let text = match line % 4 {
+            0 => format!("\x1b[1m> \x1b[0mUser message {}\r\n", line),
+            1 => format!(
+                "\x1b[38;5;174m⏺\x1b[39m Response line {} explaining the change in some detail\r\n",
+                line
+            ),
+            2 => format!(
+                "  \x1b[38;5;244m⎿\x1b[39m  Read \x1b[1msrc/module_{}.rs\x1b[0m (120 lines)\r\n",
+                line
+            ),
+            _ => "\r\n".to_string(),
+        };
+        out.extend_from_slice(text.as_bytes());
+    }
+    // The response streams in, so the last few lines grow each frame
+    for word in 0..frame % 12 {
+        out.extend_from_slice(format!("word{} ", word).as_bytes());
+    }
+    out.extend_from_slice(b"\r\n\r\n");
+    out.extend_from_slice(
+        format!(
+            "\x1b[38;5;174m{} Thinking…\x1b[39m \x1b[38;5;244m({}s · esc to interrupt)\x1b[39m\r\n",
+            SPINNER[frame % SPINNER.len()],
+            frame
+        )
+        .as_bytes(),
+    );
+    out.extend_from_slice("\x1b[38;5;244m╭──────────────────────╮\r\n│ > \x1b[39m".as_bytes());
+    out.extend_from_slice(SYNC_END);
+    out
+}
+
+/// A redraw with terminal queries mixed in, so the filter has work to do.
+fn frame_with_queries(lines: usize) -> Vec<u8> {
+    let mut out = redraw_frame(lines, 0);
+    for query in [&b"\x1b[c"[..], b"\x1b[6n", b"\x1b]11;?\x07", b"\x1b[>0c"] {
+        out.extend_from_slice(query);
+        out.extend_from_slice(&redraw_frame(lines / 100, 1));
+    }
+    out
+}
+
+fn core(rows: u16, cols: u16, now: Instant) -> ProxyCore {
+    let config = ProxyConfig {
+        auto_lookback_timeout_ms: 0,
+        ..ProxyConfig::default()
+    };
+    ProxyCore::new(config, rows, cols, now)
+}
+
+fn feed(core: &mut ProxyCore, data: &[u8], now: Instant) {
+    for chunk in data.chunks(READ_SIZE) {
+        core.output(chunk, now);
+    }
+}
+
+fn line_buffer(c: &mut Criterion) {
+    let frame = redraw_frame(REDRAW_LINES, 0);
+    let mut group = c.benchmark_group("line_buffer");
+    group.throughput(Throughput::Bytes(frame.len() as u64));
+    group.bench_function("push_bytes", |b| {
+        let mut buffer = LineBuffer::new(ProxyConfig::default().max_history_lines);
+        b.iter(|| buffer.push_bytes(black_box(&frame)));
+    });
+    group.finish();
+}
+
+fn query_filter(c: &mut Criterion) {
+    let frame = frame_with_queries(REDRAW_LINES);
+    let mut group = c.benchmark_group("query_filter");
+    group.throughput(Throughput::Bytes(frame.len() as u64));
+    group.bench_function("whole", |b| {
+        let mut filter = TerminalQueryFilter::new();
+        b.iter(|| filter.filter(black_box(&frame)));
+    });
+    group.bench_function("reads", |b| {
+        let mut filter = TerminalQueryFilter::new();
+        b.iter(|| {
+            for chunk in frame.chunks(READ_SIZE) {
+                black_box(filter.filter(black_box(chunk)));
+            }
+        });
+    });
+    group.finish();
+}
+
+/// Feeding a full redraw through the core: VT emulation, sync block
+/// buffering and the history reset, without rendering.
+fn sync_redraw(c: &mut Criterion) {
+    let frames = [redraw_frame(REDRAW_LINES, 0), redraw_frame(REDRAW_LINES, 1)];
+    let mut group = c.benchmark_group("sync_redraw");
+    group.throughput(Throughput::Bytes(frames[0].len() as u64));
+    for (rows, cols) in SIZES {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{}x{}", cols, rows)),
+            &(rows, cols),
+            |b, &(rows, cols)| {
+                let now = Instant::now();
+                let mut core = core(rows, cols, now);
+                let mut frame = 0;
+                b.iter(|| {
+                    feed(&mut core, &frames[frame % 2], now);
+                    frame += 1;
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Rendering the screen as a diff against the previous frame, which is what
+/// the proxy writes to the terminal after every redraw.
+fn render_diff(c: &mut Criterion) {
+    let frames = [redraw_frame(REDRAW_LINES, 0), redraw_frame(REDRAW_LINES, 1)];
+    let mut group = c.benchmark_group("render_diff");
+    for (rows, cols) in SIZES {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(format!("{}x{}", cols, rows)),
+            &(rows, cols),
+            |b, &(rows, cols)| {
+                let mut now = Instant::now();
+                let mut core = core(rows, cols, now);
+                feed(&mut core, &frames[1], now);
+                now += Duration::from_secs(1);
+                core.tick(now);
+                core.take_terminal_output();
+                let mut frame = 0;
+                b.iter_custom(|iters| {
+                    let mut total = Duration::ZERO;
+                    for _ in 0..iters {
+                        feed(&mut core, &frames[frame % 2], now);
+                        frame += 1;
+                        now += Duration::from_secs(1);
+                        let start = Instant::now();
+                        core.tick(now);
+                        total += start.elapsed();
+                        black_box(core.take_terminal_output());
+                    }
+                    total
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, line_buffer, query_filter, sync_redraw, render_diff);
+criterion_main!(benches);
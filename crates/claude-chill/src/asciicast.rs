@@ -0,0 +1,155 @@
+//! Writer for asciinema's asciicast v2 format.
+//!
+//! A recording is a JSON header line followed by one JSON array per event:
+//! `[seconds, "o", data]` for output and `[seconds, "r", "COLSxROWS"]` for
+//! resizes. See <https://docs.asciinema.org/manual/asciicast/v2/>.
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize)]
+struct Header {
+    version: u32,
+    width: u16,
+    height: u16,
+    timestamp: u64,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    env: BTreeMap<&'static str, String>,
+}
+
+pub struct AsciicastWriter {
+    writer: Box<dyn Write + Send>,
+    start: Instant,
+    /// Trailing bytes of an incomplete UTF-8 sequence, since events must be
+    /// valid strings but reads can split characters.
+    utf8_pending: Vec<u8>,
+}
+
+impl AsciicastWriter {
+    pub fn create(path: &Path, cols: u16, rows: u16) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("failed to create recording {}", path.display()))?;
+        Self::new(Box::new(BufWriter::new(file)), cols, rows)
+    }
+
+    pub fn new(mut writer: Box<dyn Write + Send>, cols: u16, rows: u16) -> Result<Self> {
+        let env = ["SHELL", "TERM"]
+            .into_iter()
+            .filter_map(|name| std::env::var(name).ok().map(|value| (name, value)))
+            .collect();
+        let header = Header {
+            version: 2,
+            width: cols,
+            height: rows,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            env,
+        };
+        serde_json::to_writer(&mut writer, &header)?;
+        writer.write_all(b"\n")?;
+        Ok(Self {
+            writer,
+            start: Instant::now(),
+            utf8_pending: Vec::new(),
+        })
+    }
+
+    pub fn output(&mut self, data: &[u8]) -> Result<()> {
+        self.utf8_pending.extend_from_slice(data);
+        let complete = complete_utf8_len(&self.utf8_pending);
+        if complete == 0 {
+            return Ok(());
+        }
+        let text = String::from_utf8_lossy(&self.utf8_pending[..complete]).into_owned();
+        self.utf8_pending.drain(..complete);
+        self.event("o", &text)
+    }
+
+    pub fn resize(&mut self, cols: u16, rows: u16) -> Result<()> {
+        self.event("r", &format!("{}x{}", cols, rows))
+    }
+
+    fn event(&mut self, code: &str, data: &str) -> Result<()> {
+        let time = self.start.elapsed().as_secs_f64();
+        serde_json::to_writer(&mut self.writer, &(time, code, data))?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Length of the longest prefix that doesn't end inside a UTF-8 sequence.
+fn complete_utf8_len(data: &[u8]) -> usize {
+    let len = data.len();
+    // A UTF-8 sequence is at most 4 bytes, so only the tail needs checking
+    for back in 1..=len.min(3) {
+        let byte = data[len - back];
+        if byte & 0xc0 == 0x80 {
+            continue;
+        }
+        let width = match byte {
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return len,
+        };
+        return if back < width { len - back } else { len };
+    }
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{SharedBuffer, lines};
+
+    #[test]
+    fn test_header() {
+        let buffer = SharedBuffer::default();
+        AsciicastWriter::new(Box::new(buffer.clone()), 80, 24).unwrap();
+        let lines = lines(&buffer);
+        assert_eq!(lines[0]["version"], 2);
+        assert_eq!(lines[0]["width"], 80);
+        assert_eq!(lines[0]["height"], 24);
+    }
+
+    #[test]
+    fn test_output_and_resize_events() {
+        let buffer = SharedBuffer::default();
+        let mut writer = AsciicastWriter::new(Box::new(buffer.clone()), 80, 24).unwrap();
+        writer.output(b"hello\r\n\x1b[31m").unwrap();
+        writer.resize(100, 30).unwrap();
+        let lines = lines(&buffer);
+        assert_eq!(lines[1][1], "o");
+        assert_eq!(lines[1][2], "hello\r\n\x1b[31m");
+        assert_eq!(lines[2][1], "r");
+        assert_eq!(lines[2][2], "100x30");
+    }
+
+    #[test]
+    fn test_split_utf8_is_joined() {
+        let buffer = SharedBuffer::default();
+        let mut writer = AsciicastWriter::new(Box::new(buffer.clone()), 80, 24).unwrap();
+        let bytes = "a€".as_bytes();
+        writer.output(&bytes[..2]).unwrap();
+        writer.output(&bytes[2..]).unwrap();
+        let lines = lines(&buffer);
+        assert_eq!(lines[1][2], "a");
+        assert_eq!(lines[2][2], "€");
+    }
+
+    #[test]
+    fn test_complete_utf8_len() {
+        assert_eq!(complete_utf8_len(b"abc"), 3);
+        assert_eq!(complete_utf8_len(&"€".as_bytes()[..2]), 0);
+        assert_eq!(complete_utf8_len("a€".as_bytes()), 4);
+        assert_eq!(complete_utf8_len(b"a\xff"), 2);
+    }
+}
@@ -0,0 +1,157 @@
+//! `claude-chill attach` and `watch`: connect the terminal to a running
+//! session, to drive it or only to look.
+
+use crate::proxy::{TerminalGuard, get_terminal_size, setup_signal_handler, write_all};
+use crate::session::{self, ClientMessage, FrameReader, Message, ServerMessage};
+use anyhow::{Context, Result};
+use nix::errno::Errno;
+use nix::poll::{PollFd, PollFlags, PollTimeout, poll};
+use nix::sys::signal::Signal;
+use nix::unistd::read;
+use std::io::{self, Write};
+use std::os::fd::AsFd;
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static SIGWINCH_RECEIVED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_sigwinch(_: libc::c_int) {
+    SIGWINCH_RECEIVED.store(true, Ordering::SeqCst);
+}
+
+/// Print the running sessions, one id per line.
+pub fn list() -> Result<()> {
+    for id in session::list(&session::session_dir())? {
+        println!("{}", id);
+    }
+    Ok(())
+}
+
+/// Attach to session `id`, or the only one running, until it detaches us or
+/// exits. Returns the exit code to use.
+pub fn run(id: Option<&str>) -> Result<i32> {
+    let winsize = get_terminal_size()?;
+    let attach = ClientMessage::Attach {
+        rows: winsize.ws_row,
+        cols: winsize.ws_col,
+    };
+    match connect(id, &attach, false)? {
+        Some(ServerMessage::Detached) => {
+            eprintln!("[detached]");
+            Ok(0)
+        }
+        Some(ServerMessage::Exited(code)) => {
+            eprintln!("[session ended]");
+            Ok(code)
+        }
+        Some(ServerMessage::Refused(reason)) => {
+            anyhow::bail!("session refused attach: {}", reason)
+        }
+        Some(ServerMessage::Output(_)) | None => unreachable!("only viewers stop on their own"),
+    }
+}
+
+/// Watch session `id`, or the only one running, without being able to type
+/// into it, until `q` or `Ctrl+C` is pressed or the session exits.
+pub fn watch(id: Option<&str>) -> Result<i32> {
+    let result = connect(id, &ClientMessage::Watch, true);
+    // Viewers are drawn with the session's pen and cursor; put ours back
+    let _ = write_all(&io::stdout(), b"\x1b[m\x1b[?25h\r\n");
+    match result? {
+        Some(ServerMessage::Exited(_)) => {
+            eprintln!("[session ended]");
+            Ok(0)
+        }
+        Some(ServerMessage::Refused(reason)) => {
+            anyhow::bail!("session refused viewer: {}", reason)
+        }
+        Some(_) | None => Ok(0),
+    }
+}
+
+/// Connect, introduce ourselves with `hello` and pump the terminal until
+/// the session sends its last message, or `None` if a viewer quit.
+fn connect(
+    id: Option<&str>,
+    hello: &ClientMessage,
+    read_only: bool,
+) -> Result<Option<ServerMessage>> {
+    let path = session::resolve(&session::session_dir(), id)?;
+    let mut stream = UnixStream::connect(&path)
+        .with_context(|| format!("failed to connect to {}", path.display()))?;
+    stream.write_all(&hello.encode())?;
+
+    let guard = TerminalGuard::new()?;
+    if !read_only {
+        setup_signal_handler(Signal::SIGWINCH, handle_sigwinch)?;
+    }
+    let result = pump(&mut stream, read_only);
+    drop(guard);
+    result
+}
+
+/// Copy stdin to the session and the session's output to stdout until the
+/// session sends its last message. A read-only viewer's keys aren't sent;
+/// `q` or `Ctrl+C` stops it instead.
+fn pump(stream: &mut UnixStream, read_only: bool) -> Result<Option<ServerMessage>> {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut reader = FrameReader::default();
+    let mut buf = [0u8; 65536];
+    let mut stdin_open = true;
+
+    loop {
+        if SIGWINCH_RECEIVED.swap(false, Ordering::SeqCst)
+            && let Ok(winsize) = get_terminal_size()
+        {
+            stream.write_all(
+                &ClientMessage::Resize {
+                    rows: winsize.ws_row,
+                    cols: winsize.ws_col,
+                }
+                .encode(),
+            )?;
+        }
+
+        let mut poll_fds = vec![PollFd::new(stream.as_fd(), PollFlags::POLLIN)];
+        if stdin_open {
+            poll_fds.push(PollFd::new(stdin.as_fd(), PollFlags::POLLIN));
+        }
+        match poll(&mut poll_fds, PollTimeout::NONE) {
+            Ok(_) => {}
+            Err(Errno::EINTR) => continue,
+            Err(e) => anyhow::bail!("poll failed: {}", e),
+        }
+        let socket_ready = poll_fds[0].any().unwrap_or(false);
+        let stdin_ready = poll_fds.get(1).and_then(|fd| fd.any()).unwrap_or(false);
+
+        if socket_ready {
+            match read(stream.as_fd(), &mut buf) {
+                Ok(0) => anyhow::bail!("session closed the connection"),
+                Ok(n) => reader.push(&buf[..n]),
+                Err(Errno::EINTR) | Err(Errno::EAGAIN) => {}
+                Err(e) => anyhow::bail!("read from session failed: {}", e),
+            }
+            while let Some(message) = reader.next_message::<ServerMessage>()? {
+                match message {
+                    ServerMessage::Output(data) => write_all(&stdout, &data)?,
+                    last => return Ok(Some(last)),
+                }
+            }
+        }
+
+        if stdin_ready {
+            match read(stdin.as_fd(), &mut buf) {
+                Ok(0) => stdin_open = false,
+                Ok(n) if read_only => {
+                    if buf[..n].iter().any(|&b| b == b'q' || b == 0x03) {
+                        return Ok(None);
+                    }
+                }
+                Ok(n) => stream.write_all(&ClientMessage::Input(buf[..n].to_vec()).encode())?,
+                Err(Errno::EINTR) | Err(Errno::EAGAIN) => {}
+                Err(e) => anyhow::bail!("read from stdin failed: {}", e),
+            }
+        }
+    }
+}
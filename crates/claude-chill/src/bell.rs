@@ -0,0 +1,154 @@
+//! Finds the BEL characters in child output that ring the terminal bell.
+//!
+//! BEL also ends OSC sequences (`ESC ] 0 ; title BEL`), where it is only a
+//! terminator, and is ignored inside DCS, SOS, PM and APC strings. Those are
+//! told apart from real bells, including across chunk boundaries.
+
+use serde::{Deserialize, Serialize};
+
+const BEL: u8 = 0x07;
+const ESC: u8 = 0x1B;
+const CAN: u8 = 0x18;
+const SUB: u8 = 0x1A;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum BellState {
+    #[default]
+    Ground,
+    Escape,       // Saw ESC
+    Osc,          // Inside ESC ], ended by BEL or ST
+    String,       // Inside ESC P, X, ^ or _, ended by ST
+    StringEscape, // Saw ESC inside a string, ST if \ follows
+}
+
+/// What the proxy does when the child rings the bell.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BellAction {
+    /// Pass it on to the terminal.
+    #[default]
+    Forward,
+    Suppress,
+    /// Flash the screen in reverse video instead.
+    Flash,
+    /// Show a desktop notification instead, in the configured style.
+    Notify,
+}
+
+#[derive(Debug, Default)]
+pub struct BellScanner {
+    state: BellState,
+}
+
+impl BellScanner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Positions in `data` of the bells that ring.
+    pub fn scan(&mut self, data: &[u8]) -> Vec<usize> {
+        let mut bells = Vec::new();
+        for (pos, &byte) in data.iter().enumerate() {
+            if self.feed(byte) {
+                bells.push(pos);
+            }
+        }
+        bells
+    }
+
+    /// Whether the next BEL would end an OSC sequence rather than ring.
+    pub fn in_string(&self) -> bool {
+        matches!(
+            self.state,
+            BellState::Osc | BellState::String | BellState::StringEscape
+        )
+    }
+
+    /// Advance over one byte, returning whether it is a bell that rings.
+    fn feed(&mut self, byte: u8) -> bool {
+        match self.state {
+            BellState::Ground => {
+                if byte == ESC {
+                    self.state = BellState::Escape;
+                }
+                byte == BEL
+            }
+            BellState::Escape => {
+                self.state = match byte {
+                    b']' => BellState::Osc,
+                    b'P' | b'X' | b'^' | b'_' => BellState::String,
+                    // Controls are executed in the middle of a sequence
+                    BEL | ESC => self.state,
+                    _ => BellState::Ground,
+                };
+                byte == BEL
+            }
+            BellState::Osc | BellState::String => {
+                match byte {
+                    ESC => self.state = BellState::StringEscape,
+                    CAN | SUB => self.state = BellState::Ground,
+                    BEL if self.state == BellState::Osc => self.state = BellState::Ground,
+                    _ => {}
+                }
+                false
+            }
+            BellState::StringEscape => {
+                if byte == b'\\' {
+                    self.state = BellState::Ground;
+                    false
+                } else {
+                    // An ESC that isn't ST ends the string and starts a new
+                    // sequence
+                    self.state = BellState::Escape;
+                    self.feed(byte)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_bells_ring() {
+        let mut scanner = BellScanner::new();
+        assert_eq!(scanner.scan(b"done\x07 and \x07"), vec![4, 10]);
+        assert_eq!(scanner.scan(b"\x1b[1m\x07"), vec![4]);
+        assert!(scanner.scan(b"no bell").is_empty());
+    }
+
+    #[test]
+    fn test_string_terminators_do_not_ring() {
+        let mut scanner = BellScanner::new();
+        assert!(scanner.scan(b"\x1b]0;title\x07").is_empty());
+        assert!(scanner.scan(b"\x1b]8;;http://x\x1b\\link").is_empty());
+        assert!(scanner.scan(b"\x1bPq\x07\x1b\\").is_empty());
+        // Rings again once the string is over
+        assert_eq!(scanner.scan(b"\x1b]2;t\x07\x07"), vec![6]);
+    }
+
+    #[test]
+    fn test_split_across_chunks() {
+        let mut scanner = BellScanner::new();
+        assert!(scanner.scan(b"\x1b").is_empty());
+        assert!(scanner.scan(b"]9;Claude is waiting").is_empty());
+        assert!(scanner.in_string());
+        assert!(scanner.scan(b"\x07").is_empty());
+        assert!(!scanner.in_string());
+        assert_eq!(scanner.scan(b"\x07"), vec![0]);
+
+        assert!(scanner.scan(b"\x1b]0;t\x1b").is_empty());
+        assert!(scanner.scan(b"\\").is_empty());
+        assert_eq!(scanner.scan(b"\x07"), vec![0]);
+    }
+
+    #[test]
+    fn test_aborted_string() {
+        let mut scanner = BellScanner::new();
+        assert_eq!(scanner.scan(b"\x1b]0;t\x18\x07"), vec![6]);
+        // ESC starting another sequence also ends the string
+        assert_eq!(scanner.scan(b"\x1b]0;t\x1b[m\x07"), vec![8]);
+    }
+}
@@ -1,10 +1,13 @@
 mod cli;
 
-use clap::Parser;
-use claude_chill::config::Config;
-use claude_chill::key_parser;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use claude_chill::attach;
+use claude_chill::config::{Config, LoadOptions};
 use claude_chill::proxy::{Proxy, ProxyConfig};
+use claude_chill::replay::{self, ReplayOptions};
 use std::process::ExitCode;
+use toml::{Table, Value};
 
 fn main() -> ExitCode {
     // Only enable logging if CLAUDE_CHILL_LOG_FILE is set
@@ -24,38 +27,107 @@ fn main() -> ExitCode {
     }
 
     let cli = cli::Cli::parse();
-    let config = Config::load();
-
-    let history_lines = cli.history_lines.unwrap_or(config.history_lines);
+    if let Some(cli::CliCommand::Config(args)) = &cli.subcommand {
+        let cli::ConfigCommand::Show(show) = &args.command;
+        let layers = Config::layers(&LoadOptions {
+            profile: show.profile.clone(),
+            command: show.command.clone(),
+            settings: Table::new(),
+        });
+        print!("{}", layers.report());
+        return match layers.config() {
+            Ok(_) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Invalid config: {}", e);
+                ExitCode::from(1)
+            }
+        };
+    }
 
-    let lookback_key = cli
-        .lookback_key
-        .clone()
-        .unwrap_or_else(|| config.lookback_key.clone());
+    let config = Config::load(&LoadOptions {
+        profile: cli.profile.clone(),
+        command: cli.command.clone(),
+        settings: cli_settings(&cli),
+    });
 
-    let lookback_sequence = match key_parser::parse(&lookback_key) {
-        Ok(key) => key.to_escape_sequence(),
-        Err(e) => {
-            eprintln!("Invalid lookback key '{}': {}", lookback_key, e);
-            eprintln!("Using default: [ctrl][6]");
-            config.lookback_sequence()
-        }
+    let proxy_config = ProxyConfig {
+        env: config.env.clone(),
+        cwd: config.cwd(),
+        max_history_lines: config.history_lines,
+        lookback_key: config.lookback_key.clone(),
+        lookback_sequence: config.lookback_sequence(),
+        auto_lookback_timeout_ms: config.auto_lookback_timeout_ms,
+        lookback_mouse: config.lookback_mouse,
+        detach_sequence: config.detach_sequence(),
+        idle_timeout_ms: config.idle_timeout_ms,
+        macros: config.input_macros(),
+        hooks: config.hooks.clone(),
+        notifications: config.notifications.clone(),
+        bell: config.bell.clone(),
+        watchdog: config.watchdog(),
+        restart: config.restart.clone(),
+        detectors: config.detectors(),
+        record_input: cli.record_input.clone(),
+        record: cli.record.clone(),
+        record_raw: cli.record_raw.clone(),
     };
 
-    let auto_lookback_timeout_ms = cli
-        .auto_lookback_timeout
-        .unwrap_or(config.auto_lookback_timeout_ms);
+    if let Some(cli::CliCommand::Replay(args)) = &cli.subcommand {
+        let options = ReplayOptions {
+            speed: args.speed,
+            start: args.start,
+            headless: args.headless,
+        };
+        return match replay::run(&args.recording, proxy_config, &options) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("Replay failed: {:#}", e);
+                ExitCode::from(1)
+            }
+        };
+    }
+    if let Some(cli::CliCommand::Attach(args)) = &cli.subcommand {
+        return match attach::run(args.id.as_deref()) {
+            Ok(exit_code) => ExitCode::from(exit_code as u8),
+            Err(e) => {
+                eprintln!("Attach failed: {:#}", e);
+                ExitCode::from(1)
+            }
+        };
+    }
+    if let Some(cli::CliCommand::Watch(args)) = &cli.subcommand {
+        return match attach::watch(args.id.as_deref()) {
+            Ok(exit_code) => ExitCode::from(exit_code as u8),
+            Err(e) => {
+                eprintln!("Watch failed: {:#}", e);
+                ExitCode::from(1)
+            }
+        };
+    }
+    if let Some(cli::CliCommand::List) = &cli.subcommand {
+        return match attach::list() {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("{:#}", e);
+                ExitCode::from(1)
+            }
+        };
+    }
 
-    let proxy_config = ProxyConfig {
-        max_history_lines: history_lines,
-        lookback_key,
-        lookback_sequence,
-        auto_lookback_timeout_ms,
+    // Arguments from the config file only go with its command
+    let (command, args) = match (&cli.command, &config.command) {
+        (Some(command), _) => (command, &cli.args),
+        (None, Some(command)) => (command, &config.args),
+        (None, None) => cli::Cli::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "no command given, and none set with `command` in claude-chill.toml",
+            )
+            .exit(),
     };
+    let cmd_args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
 
-    let cmd_args: Vec<&str> = cli.args.iter().map(|s| s.as_str()).collect();
-
-    match Proxy::spawn(&cli.command, &cmd_args, proxy_config) {
+    match Proxy::spawn(command, &cmd_args, proxy_config) {
         Ok(mut proxy) => match proxy.run() {
             Ok(exit_code) => ExitCode::from(exit_code as u8),
             Err(e) => {
@@ -69,3 +141,21 @@ fn main() -> ExitCode {
         }
     }
 }
+
+/// The settings given as flags, the top layer of the config.
+fn cli_settings(cli: &cli::Cli) -> Table {
+    let mut settings = Table::new();
+    if let Some(lines) = cli.history_lines {
+        settings.insert("history_lines".into(), Value::Integer(lines as i64));
+    }
+    if let Some(key) = &cli.lookback_key {
+        settings.insert("lookback_key".into(), Value::String(key.clone()));
+    }
+    if let Some(timeout) = cli.auto_lookback_timeout {
+        settings.insert(
+            "auto_lookback_timeout_ms".into(),
+            Value::Integer(timeout as i64),
+        );
+    }
+    settings
+}
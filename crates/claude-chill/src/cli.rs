@@ -1,19 +1,33 @@
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
+use claude_chill::replay::ReplaySpeed;
+use std::path::PathBuf;
 
 #[derive(Parser, Debug)]
 #[command(
     name = "claude-chill",
     version,
-    about = "A PTY proxy that tames Claude Code's massive terminal updates"
+    about = "A PTY proxy that tames Claude Code's massive terminal updates",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    subcommand_value_name = "SUBCOMMAND",
+    disable_help_subcommand = true,
+    after_help = "To run a command named like a subcommand, put -- before it: claude-chill -- watch -n 1 date"
 )]
 pub struct Cli {
-    /// Command to run (e.g., "claude")
-    pub command: String,
+    #[command(subcommand)]
+    pub subcommand: Option<CliCommand>,
+
+    /// Command to run (e.g., "claude"), default: `command` from the config file
+    pub command: Option<String>,
 
     /// Arguments to pass to the command
     #[arg(trailing_var_arg = true)]
     pub args: Vec<String>,
 
+    /// Config profile to use (default: the one for the command, if any)
+    #[arg(short = 'p', long = "profile")]
+    pub profile: Option<String>,
+
     /// Max lines stored for lookback (default: 100000)
     #[arg(short = 'H', long = "history")]
     pub history_lines: Option<usize>,
@@ -25,4 +39,82 @@ pub struct Cli {
     /// Auto-lookback timeout in ms, 0 to disable (default: 5000)
     #[arg(short = 'a', long = "auto-lookback-timeout")]
     pub auto_lookback_timeout: Option<u64>,
+
+    /// Record all input sent to the command, with timestamps, to a JSON lines file
+    #[arg(long = "record-input", value_name = "FILE")]
+    pub record_input: Option<PathBuf>,
+
+    /// Record the proxied terminal output as an asciicast v2 file
+    #[arg(long = "record", value_name = "FILE")]
+    pub record: Option<PathBuf>,
+
+    /// Record the command's raw output (before proxying) as an asciicast v2 file
+    #[arg(long = "record-raw", value_name = "FILE")]
+    pub record_raw: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CliCommand {
+    /// Replay a recording made with --record-raw through the proxy
+    Replay(ReplayArgs),
+    /// Attach this terminal to a detached session
+    Attach(AttachArgs),
+    /// Watch a session read-only, quit with q or Ctrl+C
+    Watch(WatchArgs),
+    /// List running sessions
+    List,
+    /// Inspect the config
+    Config(ConfigArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    pub command: ConfigCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ConfigCommand {
+    /// Print each effective setting and where it came from
+    Show(ConfigShowArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct ConfigShowArgs {
+    /// Command whose profile to apply
+    pub command: Option<String>,
+
+    /// Config profile to apply (default: the one for the command, if any)
+    #[arg(short = 'p', long = "profile")]
+    pub profile: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct AttachArgs {
+    /// Session to attach to (default: the only one running)
+    pub id: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct WatchArgs {
+    /// Session to watch (default: the only one running)
+    pub id: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct ReplayArgs {
+    /// asciicast v2 recording of a command's output
+    pub recording: PathBuf,
+
+    /// Playback speed, a multiplier like 2 or 0.5, or "max" for as fast as possible
+    #[arg(short = 's', long = "speed", default_value = "1")]
+    pub speed: ReplaySpeed,
+
+    /// Seconds into the recording to start playback
+    #[arg(long = "start", default_value_t = 0.0)]
+    pub start: f64,
+
+    /// Replay as fast as possible without drawing and print statistics
+    #[arg(long = "headless")]
+    pub headless: bool,
 }
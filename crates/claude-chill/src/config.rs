@@ -1,69 +1,332 @@
+use crate::bell::BellAction;
+use crate::detector::{ActivityState, Detector, DetectorScope};
 use crate::key_parser::{self, KeyCombination};
-use serde::Deserialize;
+use crate::macros::InputMacro;
+use crate::notification::NotificationStyle;
+use crate::watchdog;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
 
 const DEFAULT_LOOKBACK_KEY: &str = "[ctrl][6]";
 const DEFAULT_REFRESH_RATE: u64 = 20;
 const DEFAULT_AUTO_LOOKBACK_TIMEOUT_MS: u64 = 5000;
+const DEFAULT_IDLE_TIMEOUT_MS: u64 = 5000;
+const DEFAULT_HOOK_CONTEXT_LINES: usize = 20;
+const DEFAULT_NOTIFY_MIN_ACTIVE_MS: u64 = 2000;
+const DEFAULT_BELL_MIN_INTERVAL_MS: u64 = 500;
+const DEFAULT_BUSY_CPU_PERCENT: u32 = 90;
+const DEFAULT_RESTART_COUNTDOWN_MS: u64 = 5000;
+const DEFAULT_MAX_RESTARTS: u32 = 3;
 
-#[derive(Debug, Clone, Deserialize)]
+const SYSTEM_CONFIG_PATH: &str = "/etc/claude-chill.toml";
+/// Looked for in the current directory and its parents.
+const PROJECT_CONFIG_NAME: &str = ".claude-chill.toml";
+const ENV_PREFIX: &str = "CLAUDE_CHILL_";
+/// Names the user config file in place of the usual one.
+const CONFIG_FILE_VAR: &str = "CLAUDE_CHILL_CONFIG_FILE";
+/// Settings a project file can't make, as they run commands or type into the
+/// session.
+const PROJECT_DENIED: &[&str] = &[
+    "command",
+    "args",
+    "env",
+    "cwd",
+    "hooks",
+    "macros",
+    "restart.args",
+];
+/// Settings that are unset by default, so missing from the serialized
+/// defaults.
+const OPTIONAL_SETTINGS: &[&str] = &["command", "cwd"];
+/// Longest `key = value` that the sources in `config show` are lined up
+/// after.
+const REPORT_WIDTH: usize = 48;
+
+/// What Claude Code's screen looks like in each state, checked in order.
+const DEFAULT_DETECTORS: &[(ActivityState, &str, DetectorScope)] = &[
+    (
+        ActivityState::WaitingForApproval,
+        r"Do you want to .*\?",
+        DetectorScope::Screen,
+    ),
+    (
+        ActivityState::Working,
+        r"(?i)esc to interrupt",
+        DetectorScope::Screen,
+    ),
+    (
+        ActivityState::IdleAtPrompt,
+        r"^[\s│]*>",
+        DetectorScope::CursorRow,
+    ),
+];
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(default)]
 pub struct Config {
+    /// Command to run when none is given on the command line.
+    pub command: Option<String>,
+    /// Arguments for `command`; not used with a command line command.
+    pub args: Vec<String>,
+    pub env: EnvConfig,
+    /// Working directory for the command, `~` for the home directory.
+    pub cwd: Option<String>,
     pub history_lines: usize,
     pub lookback_key: String,
     pub refresh_rate: u64,
     pub auto_lookback_timeout_ms: u64,
+    pub lookback_mouse: bool,
+    /// Key that detaches the terminal and leaves the session running, or
+    /// empty (the default) to disable, so the key reaches the command.
+    pub detach_key: String,
+    /// How long the child must stay quiet to count as idle, or 0 to never.
+    pub idle_timeout_ms: u64,
+    pub macros: Vec<MacroConfig>,
+    pub hooks: HooksConfig,
+    pub notifications: NotificationsConfig,
+    pub bell: BellConfig,
+    pub watchdog: WatchdogConfig,
+    pub restart: RestartConfig,
+    /// Screen patterns that tell what Claude is doing, checked in order.
+    pub detectors: Vec<DetectorConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MacroConfig {
+    pub key: String,
+    pub payload: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct DetectorConfig {
+    pub state: ActivityState,
+    pub pattern: String,
+    #[serde(default)]
+    pub scope: DetectorScope,
+}
+
+/// Shell commands run when something happens in the session.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HooksConfig {
+    pub on_idle: Option<String>,
+    pub on_bell: Option<String>,
+    pub on_exit: Option<String>,
+    pub on_alt_screen: Option<String>,
+    /// Lines of history passed to hooks in `CLAUDE_CHILL_LAST_LINES`.
+    pub context_lines: usize,
+}
+
+impl Default for HooksConfig {
+    fn default() -> Self {
+        Self {
+            on_idle: None,
+            on_bell: None,
+            on_exit: None,
+            on_alt_screen: None,
+            context_lines: DEFAULT_HOOK_CONTEXT_LINES,
+        }
+    }
+}
+
+/// Changes to the environment the command runs in.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct EnvConfig {
+    pub set: BTreeMap<String, String>,
+    pub unset: Vec<String>,
+}
+
+/// What happens when the child rings the bell.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BellConfig {
+    pub action: BellAction,
+    /// Bells closer together than this after the last one are dropped.
+    pub min_interval_ms: u64,
+}
+
+impl Default for BellConfig {
+    fn default() -> Self {
+        Self {
+            action: BellAction::Forward,
+            min_interval_ms: DEFAULT_BELL_MIN_INTERVAL_MS,
+        }
+    }
+}
+
+/// When to decide the child is hung, and what to do about it.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    /// No output for this long while not at a prompt, or 0 to never.
+    pub silence_timeout_ms: u64,
+    /// Using at least `busy_cpu_percent` of a CPU for this long, or 0 to
+    /// never.
+    pub busy_timeout_ms: u64,
+    pub busy_cpu_percent: u32,
+    /// Signal to send the child, e.g. `SIGINT`, besides showing a banner.
+    pub signal: Option<String>,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            silence_timeout_ms: 0,
+            busy_timeout_ms: 0,
+            busy_cpu_percent: DEFAULT_BUSY_CPU_PERCENT,
+            signal: None,
+        }
+    }
+}
+
+/// Restarting the command when it fails.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RestartConfig {
+    pub enabled: bool,
+    /// Arguments to restart the command with instead of the original ones,
+    /// e.g. `["--continue"]`.
+    pub args: Option<Vec<String>>,
+    /// How long the user has to cancel a restart.
+    pub countdown_ms: u64,
+    /// Give up after this many restarts.
+    pub max_restarts: u32,
+}
+
+impl Default for RestartConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            args: None,
+            countdown_ms: DEFAULT_RESTART_COUNTDOWN_MS,
+            max_restarts: DEFAULT_MAX_RESTARTS,
+        }
+    }
+}
+
+/// Desktop notifications shown by the terminal through escape sequences.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct NotificationsConfig {
+    pub style: NotificationStyle,
+    pub on_idle: bool,
+    pub on_bell: bool,
+    /// When a detector sees Claude asking for permission.
+    pub on_approval: bool,
+    /// How long the child must have been busy since the last key press for
+    /// going idle to be worth a notification.
+    pub min_active_ms: u64,
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        Self {
+            style: NotificationStyle::Off,
+            on_idle: true,
+            on_bell: true,
+            on_approval: true,
+            min_active_ms: DEFAULT_NOTIFY_MIN_ACTIVE_MS,
+        }
+    }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
+            command: None,
+            args: Vec::new(),
+            env: EnvConfig::default(),
+            cwd: None,
             history_lines: 100_000,
             lookback_key: DEFAULT_LOOKBACK_KEY.to_string(),
             refresh_rate: DEFAULT_REFRESH_RATE,
             auto_lookback_timeout_ms: DEFAULT_AUTO_LOOKBACK_TIMEOUT_MS,
+            lookback_mouse: true,
+            detach_key: String::new(),
+            idle_timeout_ms: DEFAULT_IDLE_TIMEOUT_MS,
+            macros: Vec::new(),
+            hooks: HooksConfig::default(),
+            notifications: NotificationsConfig::default(),
+            bell: BellConfig::default(),
+            watchdog: WatchdogConfig::default(),
+            restart: RestartConfig::default(),
+            detectors: DEFAULT_DETECTORS
+                .iter()
+                .map(|&(state, pattern, scope)| DetectorConfig {
+                    state,
+                    pattern: pattern.to_string(),
+                    scope,
+                })
+                .collect(),
         }
     }
 }
 
 impl Config {
-    pub fn load() -> Self {
-        let config_path = Self::config_path();
-        match config_path {
-            Some(path) if path.exists() => Self::load_from_file(&path),
-            _ => Self::default(),
+    /// Load the config from all its layers, warning about (and falling back
+    /// to the defaults for) an invalid one.
+    pub fn load(options: &LoadOptions) -> Self {
+        match Self::layers(options).config() {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Warning: Invalid config: {}", e);
+                Self::default()
+            }
         }
     }
 
-    pub fn config_path() -> Option<PathBuf> {
-        dirs::config_dir().map(|d| d.join("claude-chill.toml"))
+    /// Stack up the config files on disk and the `CLAUDE_CHILL_*`
+    /// environment variables with `options`.
+    pub fn layers(options: &LoadOptions) -> Layers {
+        let files = Self::config_files()
+            .into_iter()
+            .filter_map(read_config)
+            .collect();
+        let project = std::env::current_dir()
+            .ok()
+            .and_then(|dir| project_config(&dir))
+            .and_then(read_config);
+        Layers::build(files, project, std::env::vars(), options)
     }
 
-    fn load_from_file(path: &PathBuf) -> Self {
-        match fs::read_to_string(path) {
-            Ok(content) => match toml::from_str(&content) {
-                Ok(config) => config,
-                Err(e) => {
-                    eprintln!(
-                        "Warning: Failed to parse config file {}: {}",
-                        path.display(),
-                        e
-                    );
-                    Self::default()
-                }
-            },
-            Err(e) => {
-                eprintln!(
-                    "Warning: Failed to read config file {}: {}",
-                    path.display(),
-                    e
-                );
-                Self::default()
-            }
+    pub fn config_path() -> Option<PathBuf> {
+        match std::env::var_os(CONFIG_FILE_VAR) {
+            Some(path) if !path.is_empty() => Some(PathBuf::from(path)),
+            _ => dirs::config_dir().map(|d| d.join("claude-chill.toml")),
         }
     }
 
+    /// The system and user config files that exist, lowest layer first.
+    pub fn config_files() -> Vec<PathBuf> {
+        [Some(PathBuf::from(SYSTEM_CONFIG_PATH)), Self::config_path()]
+            .into_iter()
+            .flatten()
+            .filter(|path| path.is_file())
+            .collect()
+    }
+
+    /// Parse a single config file, applying a profile the way `load` does.
+    /// An unknown profile is warned about and left out.
+    pub fn parse(
+        content: &str,
+        profile: Option<&str>,
+        command: Option<&str>,
+    ) -> Result<Self, toml::de::Error> {
+        toml::from_str::<Table>(content)?;
+        let options = LoadOptions {
+            profile: profile.map(str::to_string),
+            command: command.map(str::to_string),
+            settings: Table::new(),
+        };
+        let files = vec![(PathBuf::from("claude-chill.toml"), content.to_string())];
+        Layers::build(files, None, [], &options).config()
+    }
+
     pub fn parse_lookback_key(&self) -> Result<KeyCombination, key_parser::ParseKeyError> {
         key_parser::parse(&self.lookback_key)
     }
@@ -83,12 +346,442 @@ impl Config {
             })
     }
 
+    /// The detach key's escape sequence, empty if detaching is disabled.
+    pub fn detach_sequence(&self) -> Vec<u8> {
+        if self.detach_key.is_empty() {
+            return Vec::new();
+        }
+        match key_parser::parse(&self.detach_key) {
+            Ok(key) => key.to_escape_sequence(),
+            Err(e) => {
+                eprintln!("Warning: Invalid detach_key '{}': {}", self.detach_key, e);
+                eprintln!("Detaching with a key is disabled");
+                Vec::new()
+            }
+        }
+    }
+
+    /// Parse configured macros, skipping (and warning about) invalid ones.
+    pub fn input_macros(&self) -> Vec<InputMacro> {
+        self.macros
+            .iter()
+            .filter_map(|m| match InputMacro::parse(&m.key, &m.payload) {
+                Ok(input_macro) => Some(input_macro),
+                Err(e) => {
+                    eprintln!("Warning: Ignoring macro for key '{}': {}", m.key, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Compile configured detectors, skipping (and warning about) invalid
+    /// patterns.
+    pub fn detectors(&self) -> Vec<Detector> {
+        self.detectors
+            .iter()
+            .filter_map(|d| match Detector::new(d.state, &d.pattern, d.scope) {
+                Ok(detector) => Some(detector),
+                Err(e) => {
+                    eprintln!("Warning: Ignoring detector '{}': {}", d.pattern, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// The command's working directory, with a leading `~` expanded.
+    pub fn cwd(&self) -> Option<PathBuf> {
+        let cwd = self.cwd.as_deref()?;
+        match cwd.strip_prefix('~') {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+                let home = dirs::home_dir()?;
+                Some(home.join(rest.trim_start_matches('/')))
+            }
+            _ => Some(PathBuf::from(cwd)),
+        }
+    }
+
+    /// The watchdog settings, dropping (and warning about) an unknown
+    /// signal.
+    pub fn watchdog(&self) -> WatchdogConfig {
+        let mut watchdog = self.watchdog.clone();
+        if let Some(name) = &watchdog.signal
+            && let Err(e) = watchdog::parse_signal(name)
+        {
+            eprintln!("Warning: Ignoring watchdog signal: {}", e);
+            watchdog.signal = None;
+        }
+        watchdog
+    }
+
     pub fn redraw_throttle_ms(&self) -> u64 {
         let rate = self.refresh_rate.max(1);
         1000 / rate
     }
 }
 
+/// Where a setting's value came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    /// A profile in a config file.
+    Profile {
+        name: String,
+        path: PathBuf,
+    },
+    /// A `CLAUDE_CHILL_*` environment variable.
+    Env(String),
+    CommandLine,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Profile { name, path } => {
+                write!(f, "profile {} in {}", name, path.display())
+            }
+            Source::Env(var) => write!(f, "env {}", var),
+            Source::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+/// What the command line adds to the config files.
+#[derive(Debug, Clone, Default)]
+pub struct LoadOptions {
+    /// Profile to apply instead of the one for the command.
+    pub profile: Option<String>,
+    /// Command given on the command line, which picks the profile.
+    pub command: Option<String>,
+    /// Settings given as flags, e.g. `history_lines` for `--history`.
+    pub settings: Table,
+}
+
+/// The config as a stack of layers, each overriding the ones below it key by
+/// key: the defaults, the config files, the profile, environment variables
+/// and the command line. Remembers which layer each value came from.
+#[derive(Debug, Clone)]
+pub struct Layers {
+    values: Table,
+    /// Layer that last set each dotted key, tables included.
+    sources: BTreeMap<String, Source>,
+    /// Config files read, lowest first.
+    files: Vec<PathBuf>,
+    profile: Option<String>,
+}
+
+impl Layers {
+    /// Stack `files` (path and contents, lowest first) and the `project`
+    /// file over the defaults, then the profile, the `CLAUDE_CHILL_*`
+    /// variables in `env` and the command line settings. Files that don't
+    /// parse and layers with invalid values are warned about and left out,
+    /// as are settings in the project file that could run commands.
+    pub fn build(
+        files: Vec<(PathBuf, String)>,
+        project: Option<(PathBuf, String)>,
+        env: impl IntoIterator<Item = (String, String)>,
+        options: &LoadOptions,
+    ) -> Self {
+        let defaults = match Value::try_from(Config::default()) {
+            Ok(Value::Table(defaults)) => defaults,
+            _ => Table::new(),
+        };
+        let mut layers = Self {
+            values: Table::new(),
+            sources: BTreeMap::new(),
+            files: Vec::new(),
+            profile: None,
+        };
+        layers.add(defaults.clone(), &Source::Default);
+
+        let mut profiles = Vec::new();
+        let files = files.into_iter().map(|file| (file, false));
+        for ((path, content), is_project) in files.chain(project.map(|file| (file, true))) {
+            let mut table: Table = match toml::from_str(&content) {
+                Ok(table) => table,
+                Err(e) => {
+                    eprintln!(
+                        "Warning: Failed to parse config file {}: {}",
+                        path.display(),
+                        e
+                    );
+                    continue;
+                }
+            };
+            if is_project {
+                drop_commands(&mut table, "", &path);
+            }
+            if let Some(Value::Table(mut file_profiles)) = table.remove("profile") {
+                if is_project {
+                    for (name, profile) in file_profiles.iter_mut() {
+                        if let Value::Table(profile) = profile {
+                            drop_commands(profile, &format!("profile.{}.", name), &path);
+                        }
+                    }
+                }
+                profiles.push((path.clone(), file_profiles));
+            }
+            layers.add(table, &Source::File(path.clone()));
+            layers.files.push(path);
+        }
+
+        let mut env: Vec<(String, Table)> = env
+            .into_iter()
+            .filter_map(|(var, value)| Some((var.clone(), env_setting(&defaults, &var, &value)?)))
+            .collect();
+        env.sort_by(|a, b| a.0.cmp(&b.0));
+        // The profile goes under the environment, but the command that picks
+        // it can come from there too
+        let command_in = |table: &Table| table.get("command")?.as_str().map(str::to_string);
+        let command = options
+            .command
+            .clone()
+            .or_else(|| env.iter().rev().find_map(|(_, table)| command_in(table)))
+            .or_else(|| command_in(&layers.values));
+        layers.apply_profile(profiles, options.profile.as_deref(), command.as_deref());
+
+        for (var, setting) in env {
+            layers.add(setting, &Source::Env(var));
+        }
+        layers.add(options.settings.clone(), &Source::CommandLine);
+        layers
+    }
+
+    /// The effective config.
+    pub fn config(&self) -> Result<Config, toml::de::Error> {
+        Value::Table(self.values.clone()).try_into()
+    }
+
+    /// The layer that set `key`, a dotted path like `hooks.on_idle`.
+    pub fn source(&self, key: &str) -> Option<&Source> {
+        let mut key = key;
+        loop {
+            if let Some(source) = self.sources.get(key) {
+                return Some(source);
+            }
+            key = &key[..key.rfind('.')?];
+        }
+    }
+
+    /// Each effective value as a `key = value` line, commented with the layer
+    /// it came from, after the files read and the profile applied.
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        if self.files.is_empty() {
+            out.push_str("# No config files\n");
+        }
+        for path in &self.files {
+            out.push_str(&format!("# File: {}\n", path.display()));
+        }
+        if let Some(profile) = &self.profile {
+            out.push_str(&format!("# Profile: {}\n", profile));
+        }
+
+        let mut settings = Vec::new();
+        flatten(&self.values, "", &mut settings);
+        let lines: Vec<(String, String)> = settings
+            .into_iter()
+            .map(|(key, value)| {
+                let source = self
+                    .source(&key)
+                    .map_or("default".into(), Source::to_string);
+                (format!("{} = {}", key, value), source)
+            })
+            .collect();
+        let width = lines
+            .iter()
+            .map(|(line, _)| line.len())
+            .filter(|&len| len <= REPORT_WIDTH)
+            .max()
+            .unwrap_or(0);
+        for (line, source) in lines {
+            out.push_str(&format!("{:<width$}  # {}\n", line, source));
+        }
+        out
+    }
+
+    /// Merge in the layer `table` from `source`, unless it makes the config
+    /// invalid, in which case it's warned about and left out.
+    fn add(&mut self, table: Table, source: &Source) {
+        let mut values = self.values.clone();
+        merge(&mut values, table.clone(), "", &mut |_| {});
+        if let Err(e) = Value::Table(values).try_into::<Config>() {
+            eprintln!("Warning: Ignoring config from {}: {}", source, e);
+            return;
+        }
+        let sources = &mut self.sources;
+        merge(&mut self.values, table, "", &mut |key| {
+            sources.insert(key, source.clone());
+        });
+    }
+
+    /// Apply the profile named `profile` or, without one, the profile for
+    /// `command`, as each file defines it in turn.
+    fn apply_profile(
+        &mut self,
+        profiles: Vec<(PathBuf, Table)>,
+        profile: Option<&str>,
+        command: Option<&str>,
+    ) {
+        let mut all = Table::new();
+        for (_, file_profiles) in &profiles {
+            merge(&mut all, file_profiles.clone(), "", &mut |_| {});
+        }
+        let name = match profile {
+            Some(name) => name.to_string(),
+            None => match command.and_then(|command| matching_profile(&all, command)) {
+                Some(name) => name,
+                None => return,
+            },
+        };
+        if !matches!(all.get(&name), Some(Value::Table(_))) {
+            eprintln!("Warning: Unknown profile '{}'", name);
+            return;
+        }
+        for (path, mut file_profiles) in profiles {
+            if let Some(Value::Table(mut overrides)) = file_profiles.remove(&name) {
+                overrides.remove("commands");
+                let source = Source::Profile {
+                    name: name.clone(),
+                    path,
+                };
+                self.add(overrides, &source);
+            }
+        }
+        self.profile = Some(name);
+    }
+}
+
+/// A config file's path and contents, or `None` with a warning if it can't
+/// be read.
+fn read_config(path: PathBuf) -> Option<(PathBuf, String)> {
+    match fs::read_to_string(&path) {
+        Ok(content) => Some((path, content)),
+        Err(e) => {
+            eprintln!(
+                "Warning: Failed to read config file {}: {}",
+                path.display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Remove the settings that run commands or type into the session from a
+/// project file's `table`, warning about each. Anyone could have written the
+/// file, e.g. in a cloned repository.
+fn drop_commands(table: &mut Table, prefix: &str, path: &Path) {
+    for key in PROJECT_DENIED {
+        let (parent, name) = match key.split_once('.') {
+            Some((parent, name)) => (table.get_mut(parent).and_then(Value::as_table_mut), name),
+            None => (Some(&mut *table), *key),
+        };
+        if parent.and_then(|parent| parent.remove(name)).is_some() {
+            eprintln!(
+                "Warning: Ignoring {}{} in {}: project files can't run commands",
+                prefix,
+                key,
+                path.display()
+            );
+        }
+    }
+}
+
+/// The nearest project config file in or above `dir`.
+fn project_config(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_NAME))
+        .find(|path| path.is_file())
+}
+
+/// The setting an environment variable makes, as a table to layer: e.g.
+/// `hooks.on_idle` for `CLAUDE_CHILL_HOOKS__ON_IDLE`. Variables that aren't
+/// settings, like `CLAUDE_CHILL_SESSION`, are `None`. Values are read as
+/// TOML, and as plain strings if they aren't valid TOML.
+fn env_setting(defaults: &Table, var: &str, value: &str) -> Option<Table> {
+    let name = var.strip_prefix(ENV_PREFIX)?.to_ascii_lowercase();
+    let path: Vec<&str> = name.split("__").collect();
+    let top = path[0];
+    if path.iter().any(|key| key.is_empty())
+        || !(defaults.contains_key(top) || OPTIONAL_SETTINGS.contains(&top))
+    {
+        return None;
+    }
+    let mut value = toml::from_str::<Table>(&format!("value = {}", value))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_string()));
+    for key in path.iter().rev() {
+        value = Value::Table(Table::from_iter([(key.to_string(), value)]));
+    }
+    match value {
+        Value::Table(table) => Some(table),
+        _ => None,
+    }
+}
+
+/// Every value in `table` that isn't a non-empty table, by dotted key.
+fn flatten(table: &Table, prefix: &str, out: &mut Vec<(String, Value)>) {
+    for (key, value) in table {
+        let key = dotted(prefix, key);
+        match value {
+            Value::Table(table) if !table.is_empty() => flatten(table, &key, out),
+            value => out.push((key, value.clone())),
+        }
+    }
+}
+
+fn dotted(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", prefix, key)
+    }
+}
+
+/// The profile for `command`: the first one listing its name in `commands`
+/// or, without a list, named after it.
+fn matching_profile(profiles: &Table, command: &str) -> Option<String> {
+    let name = Path::new(command).file_name()?.to_str()?;
+    profiles
+        .iter()
+        .find(|(profile, overrides)| match overrides.get("commands") {
+            Some(Value::Array(commands)) => commands.iter().any(|c| c.as_str() == Some(name)),
+            _ => profile.as_str() == name,
+        })
+        .map(|(profile, _)| profile.clone())
+}
+
+/// Merge `overrides` into `table`, key by key within nested tables, calling
+/// `set` with the dotted key of each value and table set.
+fn merge(table: &mut Table, overrides: Table, prefix: &str, set: &mut impl FnMut(String)) {
+    for (key, value) in overrides {
+        let path = dotted(prefix, &key);
+        set(path.clone());
+        match value {
+            Value::Table(value) => {
+                let base = table
+                    .entry(key)
+                    .or_insert_with(|| Value::Table(Table::new()));
+                if !base.is_table() {
+                    *base = Value::Table(Table::new());
+                }
+                if let Value::Table(base) = base {
+                    merge(base, value, &path, set);
+                }
+            }
+            value => {
+                table.insert(key, value);
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -101,6 +794,13 @@ mod tests {
         assert_eq!(config.refresh_rate, 20);
         assert_eq!(config.redraw_throttle_ms(), 50);
         assert_eq!(config.auto_lookback_timeout_ms, 5000);
+        assert!(config.lookback_mouse);
+        assert!(config.macros.is_empty());
+        assert_eq!(config.idle_timeout_ms, 5000);
+        assert_eq!(config.hooks, HooksConfig::default());
+        assert_eq!(config.notifications.style, NotificationStyle::Off);
+        assert_eq!(config.detectors().len(), 3);
+        assert_eq!(config.bell.action, BellAction::Forward);
     }
 
     #[test]
@@ -108,4 +808,439 @@ mod tests {
         let config = Config::default();
         assert_eq!(config.lookback_sequence(), vec![0x1E]);
     }
+
+    #[test]
+    fn test_detach_sequence() {
+        let mut config = Config::default();
+        assert!(config.detach_sequence().is_empty());
+        config.detach_key = "[ctrl][backslash]".to_string();
+        assert_eq!(config.detach_sequence(), vec![0x1C]);
+        config.detach_key = "[nope]".to_string();
+        assert!(config.detach_sequence().is_empty());
+    }
+
+    #[test]
+    fn test_parse_macros() {
+        let config: Config = toml::from_str(
+            r#"
+            [[macros]]
+            key = "[f5]"
+            payload = '/compact\<enter>'
+
+            [[macros]]
+            key = "[bogus]"
+            payload = "x"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.macros.len(), 2);
+        let macros = config.input_macros();
+        assert_eq!(macros.len(), 1);
+        assert_eq!(macros[0].payload, b"/compact\r");
+    }
+
+    #[test]
+    fn test_parse_hooks() {
+        let config: Config = toml::from_str(
+            r#"
+            [bell]
+            action = "flash"
+            min_interval_ms = 0
+
+            [hooks]
+            on_idle = "notify-send 'Claude is idle'"
+            context_lines = 5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.hooks.on_idle.as_deref(),
+            Some("notify-send 'Claude is idle'")
+        );
+        assert_eq!(config.hooks.on_bell, None);
+        assert_eq!(config.hooks.context_lines, 5);
+        assert_eq!(
+            config.bell,
+            BellConfig {
+                action: BellAction::Flash,
+                min_interval_ms: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_detectors() {
+        let config: Config = toml::from_str(
+            r#"
+            [[detectors]]
+            state = "waiting-for-approval"
+            pattern = "Allow this\\?"
+
+            [[detectors]]
+            state = "idle-at-prompt"
+            pattern = "^> "
+            scope = "cursor-row"
+
+            [[detectors]]
+            state = "working"
+            pattern = "("
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.detectors.len(), 3);
+        assert_eq!(config.detectors[1].scope, DetectorScope::CursorRow);
+        let detectors = config.detectors();
+        assert_eq!(detectors.len(), 2);
+        assert_eq!(detectors[0].state, ActivityState::WaitingForApproval);
+    }
+
+    #[test]
+    fn test_parse_watchdog() {
+        let config: Config = toml::from_str(
+            r#"
+            [watchdog]
+            silence_timeout_ms = 600000
+            signal = "SIGINT"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.watchdog(),
+            WatchdogConfig {
+                silence_timeout_ms: 600_000,
+                signal: Some("SIGINT".to_string()),
+                ..WatchdogConfig::default()
+            }
+        );
+
+        let config: Config = toml::from_str("[watchdog]\nsignal = \"SIGNOPE\"").unwrap();
+        assert_eq!(config.watchdog().signal, None);
+    }
+
+    #[test]
+    fn test_parse_command() {
+        let config: Config = toml::from_str(
+            r#"
+            command = "claude"
+            args = ["--model", "opus"]
+            cwd = "~/src"
+
+            [env]
+            set = { CLAUDE_CODE_ENABLE_TELEMETRY = "0" }
+            unset = ["ANTHROPIC_API_KEY"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.command.as_deref(), Some("claude"));
+        assert_eq!(config.args, ["--model", "opus"]);
+        assert_eq!(config.env.set["CLAUDE_CODE_ENABLE_TELEMETRY"], "0");
+        assert_eq!(config.env.unset, ["ANTHROPIC_API_KEY"]);
+        assert_eq!(config.cwd(), dirs::home_dir().map(|home| home.join("src")));
+
+        let config = Config {
+            cwd: Some("~other/src".to_string()),
+            ..Config::default()
+        };
+        assert_eq!(config.cwd(), Some(PathBuf::from("~other/src")));
+        assert_eq!(Config::default().cwd(), None);
+    }
+
+    #[test]
+    fn test_parse_restart() {
+        let config: Config = toml::from_str(
+            r#"
+            [restart]
+            enabled = true
+            args = ["--continue"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.restart,
+            RestartConfig {
+                enabled: true,
+                args: Some(vec!["--continue".to_string()]),
+                ..RestartConfig::default()
+            }
+        );
+        assert!(!Config::default().restart.enabled);
+    }
+
+    const PROFILES: &str = r#"
+        history_lines = 5000
+        lookback_key = "[f12]"
+
+        [hooks]
+        on_idle = "notify-send idle"
+
+        [profile.claude]
+        idle_timeout_ms = 1000
+
+        [profile.agents]
+        commands = ["aider", "codex"]
+        history_lines = 20000
+        refresh_rate = 60
+        hooks = { on_bell = "true" }
+
+        [profile.quiet]
+        command = "claude"
+        hooks = { on_idle = "" }
+    "#;
+
+    #[test]
+    fn test_profile_matches_command() {
+        let config = Config::parse(PROFILES, None, Some("/usr/bin/claude")).unwrap();
+        assert_eq!(config.idle_timeout_ms, 1000);
+        assert_eq!(config.history_lines, 5000);
+
+        let config = Config::parse(PROFILES, None, Some("aider")).unwrap();
+        assert_eq!(config.history_lines, 20000);
+        assert_eq!(config.refresh_rate, 60);
+        assert_eq!(config.lookback_key, "[f12]");
+        // Tables are merged rather than replaced
+        assert_eq!(config.hooks.on_idle.as_deref(), Some("notify-send idle"));
+        assert_eq!(config.hooks.on_bell.as_deref(), Some("true"));
+
+        let config = Config::parse(PROFILES, None, Some("bash")).unwrap();
+        assert_eq!(config.history_lines, 5000);
+        assert_eq!(config.idle_timeout_ms, DEFAULT_IDLE_TIMEOUT_MS);
+    }
+
+    #[test]
+    fn test_explicit_profile() {
+        let config = Config::parse(PROFILES, Some("quiet"), Some("aider")).unwrap();
+        assert_eq!(config.command.as_deref(), Some("claude"));
+        assert_eq!(config.history_lines, 5000);
+        assert_eq!(config.hooks.on_idle.as_deref(), Some(""));
+
+        // Unknown profiles are left out
+        let config = Config::parse(PROFILES, Some("nope"), Some("claude")).unwrap();
+        assert_eq!(config.idle_timeout_ms, DEFAULT_IDLE_TIMEOUT_MS);
+
+        // Without a command line command, the config file's one is matched
+        let config = Config::parse(
+            "command = \"claude\"\n[profile.claude]\nhistory_lines = 7",
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(config.history_lines, 7);
+        // A profile with a bad value is left out
+        let config = Config::parse(
+            "history_lines = 3\n[profile.claude]\nhistory_lines = \"x\"",
+            None,
+            Some("claude"),
+        )
+        .unwrap();
+        assert_eq!(config.history_lines, 3);
+    }
+
+    fn layers(env: &[(&str, &str)], options: &LoadOptions) -> Layers {
+        let files =
+            vec![
+            (
+                PathBuf::from("/etc/claude-chill.toml"),
+                "history_lines = 1000\nrefresh_rate = 30\n[profile.claude]\ndetach_key = \"[ctrl][d]\"\n"
+                    .to_string(),
+            ),
+            (PathBuf::from("/home/me/.config/claude-chill.toml"), PROFILES.to_string()),
+        ];
+        let project = (
+            PathBuf::from("/src/app/.claude-chill.toml"),
+            "refresh_rate = 60\n[profile.claude]\nbell = { action = \"flash\" }\n".to_string(),
+        );
+        let env = env
+            .iter()
+            .map(|(var, value)| (var.to_string(), value.to_string()));
+        Layers::build(files, Some(project), env, options)
+    }
+
+    #[test]
+    fn test_layers() {
+        let settings = Table::from_iter([("lookback_key".to_string(), Value::from("[f9]"))]);
+        let options = LoadOptions {
+            command: Some("claude".to_string()),
+            settings,
+            ..LoadOptions::default()
+        };
+        let layers = layers(&[("CLAUDE_CHILL_HISTORY_LINES", "42")], &options);
+        let config = layers.config().unwrap();
+        assert_eq!(config.history_lines, 42);
+        assert_eq!(config.refresh_rate, 60);
+        assert_eq!(config.idle_timeout_ms, 1000);
+        assert_eq!(config.detach_key, "[ctrl][d]");
+        assert_eq!(config.lookback_key, "[f9]");
+        assert_eq!(config.hooks.on_idle.as_deref(), Some("notify-send idle"));
+        assert_eq!(config.bell.action, BellAction::Flash);
+
+        let source = |key| layers.source(key).unwrap().to_string();
+        assert_eq!(source("history_lines"), "env CLAUDE_CHILL_HISTORY_LINES");
+        assert_eq!(source("refresh_rate"), "/src/app/.claude-chill.toml");
+        assert_eq!(
+            source("idle_timeout_ms"),
+            "profile claude in /home/me/.config/claude-chill.toml"
+        );
+        assert_eq!(
+            source("detach_key"),
+            "profile claude in /etc/claude-chill.toml"
+        );
+        assert_eq!(source("lookback_key"), "command line");
+        assert_eq!(
+            source("hooks.on_idle"),
+            "/home/me/.config/claude-chill.toml"
+        );
+        assert_eq!(
+            source("bell.action"),
+            "profile claude in /src/app/.claude-chill.toml"
+        );
+        assert_eq!(source("hooks.context_lines"), "default");
+        assert_eq!(source("detectors"), "default");
+    }
+
+    #[test]
+    fn test_project_file_cannot_run_commands() {
+        let project = r#"
+            command = "/tmp/evil"
+            args = ["--yes"]
+            cwd = "/tmp"
+            refresh_rate = 60
+            env = { set = { LD_PRELOAD = "/tmp/evil.so" } }
+            hooks = { on_idle = "/tmp/evil", context_lines = 5 }
+            macros = [{ key = "[f1]", payload = "run /tmp/evil\\r" }]
+            restart = { enabled = true, args = ["--yes"] }
+
+            [profile.claude]
+            idle_timeout_ms = 1000
+            hooks = { on_exit = "/tmp/evil" }
+        "#;
+        let options = LoadOptions {
+            command: Some("claude".to_string()),
+            ..LoadOptions::default()
+        };
+        let project = (
+            PathBuf::from("/src/app/.claude-chill.toml"),
+            project.to_string(),
+        );
+        let config = Layers::build(Vec::new(), Some(project), [], &options)
+            .config()
+            .unwrap();
+        assert_eq!(config.command, None);
+        assert!(config.args.is_empty());
+        assert_eq!(config.cwd, None);
+        assert!(config.env.set.is_empty());
+        assert_eq!(config.hooks, HooksConfig::default());
+        assert!(config.macros.is_empty());
+        assert_eq!(config.restart.args, None);
+        // Everything else still applies
+        assert_eq!(config.refresh_rate, 60);
+        assert!(config.restart.enabled);
+        assert_eq!(config.idle_timeout_ms, 1000);
+    }
+
+    #[test]
+    fn test_invalid_layers_are_left_out() {
+        let files = vec![
+            (
+                PathBuf::from("/etc/claude-chill.toml"),
+                "lookback_mouse = false\nrefresh_rate = \"fast\"\n".to_string(),
+            ),
+            (
+                PathBuf::from("/home/me/.config/claude-chill.toml"),
+                "history_lines = 5000\nrefresh_rate = 30\n".to_string(),
+            ),
+        ];
+        let env = [("CLAUDE_CHILL_HISTORY_LINES".to_string(), "lots".to_string())];
+        let options = LoadOptions {
+            settings: Table::from_iter([("lookback_key".to_string(), Value::from("[f9]"))]),
+            ..LoadOptions::default()
+        };
+        let layers = Layers::build(files, None, env, &options);
+        let config = layers.config().unwrap();
+        // Only the layers with bad values are gone
+        assert!(config.lookback_mouse);
+        assert_eq!(config.refresh_rate, 30);
+        assert_eq!(config.history_lines, 5000);
+        assert_eq!(config.lookback_key, "[f9]");
+        assert_eq!(
+            layers.source("history_lines"),
+            Some(&Source::File(PathBuf::from(
+                "/home/me/.config/claude-chill.toml"
+            )))
+        );
+    }
+
+    #[test]
+    fn test_env_settings() {
+        let options = LoadOptions::default();
+        let layers = layers(
+            &[
+                ("CLAUDE_CHILL_COMMAND", "aider"),
+                ("CLAUDE_CHILL_HOOKS__ON_IDLE", "say done"),
+                ("CLAUDE_CHILL_LOOKBACK_MOUSE", "false"),
+                ("CLAUDE_CHILL_ARGS", "[\"--yes\"]"),
+                // Not settings
+                ("CLAUDE_CHILL", "1"),
+                ("CLAUDE_CHILL_SESSION", "1234"),
+                ("CLAUDE_CHILL_HOOKS__", "x"),
+            ],
+            &options,
+        );
+        let config = layers.config().unwrap();
+        assert_eq!(config.command.as_deref(), Some("aider"));
+        assert_eq!(config.args, ["--yes"]);
+        assert_eq!(config.hooks.on_idle.as_deref(), Some("say done"));
+        assert!(!config.lookback_mouse);
+        // The command from the environment picks the profile
+        assert_eq!(config.history_lines, 20000);
+        assert_eq!(layers.source("session"), None);
+    }
+
+    #[test]
+    fn test_report() {
+        let options = LoadOptions {
+            profile: Some("quiet".to_string()),
+            ..LoadOptions::default()
+        };
+        let report = layers(&[], &options).report();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[0], "# File: /etc/claude-chill.toml");
+        assert_eq!(lines[3], "# Profile: quiet");
+        // Each setting with its source
+        let line = |key: &str| {
+            let start = format!("{} = ", key);
+            let line = lines.iter().find(|line| line.starts_with(&start)).unwrap();
+            let (setting, source) = line.split_once("  # ").unwrap();
+            (
+                setting.trim_end()[start.len()..].to_string(),
+                source.to_string(),
+            )
+        };
+        assert_eq!(
+            line("history_lines"),
+            ("5000".into(), "/home/me/.config/claude-chill.toml".into())
+        );
+        assert_eq!(
+            line("hooks.on_idle"),
+            (
+                "\"\"".into(),
+                "profile quiet in /home/me/.config/claude-chill.toml".into()
+            )
+        );
+        assert_eq!(line("env.set"), ("{}".into(), "default".into()));
+        assert!(!report.contains("commands"));
+
+        let report = Layers::build(Vec::new(), None, [], &LoadOptions::default()).report();
+        assert!(report.starts_with("# No config files\n"));
+    }
+
+    #[test]
+    fn test_project_config() {
+        let dir = std::env::temp_dir().join(format!("claude-chill-project-{}", std::process::id()));
+        let nested = dir.join("src").join("deep");
+        fs::create_dir_all(&nested).unwrap();
+        assert_ne!(project_config(&nested), Some(dir.join(PROJECT_CONFIG_NAME)));
+        fs::write(dir.join(PROJECT_CONFIG_NAME), "").unwrap();
+        assert_eq!(project_config(&nested), Some(dir.join(PROJECT_CONFIG_NAME)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
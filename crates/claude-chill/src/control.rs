@@ -0,0 +1,425 @@
+//! The control socket: line-delimited JSON commands for scripts and editor
+//! plugins to query and drive a running session.
+//!
+//! Each session listens on `<session dir>/<id>.ctl`. A request is one JSON
+//! object per line with a `cmd` and the command's fields, plus an optional
+//! `id` that is echoed in the reply:
+//!
+//! ```text
+//! {"id": 1, "cmd": "get-state"}
+//! {"id": 1, "ok": true, "mode": "normal", "idle_ms": 5400, ...}
+//! {"cmd": "send-input", "data": "/compact\r"}
+//! {"ok": true}
+//! {"cmd": "resize"}
+//! {"ok": false, "error": "missing field `rows`"}
+//! ```
+
+use crate::detector::ActivityState;
+use crate::session;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Longer request lines mean a confused or hostile peer.
+const MAX_LINE_LEN: usize = 1024 * 1024;
+/// Replies queued for a controller that isn't reading; past this it's dropped.
+const MAX_CONTROLLER_BACKLOG: usize = 64 * 1024 * 1024;
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "cmd", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Request {
+    GetState {},
+    /// History as lookback mode shows it, optionally only the last `lines`.
+    DumpHistory {
+        #[serde(default)]
+        format: TextFormat,
+        #[serde(default)]
+        lines: Option<usize>,
+    },
+    GetScreenText {
+        #[serde(default)]
+        format: TextFormat,
+    },
+    ToggleLookback {},
+    /// Type `data` into the child, bypassing key bindings.
+    SendInput {
+        data: String,
+    },
+    Resize {
+        rows: u16,
+        cols: u16,
+    },
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TextFormat {
+    /// Plain text, one line per row.
+    #[default]
+    Text,
+    /// With the escape sequences that color and position it.
+    Ansi,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Mode {
+    Normal,
+    Lookback,
+    AltScreen,
+}
+
+/// Which terminal is driving the session.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Attached {
+    /// The terminal the session started in.
+    Terminal,
+    /// A `claude-chill attach` client.
+    Client,
+    None,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct State {
+    pub session: String,
+    pub mode: Mode,
+    pub alt_screen: bool,
+    pub lookback: bool,
+    /// What the detectors make of the screen, `null` if they can't tell.
+    pub activity: Option<ActivityState>,
+    /// Milliseconds since the child last wrote anything.
+    pub idle_ms: Option<u64>,
+    pub rows: u16,
+    pub cols: u16,
+    pub history_lines: usize,
+    pub attached: Attached,
+    pub viewers: usize,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(untagged)]
+pub enum Response {
+    State(State),
+    History { history: String },
+    Screen { text: String, cursor: [u16; 2] },
+    Lookback { lookback: bool },
+    Done {},
+}
+
+#[derive(Serialize)]
+struct Reply<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<&'a Value>,
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(flatten)]
+    response: Option<&'a Response>,
+}
+
+/// Parse one request line, returning its `id` even if the rest is invalid
+/// so the error reply can carry it.
+pub fn parse_request(line: &str) -> (Option<Value>, Result<Request>) {
+    let mut value: Value = match serde_json::from_str(line) {
+        Ok(value) => value,
+        Err(e) => return (None, Err(e.into())),
+    };
+    let Some(object) = value.as_object_mut() else {
+        return (None, Err(anyhow::anyhow!("request must be a JSON object")));
+    };
+    let id = object.remove("id");
+    let request = Request::deserialize(value).map_err(anyhow::Error::from);
+    (id, request)
+}
+
+/// The reply line for a request, newline included.
+pub fn format_reply(id: Option<&Value>, result: &Result<Response>) -> String {
+    let reply = match result {
+        Ok(response) => Reply {
+            id,
+            ok: true,
+            error: None,
+            response: Some(response),
+        },
+        Err(e) => Reply {
+            id,
+            ok: false,
+            error: Some(format!("{:#}", e)),
+            response: None,
+        },
+    };
+    let mut line = serde_json::to_string(&reply).expect("replies always serialize");
+    line.push('\n');
+    line
+}
+
+/// The listening end of the control socket. The socket is removed on drop
+/// by the process that created it.
+pub struct ControlServer {
+    listener: UnixListener,
+    path: PathBuf,
+    owner: u32,
+}
+
+impl ControlServer {
+    pub fn bind(dir: &Path, id: &str) -> Result<Self> {
+        let path = session::control_path(dir, id);
+        let listener = session::listen(dir, &path)?;
+        Ok(Self {
+            listener,
+            path,
+            owner: std::process::id(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn listener(&self) -> &UnixListener {
+        &self.listener
+    }
+
+    /// Accept a waiting connection, or `None` if nobody is waiting.
+    pub fn accept(&self) -> Result<Option<Controller>> {
+        let stream = match self.listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+            Err(e) => return Err(e).context("accept failed"),
+        };
+        stream.set_nonblocking(true)?;
+        Ok(Some(Controller {
+            stream,
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+        }))
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        if self.owner == std::process::id() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// A connection to the control socket.
+pub struct Controller {
+    stream: UnixStream,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+}
+
+impl Controller {
+    pub fn stream(&self) -> &UnixStream {
+        &self.stream
+    }
+
+    /// Whether replies are waiting for the socket to become writable.
+    pub fn wants_write(&self) -> bool {
+        !self.outgoing.is_empty()
+    }
+
+    /// Complete request lines that have arrived, or `None` once the
+    /// controller has gone.
+    pub fn receive(&mut self) -> Result<Option<Vec<String>>> {
+        let mut buf = [0u8; 65536];
+        let mut closed = false;
+        loop {
+            match (&self.stream).read(&mut buf) {
+                Ok(0) => {
+                    closed = true;
+                    break;
+                }
+                Ok(n) => self.incoming.extend_from_slice(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e).context("read from controller failed"),
+            }
+        }
+        let mut lines = Vec::new();
+        while let Some(end) = memchr::memchr(b'\n', &self.incoming) {
+            let line: Vec<u8> = self.incoming.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line[..end]);
+            let line = line.trim();
+            if !line.is_empty() {
+                lines.push(line.to_string());
+            }
+        }
+        anyhow::ensure!(
+            self.incoming.len() <= MAX_LINE_LEN,
+            "request line is too long"
+        );
+        // A last request without a newline still counts
+        if closed && lines.is_empty() {
+            let rest = String::from_utf8_lossy(&std::mem::take(&mut self.incoming))
+                .trim()
+                .to_string();
+            if rest.is_empty() {
+                return Ok(None);
+            }
+            lines.push(rest);
+        }
+        Ok(Some(lines))
+    }
+
+    pub fn send(&mut self, line: &str) -> Result<()> {
+        self.outgoing.extend_from_slice(line.as_bytes());
+        anyhow::ensure!(
+            self.outgoing.len() <= MAX_CONTROLLER_BACKLOG,
+            "controller stopped reading"
+        );
+        self.flush()
+    }
+
+    /// Hang up once the controller has stopped sending, giving it a moment
+    /// to read any replies still queued, such as a large history dump.
+    pub fn close(self) {
+        if self.outgoing.is_empty() {
+            return;
+        }
+        let _ = self.stream.set_nonblocking(false);
+        let _ = self.stream.set_write_timeout(Some(CLOSE_TIMEOUT));
+        let _ = (&self.stream).write_all(&self.outgoing);
+    }
+
+    /// Write as much queued output as the socket takes without blocking.
+    pub fn flush(&mut self) -> Result<()> {
+        while !self.outgoing.is_empty() {
+            match (&self.stream).write(&self.outgoing) {
+                Ok(0) => anyhow::bail!("controller closed"),
+                Ok(n) => {
+                    self.outgoing.drain(..n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e).context("write to controller failed"),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+
+    #[test]
+    fn test_parse_requests() {
+        let (id, request) = parse_request(r#"{"id": 7, "cmd": "get-state"}"#);
+        assert_eq!(id, Some(Value::from(7)));
+        assert_eq!(request.unwrap(), Request::GetState {});
+
+        let (id, request) = parse_request(r#"{"cmd": "dump-history", "lines": 20}"#);
+        assert_eq!(id, None);
+        assert_eq!(
+            request.unwrap(),
+            Request::DumpHistory {
+                format: TextFormat::Text,
+                lines: Some(20)
+            }
+        );
+
+        let (_, request) = parse_request(r#"{"cmd": "get-screen-text", "format": "ansi"}"#);
+        assert_eq!(
+            request.unwrap(),
+            Request::GetScreenText {
+                format: TextFormat::Ansi
+            }
+        );
+
+        let (_, request) = parse_request(r#"{"cmd": "send-input", "data": "hi\r"}"#);
+        assert_eq!(
+            request.unwrap(),
+            Request::SendInput {
+                data: "hi\r".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_bad_requests_keep_their_id() {
+        let (id, request) = parse_request(r#"{"id": "a", "cmd": "resize", "rows": 10}"#);
+        assert_eq!(id, Some(Value::from("a")));
+        assert!(request.unwrap_err().to_string().contains("cols"));
+
+        assert!(parse_request(r#"{"cmd": "dance"}"#).1.is_err());
+        assert!(parse_request(r#"{"cmd": "get-state", "x": 1}"#).1.is_err());
+        assert!(parse_request("[1]").1.is_err());
+        assert!(parse_request("not json").1.is_err());
+    }
+
+    #[test]
+    fn test_format_reply() {
+        let id = Value::from(3);
+        let reply = format_reply(Some(&id), &Ok(Response::Lookback { lookback: true }));
+        assert_eq!(reply, "{\"id\":3,\"ok\":true,\"lookback\":true}\n");
+
+        let reply = format_reply(None, &Ok(Response::Done {}));
+        assert_eq!(reply, "{\"ok\":true}\n");
+
+        let reply = format_reply(None, &Err(anyhow::anyhow!("nope")));
+        assert_eq!(reply, "{\"ok\":false,\"error\":\"nope\"}\n");
+
+        let state = Response::State(State {
+            session: "1".to_string(),
+            mode: Mode::AltScreen,
+            alt_screen: true,
+            lookback: false,
+            activity: Some(ActivityState::WaitingForApproval),
+            idle_ms: None,
+            rows: 24,
+            cols: 80,
+            history_lines: 0,
+            attached: Attached::None,
+            viewers: 2,
+        });
+        let reply: Value = serde_json::from_str(&format_reply(None, &Ok(state))).unwrap();
+        assert_eq!(reply["mode"], "alt-screen");
+        assert_eq!(reply["attached"], "none");
+        assert_eq!(reply["activity"], "waiting-for-approval");
+        assert_eq!(reply["idle_ms"], Value::Null);
+    }
+
+    #[test]
+    fn test_controller_lines() {
+        let dir =
+            std::env::temp_dir().join(format!("claude-chill-control-test-{}", std::process::id()));
+        let server = ControlServer::bind(&dir, "1").unwrap();
+        let mut client = UnixStream::connect(server.path()).unwrap();
+        client
+            .write_all(b"{\"cmd\":\"get-state\"}\n\n{\"cmd\":")
+            .unwrap();
+        let mut controller = server.accept().unwrap().unwrap();
+        assert_eq!(
+            controller.receive().unwrap(),
+            Some(vec!["{\"cmd\":\"get-state\"}".to_string()])
+        );
+
+        client.write_all(b"\"toggle-lookback\"}").unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        assert_eq!(
+            controller.receive().unwrap(),
+            Some(vec!["{\"cmd\":\"toggle-lookback\"}".to_string()])
+        );
+        assert_eq!(controller.receive().unwrap(), None);
+
+        controller.send("{\"ok\":true}\n").unwrap();
+        let mut reply = String::new();
+        BufReader::new(&client).read_line(&mut reply).unwrap();
+        assert_eq!(reply, "{\"ok\":true}\n");
+
+        drop(server);
+        assert!(!session::control_path(&dir, "1").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,154 @@
+//! Tells what Claude is doing from what is on its screen, which a timer
+//! can't: quiet thinking is still working, and a permission prompt that
+//! appears mid-stream is waiting for you at once.
+//!
+//! Each detector is a regex over the rendered rows, or only over the row
+//! the cursor is on. The first detector that matches decides the state.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+/// What the child appears to be doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ActivityState {
+    Working,
+    WaitingForApproval,
+    IdleAtPrompt,
+}
+
+impl ActivityState {
+    /// How the state reads in status lines and notifications.
+    pub fn description(self) -> &'static str {
+        match self {
+            ActivityState::Working => "working",
+            ActivityState::WaitingForApproval => "waiting for approval",
+            ActivityState::IdleAtPrompt => "idle at prompt",
+        }
+    }
+}
+
+/// Which rows of the screen a detector looks at.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DetectorScope {
+    /// Any row of the screen.
+    #[default]
+    Screen,
+    /// Only the row the cursor is on.
+    CursorRow,
+}
+
+#[derive(Debug, Clone)]
+pub struct Detector {
+    pub state: ActivityState,
+    pub scope: DetectorScope,
+    regex: Regex,
+}
+
+impl Detector {
+    pub fn new(
+        state: ActivityState,
+        pattern: &str,
+        scope: DetectorScope,
+    ) -> Result<Self, regex::Error> {
+        Ok(Self {
+            state,
+            scope,
+            regex: Regex::new(pattern)?,
+        })
+    }
+
+    fn matches(&self, rows: &[String], cursor_row: usize) -> bool {
+        match self.scope {
+            DetectorScope::Screen => rows.iter().any(|row| self.regex.is_match(row)),
+            DetectorScope::CursorRow => rows
+                .get(cursor_row)
+                .is_some_and(|row| self.regex.is_match(row)),
+        }
+    }
+}
+
+/// The state of the first detector that matches `screen`, if any.
+pub fn classify(detectors: &[Detector], screen: &vt100::Screen) -> Option<ActivityState> {
+    if detectors.is_empty() {
+        return None;
+    }
+    let (_, cols) = screen.size();
+    let rows: Vec<String> = screen.rows(0, cols).collect();
+    let cursor_row = screen.cursor_position().0 as usize;
+    detectors
+        .iter()
+        .find(|detector| detector.matches(&rows, cursor_row))
+        .map(|detector| detector.state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen(text: &str) -> vt100::Parser {
+        let mut parser = vt100::Parser::new(6, 40, 0);
+        parser.process(text.as_bytes());
+        parser
+    }
+
+    fn detectors() -> Vec<Detector> {
+        vec![
+            Detector::new(
+                ActivityState::WaitingForApproval,
+                r"Do you want to .*\?",
+                DetectorScope::Screen,
+            )
+            .unwrap(),
+            Detector::new(
+                ActivityState::Working,
+                r"(?i)esc to interrupt",
+                DetectorScope::Screen,
+            )
+            .unwrap(),
+            Detector::new(
+                ActivityState::IdleAtPrompt,
+                r"^[\s│]*>",
+                DetectorScope::CursorRow,
+            )
+            .unwrap(),
+        ]
+    }
+
+    #[test]
+    fn test_first_match_wins() {
+        let detectors = detectors();
+        let working = screen("answer\r\n✻ Thinking… (3s · esc to interrupt)\r\n│ > ");
+        assert_eq!(
+            classify(&detectors, working.screen()),
+            Some(ActivityState::Working)
+        );
+
+        let approval = screen("Do you want to proceed?\r\n❯ 1. Yes\r\n  2. No");
+        assert_eq!(
+            classify(&detectors, approval.screen()),
+            Some(ActivityState::WaitingForApproval)
+        );
+
+        let prompt = screen("answer\r\n│ > ");
+        assert_eq!(
+            classify(&detectors, prompt.screen()),
+            Some(ActivityState::IdleAtPrompt)
+        );
+    }
+
+    #[test]
+    fn test_cursor_row_scope() {
+        let detectors = detectors();
+        // The prompt is on screen, but the cursor is elsewhere
+        let elsewhere = screen("│ > \r\nstreaming text");
+        assert_eq!(classify(&detectors, elsewhere.screen()), None);
+        assert_eq!(classify(&[], screen("│ > ").screen()), None);
+    }
+
+    #[test]
+    fn test_invalid_pattern() {
+        assert!(Detector::new(ActivityState::Working, "(", DetectorScope::Screen).is_err());
+    }
+}
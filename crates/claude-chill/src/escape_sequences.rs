@@ -9,6 +9,17 @@ pub const ALT_SCREEN_EXIT: &[u8] = b"\x1b[?1049l";
 pub const ALT_SCREEN_ENTER_LEGACY: &[u8] = b"\x1b[?47h";
 pub const ALT_SCREEN_EXIT_LEGACY: &[u8] = b"\x1b[?47l";
 
+/// Reverse video for the whole screen, for a visual bell.
+pub const VISUAL_BELL_ON: &[u8] = b"\x1b[?5h";
+pub const VISUAL_BELL_OFF: &[u8] = b"\x1b[?5l";
+
+pub const LOOKBACK_MOUSE_ENABLE: &[u8] = b"\x1b[?1002h\x1b[?1006h";
+pub const LOOKBACK_MOUSE_DISABLE: &[u8] = b"\x1b[?1006l\x1b[?1002l";
+
+/// Turns off mouse reporting, bracketed paste and application cursor and
+/// keypad modes, and shows the cursor.
+pub const INPUT_MODES_RESET: &[u8] =
+    b"\x1b[?1000l\x1b[?1002l\x1b[?1003l\x1b[?1005l\x1b[?1006l\x1b[?2004l\x1b[?1l\x1b>\x1b[?25h";
+
 pub const SYNC_BUFFER_CAPACITY: usize = 1024 * 1024;
 pub const OUTPUT_BUFFER_CAPACITY: usize = 32768;
-pub const INPUT_BUFFER_CAPACITY: usize = 64;
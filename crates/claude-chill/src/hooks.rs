@@ -0,0 +1,152 @@
+//! Hooks: shell commands run when the session goes idle, rings the bell,
+//! switches screens or exits, e.g. to get a desktop notification when Claude
+//! is done thinking.
+//!
+//! Hooks run in the background through `sh -c`, with their stdio on
+//! `/dev/null` and what happened in `CLAUDE_CHILL_*` environment variables.
+
+use crate::config::HooksConfig;
+use anyhow::{Context, Result};
+use log::debug;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    Idle,
+    Bell,
+    Exit,
+    AltScreen,
+}
+
+impl Hook {
+    /// The event name hooks see in `CLAUDE_CHILL_EVENT`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Hook::Idle => "idle",
+            Hook::Bell => "bell",
+            Hook::Exit => "exit",
+            Hook::AltScreen => "alt-screen",
+        }
+    }
+}
+
+pub struct Hooks {
+    config: HooksConfig,
+    running: Vec<(Hook, Child)>,
+}
+
+impl Hooks {
+    pub fn new(config: HooksConfig) -> Self {
+        Self {
+            config,
+            running: Vec::new(),
+        }
+    }
+
+    /// Whether `hook` has a command and isn't still running from last time,
+    /// so a bell storm doesn't start a storm of processes.
+    pub fn ready(&mut self, hook: Hook) -> bool {
+        self.reap();
+        self.command(hook).is_some() && !self.running.iter().any(|(h, _)| *h == hook)
+    }
+
+    /// Lines of history to pass to hooks.
+    pub fn context_lines(&self) -> usize {
+        self.config.context_lines
+    }
+
+    /// Start `hook`'s command with `env` added to ours, without waiting for
+    /// it to finish.
+    pub fn run(&mut self, hook: Hook, env: &[(&str, String)]) -> Result<()> {
+        let Some(command) = self.command(hook) else {
+            return Ok(());
+        };
+        debug!("Hooks::run: {} hook: {}", hook.name(), command);
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .env("CLAUDE_CHILL_EVENT", hook.name())
+            .envs(env.iter().map(|(name, value)| (name, value)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            // Keep keys like Ctrl+C meant for Claude away from it
+            .process_group(0)
+            .spawn()
+            .with_context(|| format!("failed to run {} hook", hook.name()))?;
+        self.running.push((hook, child));
+        Ok(())
+    }
+
+    /// Forget hooks that have finished. After detaching, ones started before
+    /// belong to the process that exited and are forgotten too.
+    fn reap(&mut self) {
+        self.running
+            .retain_mut(|(_, child)| matches!(child.try_wait(), Ok(None)));
+    }
+
+    fn command(&self, hook: Hook) -> Option<&str> {
+        match hook {
+            Hook::Idle => self.config.on_idle.as_deref(),
+            Hook::Bell => self.config.on_bell.as_deref(),
+            Hook::Exit => self.config.on_exit.as_deref(),
+            Hook::AltScreen => self.config.on_alt_screen.as_deref(),
+        }
+        .filter(|command| !command.trim().is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn wait_for_file(path: &std::path::Path) -> String {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            if let Ok(content) = fs::read_to_string(path)
+                && content.ends_with('\n')
+            {
+                return content;
+            }
+            assert!(Instant::now() < deadline, "hook never wrote {:?}", path);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_hook_gets_its_context() {
+        let dir = std::env::temp_dir().join(format!("claude-chill-hooks-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let out = dir.join("idle");
+        let mut hooks = Hooks::new(HooksConfig {
+            on_idle: Some(format!(
+                "echo \"$CLAUDE_CHILL_EVENT $CLAUDE_CHILL_IDLE_MS\" > {}",
+                out.display()
+            )),
+            ..HooksConfig::default()
+        });
+        assert!(hooks.ready(Hook::Idle));
+        assert!(!hooks.ready(Hook::Bell));
+        hooks
+            .run(Hook::Idle, &[("CLAUDE_CHILL_IDLE_MS", "5000".to_string())])
+            .unwrap();
+        assert_eq!(wait_for_file(&out), "idle 5000\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_running_hook_is_not_ready() {
+        let mut hooks = Hooks::new(HooksConfig {
+            on_bell: Some("sleep 1".to_string()),
+            on_exit: Some("  ".to_string()),
+            ..HooksConfig::default()
+        });
+        hooks.run(Hook::Bell, &[]).unwrap();
+        assert!(!hooks.ready(Hook::Bell));
+        assert!(!hooks.ready(Hook::Exit));
+    }
+}
@@ -0,0 +1,636 @@
+//! Streaming decoder that turns terminal input bytes into typed events.
+//!
+//! This is the reverse of [`KeyCombination::to_escape_sequence`]: it
+//! understands legacy CSI and SS3 key sequences, CSI u (kitty keyboard
+//! protocol), UTF-8 text, bracketed paste, SGR and X10 mouse reports and
+//! focus events. Every decoded event keeps the raw bytes it came from so the
+//! proxy can forward input to the child unchanged.
+
+use crate::key_parser::{CSI_U_F13, CSI_U_KP_0, KeyCode, KeyCombination, KeypadKey, Modifiers};
+use crate::mouse::{self, MouseEvent, SgrParse};
+use memchr::memmem;
+use std::fmt;
+
+const PASTE_START: &[u8] = b"\x1b[200~";
+const PASTE_END: &[u8] = b"\x1b[201~";
+
+const CSI_U_F24: u32 = 57387;
+const CSI_U_KP_EQUAL: u32 = 57415;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputEvent {
+    Key(KeyCombination),
+    /// Bracketed paste contents, without the start/end markers.
+    Paste(Vec<u8>),
+    Mouse(MouseEvent),
+    FocusIn,
+    FocusOut,
+    /// A sequence that was recognized as a unit but not understood.
+    Unknown,
+}
+
+impl fmt::Display for InputEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputEvent::Key(key) => write!(f, "{}", key),
+            InputEvent::Paste(data) => write!(f, "paste({} bytes)", data.len()),
+            InputEvent::Mouse(event) => {
+                write!(f, "mouse({:?} at {},{})", event.kind, event.col, event.row)
+            }
+            InputEvent::FocusIn => write!(f, "focus-in"),
+            InputEvent::FocusOut => write!(f, "focus-out"),
+            InputEvent::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedInput {
+    pub event: InputEvent,
+    pub raw: Vec<u8>,
+}
+
+enum Parsed {
+    Event(InputEvent, usize),
+    Incomplete,
+}
+
+/// Stateful decoder. Maintains a buffer across [`feed`](Self::feed) calls so
+/// sequences split across reads decode correctly.
+#[derive(Debug, Default)]
+pub struct InputDecoder {
+    pending: Vec<u8>,
+}
+
+impl InputDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decode as many complete events as possible from `data`. Incomplete
+    /// trailing sequences are kept until more bytes arrive or
+    /// [`flush`](Self::flush) is called.
+    pub fn feed(&mut self, data: &[u8]) -> Vec<DecodedInput> {
+        self.pending.extend_from_slice(data);
+        self.decode(false)
+    }
+
+    /// Decode everything that is pending, treating incomplete sequences as
+    /// final. Call this when no more input arrived within the escape timeout,
+    /// e.g. to turn a lone ESC into an Esc key press.
+    pub fn flush(&mut self) -> Vec<DecodedInput> {
+        self.decode(true)
+    }
+
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Remove pending bytes without decoding them.
+    pub fn take_pending(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.pending)
+    }
+
+    fn decode(&mut self, is_final: bool) -> Vec<DecodedInput> {
+        let mut events = Vec::new();
+        let mut pos = 0;
+        while pos < self.pending.len() {
+            match parse_one(&self.pending[pos..], is_final) {
+                Parsed::Event(event, len) => {
+                    events.push(DecodedInput {
+                        event,
+                        raw: self.pending[pos..pos + len].to_vec(),
+                    });
+                    pos += len;
+                }
+                Parsed::Incomplete => break,
+            }
+        }
+        self.pending.drain(..pos);
+        events
+    }
+}
+
+/// Decode a complete byte sequence into exactly one event, if it is one.
+pub fn decode_single(data: &[u8]) -> Option<InputEvent> {
+    let mut decoder = InputDecoder::new();
+    let mut events = decoder.feed(data);
+    events.extend(decoder.flush());
+    match events.as_slice() {
+        [single] => Some(single.event.clone()),
+        _ => None,
+    }
+}
+
+fn key(code: KeyCode, modifiers: Modifiers) -> InputEvent {
+    InputEvent::Key(KeyCombination { code, modifiers })
+}
+
+fn plain(code: KeyCode) -> InputEvent {
+    key(code, Modifiers::default())
+}
+
+fn ctrl(c: char) -> InputEvent {
+    key(
+        KeyCode::Char(c),
+        Modifiers {
+            ctrl: true,
+            ..Modifiers::default()
+        },
+    )
+}
+
+fn parse_one(data: &[u8], is_final: bool) -> Parsed {
+    match data[0] {
+        0x1b => parse_escape(data, is_final),
+        0x80.. => parse_utf8(data, is_final),
+        byte => Parsed::Event(decode_byte(byte), 1),
+    }
+}
+
+fn decode_byte(byte: u8) -> InputEvent {
+    match byte {
+        0x00 => key(
+            KeyCode::Space,
+            Modifiers {
+                ctrl: true,
+                ..Modifiers::default()
+            },
+        ),
+        0x08 => key(
+            KeyCode::Backspace,
+            Modifiers {
+                ctrl: true,
+                ..Modifiers::default()
+            },
+        ),
+        b'\t' => plain(KeyCode::Tab),
+        b'\r' => plain(KeyCode::Enter),
+        0x01..=0x1a => ctrl((b'a' + byte - 1) as char),
+        0x1c => ctrl('\\'),
+        0x1d => ctrl(']'),
+        0x1e => ctrl('^'),
+        0x1f => ctrl('_'),
+        b' ' => plain(KeyCode::Space),
+        0x7f => plain(KeyCode::Backspace),
+        b'A'..=b'Z' => key(
+            KeyCode::Char(byte.to_ascii_lowercase() as char),
+            Modifiers {
+                shift: true,
+                ..Modifiers::default()
+            },
+        ),
+        _ => plain(KeyCode::Char(byte as char)),
+    }
+}
+
+fn parse_utf8(data: &[u8], is_final: bool) -> Parsed {
+    let width = match data[0] {
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Parsed::Event(InputEvent::Unknown, 1),
+    };
+    if data.len() < width {
+        return if is_final {
+            Parsed::Event(InputEvent::Unknown, data.len())
+        } else {
+            Parsed::Incomplete
+        };
+    }
+    match std::str::from_utf8(&data[..width])
+        .ok()
+        .and_then(|s| s.chars().next())
+    {
+        Some(c) => Parsed::Event(plain(KeyCode::Char(c)), width),
+        None => Parsed::Event(InputEvent::Unknown, 1),
+    }
+}
+
+fn parse_escape(data: &[u8], is_final: bool) -> Parsed {
+    if data.len() == 1 {
+        return if is_final {
+            Parsed::Event(plain(KeyCode::Esc), 1)
+        } else {
+            Parsed::Incomplete
+        };
+    }
+
+    match data[1] {
+        b'[' => parse_csi(data, is_final),
+        b'O' if data.len() > 2 => match ss3_key(data[2]) {
+            Some(event) => Parsed::Event(event, 3),
+            None => Parsed::Event(InputEvent::Unknown, 3),
+        },
+        b'O' if !is_final => Parsed::Incomplete,
+        0x1b => Parsed::Event(plain(KeyCode::Esc), 1),
+        _ => match parse_one(&data[1..], is_final) {
+            Parsed::Event(InputEvent::Key(mut combination), len) => {
+                combination.modifiers.alt = true;
+                Parsed::Event(InputEvent::Key(combination), len + 1)
+            }
+            Parsed::Event(_, len) => Parsed::Event(InputEvent::Unknown, len + 1),
+            Parsed::Incomplete => Parsed::Incomplete,
+        },
+    }
+}
+
+fn parse_csi(data: &[u8], is_final: bool) -> Parsed {
+    // Legacy X10 mouse: ESC [ M Cb Cx Cy
+    if data.get(2) == Some(&b'M') {
+        if data.len() < 6 {
+            return if is_final {
+                Parsed::Event(InputEvent::Unknown, data.len())
+            } else {
+                Parsed::Incomplete
+            };
+        }
+        return Parsed::Event(x10_mouse(data[3], data[4], data[5]), 6);
+    }
+
+    let mut end = None;
+    for (i, &byte) in data.iter().enumerate().skip(2) {
+        match byte {
+            0x20..=0x3f => {}
+            0x40..=0x7e => {
+                end = Some(i);
+                break;
+            }
+            _ => return Parsed::Event(InputEvent::Unknown, i),
+        }
+    }
+    let Some(end) = end else {
+        return if is_final {
+            Parsed::Event(InputEvent::Unknown, data.len())
+        } else {
+            Parsed::Incomplete
+        };
+    };
+
+    let params = &data[2..end];
+    let final_byte = data[end];
+    let len = end + 1;
+
+    if params.first() == Some(&b'<') {
+        return match mouse::parse_sgr(&data[..len]) {
+            SgrParse::Complete(event, _) => Parsed::Event(InputEvent::Mouse(event), len),
+            _ => Parsed::Event(InputEvent::Unknown, len),
+        };
+    }
+
+    if data[..len] == *PASTE_START {
+        return parse_paste(data, is_final);
+    }
+
+    if params.is_empty() {
+        match final_byte {
+            b'I' => return Parsed::Event(InputEvent::FocusIn, len),
+            b'O' => return Parsed::Event(InputEvent::FocusOut, len),
+            _ => {}
+        }
+    }
+
+    match csi_key(params, final_byte) {
+        Some(event) => Parsed::Event(event, len),
+        None => Parsed::Event(InputEvent::Unknown, len),
+    }
+}
+
+fn parse_paste(data: &[u8], is_final: bool) -> Parsed {
+    let content = &data[PASTE_START.len()..];
+    match memmem::find(content, PASTE_END) {
+        Some(idx) => Parsed::Event(
+            InputEvent::Paste(content[..idx].to_vec()),
+            PASTE_START.len() + idx + PASTE_END.len(),
+        ),
+        None if is_final => Parsed::Event(InputEvent::Paste(content.to_vec()), data.len()),
+        None => Parsed::Incomplete,
+    }
+}
+
+fn x10_mouse(cb: u8, cx: u8, cy: u8) -> InputEvent {
+    let code = cb.saturating_sub(32) as u16;
+    let release = code & 0b11 == 0b11 && code & 64 == 0;
+    InputEvent::Mouse(MouseEvent {
+        kind: mouse::event_kind(code, release),
+        col: cx.saturating_sub(33) as u16,
+        row: cy.saturating_sub(33) as u16,
+    })
+}
+
+/// Parse `;`-separated numeric parameters, ignoring `:` sub-parameters.
+fn csi_params(params: &[u8]) -> Option<Vec<u32>> {
+    let text = std::str::from_utf8(params).ok()?;
+    if text.is_empty() {
+        return Some(Vec::new());
+    }
+    text.split(';')
+        .map(|field| {
+            let value = field.split(':').next().unwrap_or("");
+            if value.is_empty() {
+                Some(1)
+            } else {
+                value.parse().ok()
+            }
+        })
+        .collect()
+}
+
+fn decode_modifiers(param: Option<&u32>) -> Modifiers {
+    let bits = param.copied().unwrap_or(1).saturating_sub(1);
+    Modifiers {
+        shift: bits & 1 != 0,
+        alt: bits & 2 != 0,
+        ctrl: bits & 4 != 0,
+        super_key: bits & 8 != 0,
+        meta: bits & 32 != 0,
+    }
+}
+
+fn csi_key(params: &[u8], final_byte: u8) -> Option<InputEvent> {
+    let params = csi_params(params)?;
+    let modifiers = decode_modifiers(params.get(1));
+    let first = params.first().copied();
+
+    let code = match final_byte {
+        b'A' => KeyCode::Up,
+        b'B' => KeyCode::Down,
+        b'C' => KeyCode::Right,
+        b'D' => KeyCode::Left,
+        b'H' => KeyCode::Home,
+        b'F' => KeyCode::End,
+        b'P' => KeyCode::F(1),
+        b'Q' => KeyCode::F(2),
+        b'R' => KeyCode::F(3),
+        b'S' => KeyCode::F(4),
+        b'Z' => {
+            let mut modifiers = modifiers;
+            modifiers.shift = true;
+            return Some(key(KeyCode::Tab, modifiers));
+        }
+        b'~' => tilde_key(first?)?,
+        b'u' => csi_u_key(first?)?,
+        _ => return None,
+    };
+    Some(key(code, modifiers))
+}
+
+fn tilde_key(n: u32) -> Option<KeyCode> {
+    let code = match n {
+        1 | 7 => KeyCode::Home,
+        2 => KeyCode::Insert,
+        3 => KeyCode::Delete,
+        4 | 8 => KeyCode::End,
+        5 => KeyCode::PageUp,
+        6 => KeyCode::PageDown,
+        11..=15 => KeyCode::F((n - 10) as u8),
+        17..=21 => KeyCode::F((n - 11) as u8),
+        23..=26 => KeyCode::F((n - 12) as u8),
+        28 | 29 => KeyCode::F((n - 13) as u8),
+        31..=34 => KeyCode::F((n - 14) as u8),
+        _ => return None,
+    };
+    Some(code)
+}
+
+fn csi_u_key(codepoint: u32) -> Option<KeyCode> {
+    let code = match codepoint {
+        13 => KeyCode::Enter,
+        27 => KeyCode::Esc,
+        9 => KeyCode::Tab,
+        127 => KeyCode::Backspace,
+        32 => KeyCode::Space,
+        CSI_U_F13..=CSI_U_F24 => KeyCode::F((codepoint - CSI_U_F13 + 13) as u8),
+        CSI_U_KP_0..=CSI_U_KP_EQUAL => KeyCode::Keypad(match codepoint - CSI_U_KP_0 {
+            n @ 0..=9 => KeypadKey::Digit(n as u8),
+            10 => KeypadKey::Decimal,
+            11 => KeypadKey::Divide,
+            12 => KeypadKey::Multiply,
+            13 => KeypadKey::Minus,
+            14 => KeypadKey::Plus,
+            15 => KeypadKey::Enter,
+            _ => KeypadKey::Equal,
+        }),
+        _ => KeyCode::Char(char::from_u32(codepoint)?),
+    };
+    Some(code)
+}
+
+fn ss3_key(final_byte: u8) -> Option<InputEvent> {
+    let code = match final_byte {
+        b'A' => KeyCode::Up,
+        b'B' => KeyCode::Down,
+        b'C' => KeyCode::Right,
+        b'D' => KeyCode::Left,
+        b'H' => KeyCode::Home,
+        b'F' => KeyCode::End,
+        b'P' => KeyCode::F(1),
+        b'Q' => KeyCode::F(2),
+        b'R' => KeyCode::F(3),
+        b'S' => KeyCode::F(4),
+        b'p'..=b'y' => KeyCode::Keypad(KeypadKey::Digit(final_byte - b'p')),
+        b'M' => KeyCode::Keypad(KeypadKey::Enter),
+        b'k' => KeyCode::Keypad(KeypadKey::Plus),
+        b'm' => KeyCode::Keypad(KeypadKey::Minus),
+        b'j' => KeyCode::Keypad(KeypadKey::Multiply),
+        b'o' => KeyCode::Keypad(KeypadKey::Divide),
+        b'n' => KeyCode::Keypad(KeypadKey::Decimal),
+        b'X' => KeyCode::Keypad(KeypadKey::Equal),
+        _ => return None,
+    };
+    Some(plain(code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_parser;
+    use crate::mouse::MouseEventKind;
+
+    fn events(data: &[u8]) -> Vec<InputEvent> {
+        let mut decoder = InputDecoder::new();
+        let mut decoded = decoder.feed(data);
+        decoded.extend(decoder.flush());
+        decoded.into_iter().map(|d| d.event).collect()
+    }
+
+    fn parsed(spec: &str) -> InputEvent {
+        InputEvent::Key(key_parser::parse(spec).unwrap())
+    }
+
+    #[test]
+    fn test_plain_text() {
+        assert_eq!(events(b"ab"), vec![parsed("[a]"), parsed("[b]")]);
+    }
+
+    #[test]
+    fn test_uppercase_is_shifted() {
+        assert_eq!(events(b"J"), vec![parsed("[shift][j]")]);
+    }
+
+    #[test]
+    fn test_control_bytes() {
+        assert_eq!(events(b"\x03"), vec![parsed("[ctrl][c]")]);
+        assert_eq!(events(b"\r"), vec![parsed("[enter]")]);
+        assert_eq!(events(b"\x7f"), vec![parsed("[backspace]")]);
+    }
+
+    #[test]
+    fn test_utf8() {
+        assert_eq!(events("é".as_bytes()), vec![parsed("[é]")]);
+    }
+
+    #[test]
+    fn test_utf8_split_across_feeds() {
+        let bytes = "€".as_bytes();
+        let mut decoder = InputDecoder::new();
+        assert!(decoder.feed(&bytes[..1]).is_empty());
+        let decoded = decoder.feed(&bytes[1..]);
+        assert_eq!(decoded[0].event, parsed("[€]"));
+        assert_eq!(decoded[0].raw, bytes);
+    }
+
+    #[test]
+    fn test_legacy_csi_keys() {
+        assert_eq!(events(b"\x1b[A"), vec![parsed("[up]")]);
+        assert_eq!(events(b"\x1b[5;6~"), vec![parsed("[ctrl][shift][pageup]")]);
+        assert_eq!(events(b"\x1b[24~"), vec![parsed("[f12]")]);
+        assert_eq!(events(b"\x1b[1;3C"), vec![parsed("[alt][right]")]);
+        assert_eq!(events(b"\x1b[Z"), vec![parsed("[shift][tab]")]);
+    }
+
+    #[test]
+    fn test_ss3_keys() {
+        assert_eq!(events(b"\x1bOP"), vec![parsed("[f1]")]);
+        assert_eq!(events(b"\x1bOA"), vec![parsed("[up]")]);
+        assert_eq!(events(b"\x1bOu"), vec![parsed("[kp5]")]);
+    }
+
+    #[test]
+    fn test_csi_u() {
+        assert_eq!(events(b"\x1b[106;9u"), vec![parsed("[super][j]")]);
+        assert_eq!(events(b"\x1b[13;33u"), vec![parsed("[meta][enter]")]);
+        assert_eq!(events(b"\x1b[57387u"), vec![parsed("[f24]")]);
+    }
+
+    #[test]
+    fn test_alt_prefix() {
+        assert_eq!(events(b"\x1bx"), vec![parsed("[alt][x]")]);
+        assert_eq!(events(b"\x1b\r"), vec![parsed("[alt][enter]")]);
+    }
+
+    #[test]
+    fn test_lone_escape_needs_flush() {
+        let mut decoder = InputDecoder::new();
+        assert!(decoder.feed(b"\x1b").is_empty());
+        assert!(decoder.has_pending());
+        let flushed = decoder.flush();
+        assert_eq!(flushed[0].event, parsed("[esc]"));
+        assert!(!decoder.has_pending());
+    }
+
+    #[test]
+    fn test_double_escape() {
+        assert_eq!(events(b"\x1b\x1b[A"), vec![parsed("[esc]"), parsed("[up]")]);
+    }
+
+    #[test]
+    fn test_split_csi() {
+        let mut decoder = InputDecoder::new();
+        assert!(decoder.feed(b"\x1b[5;").is_empty());
+        let decoded = decoder.feed(b"6~x");
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[0].raw, b"\x1b[5;6~");
+        assert_eq!(decoded[1].event, parsed("[x]"));
+    }
+
+    #[test]
+    fn test_bracketed_paste() {
+        let mut decoder = InputDecoder::new();
+        assert!(decoder.feed(b"\x1b[200~hello\x1b[A").is_empty());
+        let decoded = decoder.feed(b"\x1b[201~q");
+        assert_eq!(decoded[0].event, InputEvent::Paste(b"hello\x1b[A".to_vec()));
+        assert_eq!(decoded[0].raw, b"\x1b[200~hello\x1b[A\x1b[201~");
+        assert_eq!(decoded[1].event, parsed("[q]"));
+    }
+
+    #[test]
+    fn test_sgr_mouse() {
+        let decoded = events(b"\x1b[<64;3;4M");
+        assert_eq!(
+            decoded,
+            vec![InputEvent::Mouse(MouseEvent {
+                kind: MouseEventKind::WheelUp,
+                col: 2,
+                row: 3,
+            })]
+        );
+    }
+
+    #[test]
+    fn test_x10_mouse() {
+        let decoded = events(b"\x1b[M`!!");
+        assert_eq!(
+            decoded,
+            vec![InputEvent::Mouse(MouseEvent {
+                kind: MouseEventKind::WheelUp,
+                col: 0,
+                row: 0,
+            })]
+        );
+    }
+
+    #[test]
+    fn test_focus_events() {
+        assert_eq!(
+            events(b"\x1b[I\x1b[O"),
+            vec![InputEvent::FocusIn, InputEvent::FocusOut]
+        );
+    }
+
+    #[test]
+    fn test_unknown_sequence_keeps_raw() {
+        let mut decoder = InputDecoder::new();
+        let decoded = decoder.feed(b"\x1b[?1;2cz");
+        assert_eq!(decoded[0].event, InputEvent::Unknown);
+        assert_eq!(decoded[0].raw, b"\x1b[?1;2c");
+        assert_eq!(decoded[1].event, parsed("[z]"));
+    }
+
+    #[test]
+    fn test_raw_bytes_round_trip() {
+        let input = b"a\x1b[A\x1b[200~x\x1b[201~\x1b[<0;1;1M\xc3\xa9\x1bOP\x03";
+        let mut decoder = InputDecoder::new();
+        let raw: Vec<u8> = decoder
+            .feed(input)
+            .into_iter()
+            .flat_map(|d| d.raw)
+            .collect();
+        assert_eq!(raw, input.to_vec());
+    }
+
+    #[test]
+    fn test_encoded_keys_decode_to_single_event() {
+        for spec in [
+            "[ctrl][6]",
+            "[f12]",
+            "[ctrl][shift][pageup]",
+            "[alt][enter]",
+            "[super][k]",
+            "[kpenter]",
+            "[f21]",
+        ] {
+            let sequence = key_parser::parse(spec).unwrap().to_escape_sequence();
+            assert!(decode_single(&sequence).is_some(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(parsed("[ctrl][c]").to_string(), "[ctrl][c]");
+        assert_eq!(
+            InputEvent::Paste(b"abc".to_vec()).to_string(),
+            "paste(3 bytes)"
+        );
+    }
+}
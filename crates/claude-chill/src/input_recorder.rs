@@ -0,0 +1,167 @@
+use crate::input_decoder::InputEvent;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+const FORMAT_VERSION: u32 = 1;
+
+/// What the proxy did with a piece of input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputAction {
+    /// Written to the PTY unchanged.
+    Forward,
+    /// Replaced by a macro payload.
+    Macro,
+    LookbackEnter,
+    LookbackExit,
+    /// Consumed by the lookback view.
+    LookbackMouse,
+    /// Swallowed while in lookback mode.
+    Dropped,
+    /// Detached the terminal from the session.
+    Detach,
+}
+
+#[derive(Serialize)]
+struct Header {
+    version: u32,
+    kind: &'static str,
+    timestamp: u64,
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    /// Seconds since the recording started.
+    t: f64,
+    action: InputAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event: Option<String>,
+    #[serde(flatten)]
+    input: Bytes<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    written: Option<Bytes<'a>>,
+}
+
+/// Raw bytes as a JSON string when they are valid UTF-8, hex otherwise.
+#[derive(Serialize)]
+enum Bytes<'a> {
+    #[serde(rename = "data")]
+    Text(&'a str),
+    #[serde(rename = "data_hex")]
+    Hex(String),
+}
+
+impl<'a> Bytes<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        match std::str::from_utf8(data) {
+            Ok(text) => Bytes::Text(text),
+            Err(_) => Bytes::Hex(data.iter().map(|b| format!("{:02x}", b)).collect()),
+        }
+    }
+}
+
+/// Records every piece of stdin input as line-delimited JSON: a header line
+/// followed by one record per decoded input event.
+pub struct InputRecorder {
+    writer: Box<dyn Write + Send>,
+    start: Instant,
+}
+
+impl InputRecorder {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("failed to create input recording {}", path.display()))?;
+        Self::new(Box::new(BufWriter::new(file)))
+    }
+
+    pub fn new(mut writer: Box<dyn Write + Send>) -> Result<Self> {
+        let header = Header {
+            version: FORMAT_VERSION,
+            kind: "claude-chill-input",
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+        };
+        serde_json::to_writer(&mut writer, &header)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        Ok(Self {
+            writer,
+            start: Instant::now(),
+        })
+    }
+
+    /// Record `input` and the action taken. `written` is set when the bytes
+    /// sent to the PTY differ from the input, e.g. for macros.
+    pub fn record(
+        &mut self,
+        input: &[u8],
+        event: Option<&InputEvent>,
+        action: InputAction,
+        written: Option<&[u8]>,
+    ) -> Result<()> {
+        let record = Record {
+            t: self.start.elapsed().as_secs_f64(),
+            action,
+            event: event.map(|e| e.to_string()),
+            input: Bytes::new(input),
+            written: written.map(Bytes::new),
+        };
+        serde_json::to_writer(&mut self.writer, &record)?;
+        self.writer.write_all(b"\n")?;
+        // Flush every record so the file is usable even if the proxy dies
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input_decoder::decode_single;
+    use crate::test_support::{SharedBuffer, lines};
+
+    #[test]
+    fn test_header() {
+        let buffer = SharedBuffer::default();
+        InputRecorder::new(Box::new(buffer.clone())).unwrap();
+        let lines = lines(&buffer);
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0]["version"], 1);
+        assert_eq!(lines[0]["kind"], "claude-chill-input");
+    }
+
+    #[test]
+    fn test_record_forward() {
+        let buffer = SharedBuffer::default();
+        let mut recorder = InputRecorder::new(Box::new(buffer.clone())).unwrap();
+        let event = decode_single(b"\x03").unwrap();
+        recorder
+            .record(b"\x03", Some(&event), InputAction::Forward, None)
+            .unwrap();
+        let lines = lines(&buffer);
+        assert_eq!(lines[1]["action"], "forward");
+        assert_eq!(lines[1]["event"], "[ctrl][c]");
+        assert_eq!(lines[1]["data"], "\u{3}");
+        assert!(lines[1]["t"].as_f64().unwrap() >= 0.0);
+        assert!(lines[1].get("written").is_none());
+    }
+
+    #[test]
+    fn test_record_macro_and_binary() {
+        let buffer = SharedBuffer::default();
+        let mut recorder = InputRecorder::new(Box::new(buffer.clone())).unwrap();
+        recorder
+            .record(b"\xff", None, InputAction::Macro, Some(b"/compact\r"))
+            .unwrap();
+        let lines = lines(&buffer);
+        assert_eq!(lines[1]["action"], "macro");
+        assert_eq!(lines[1]["data_hex"], "ff");
+        assert_eq!(lines[1]["written"]["data"], "/compact\r");
+    }
+}
@@ -1,9 +1,18 @@
 use std::fmt;
 
+const MAX_FUNCTION_KEY: u8 = 24;
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+
+/// First kitty keyboard protocol code for F13, used for keys without a
+/// legacy escape sequence.
+pub(crate) const CSI_U_F13: u32 = 57376;
+pub(crate) const CSI_U_KP_0: u32 = 57399;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ParseKeyError {
     pub raw: String,
     pub reason: String,
+    pub suggestion: Option<String>,
 }
 
 impl ParseKeyError {
@@ -11,13 +20,23 @@ impl ParseKeyError {
         Self {
             raw: raw.into(),
             reason: reason.into(),
+            suggestion: None,
         }
     }
+
+    pub fn with_suggestion(mut self, suggestion: Option<String>) -> Self {
+        self.suggestion = suggestion;
+        self
+    }
 }
 
 impl fmt::Display for ParseKeyError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "cannot parse {:?} as key: {}", self.raw, self.reason)
+        write!(f, "cannot parse {:?} as key: {}", self.raw, self.reason)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, " (did you mean {:?}?)", suggestion)?;
+        }
+        Ok(())
     }
 }
 
@@ -28,12 +47,90 @@ pub struct Modifiers {
     pub ctrl: bool,
     pub shift: bool,
     pub alt: bool,
+    /// Only expressible through CSI u / modifyOtherKeys style sequences.
+    pub super_key: bool,
+    /// Only expressible through CSI u / modifyOtherKeys style sequences.
+    pub meta: bool,
+}
+
+impl Modifiers {
+    /// xterm modifier parameter: 1 + bitmask, or 0 when no modifier is held.
+    fn code(&self) -> u8 {
+        let bits = self.shift as u8
+            | (self.alt as u8) << 1
+            | (self.ctrl as u8) << 2
+            | (self.super_key as u8) << 3
+            | (self.meta as u8) << 5;
+        if bits == 0 { 0 } else { bits + 1 }
+    }
+
+    /// Whether the combination needs CSI u to be encoded at all.
+    fn is_extended(&self) -> bool {
+        self.super_key || self.meta
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeypadKey {
+    Digit(u8),
+    Enter,
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+    Decimal,
+    Equal,
+}
+
+impl KeypadKey {
+    /// Final byte of the SS3 sequence sent in application keypad mode.
+    fn ss3_final(&self) -> u8 {
+        match self {
+            KeypadKey::Digit(n) => b'p' + n.min(&9),
+            KeypadKey::Enter => b'M',
+            KeypadKey::Plus => b'k',
+            KeypadKey::Minus => b'm',
+            KeypadKey::Multiply => b'j',
+            KeypadKey::Divide => b'o',
+            KeypadKey::Decimal => b'n',
+            KeypadKey::Equal => b'X',
+        }
+    }
+
+    fn csi_u_code(&self) -> u32 {
+        CSI_U_KP_0
+            + match self {
+                KeypadKey::Digit(n) => *n.min(&9) as u32,
+                KeypadKey::Decimal => 10,
+                KeypadKey::Divide => 11,
+                KeypadKey::Multiply => 12,
+                KeypadKey::Minus => 13,
+                KeypadKey::Plus => 14,
+                KeypadKey::Enter => 15,
+                KeypadKey::Equal => 16,
+            }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            KeypadKey::Digit(n) => format!("kp{}", n),
+            KeypadKey::Enter => "kpenter".to_string(),
+            KeypadKey::Plus => "kpplus".to_string(),
+            KeypadKey::Minus => "kpminus".to_string(),
+            KeypadKey::Multiply => "kpmultiply".to_string(),
+            KeypadKey::Divide => "kpdivide".to_string(),
+            KeypadKey::Decimal => "kpdecimal".to_string(),
+            KeypadKey::Equal => "kpequal".to_string(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum KeyCode {
     Char(char),
+    /// Function keys F1-F24. Other numbers have no escape sequence.
     F(u8),
+    Keypad(KeypadKey),
     Enter,
     Esc,
     Tab,
@@ -74,9 +171,17 @@ impl fmt::Display for KeyCombination {
         if self.modifiers.alt {
             write!(f, "[alt]")?;
         }
+        if self.modifiers.super_key {
+            write!(f, "[super]")?;
+        }
+        if self.modifiers.meta {
+            write!(f, "[meta]")?;
+        }
         let key_name = match &self.code {
+            KeyCode::Char(']') => "[rbracket]".to_string(),
             KeyCode::Char(c) => format!("[{}]", c),
             KeyCode::F(n) => format!("[f{}]", n),
+            KeyCode::Keypad(k) => format!("[{}]", k.name()),
             KeyCode::Enter => "[enter]".to_string(),
             KeyCode::Esc => "[esc]".to_string(),
             KeyCode::Tab => "[tab]".to_string(),
@@ -97,8 +202,123 @@ impl fmt::Display for KeyCombination {
     }
 }
 
+const MODIFIER_NAMES: &[&str] = &[
+    "ctrl", "control", "shift", "alt", "option", "meta", "super", "cmd",
+];
+
+const NAMED_KEYS: &[(&str, KeyCode)] = &[
+    ("[", KeyCode::Char('[')),
+    ("]", KeyCode::Char(']')),
+    ("esc", KeyCode::Esc),
+    ("escape", KeyCode::Esc),
+    ("enter", KeyCode::Enter),
+    ("return", KeyCode::Enter),
+    ("cr", KeyCode::Enter),
+    ("tab", KeyCode::Tab),
+    ("backspace", KeyCode::Backspace),
+    ("bs", KeyCode::Backspace),
+    ("delete", KeyCode::Delete),
+    ("del", KeyCode::Delete),
+    ("insert", KeyCode::Insert),
+    ("ins", KeyCode::Insert),
+    ("home", KeyCode::Home),
+    ("end", KeyCode::End),
+    ("pageup", KeyCode::PageUp),
+    ("pgup", KeyCode::PageUp),
+    ("pagedown", KeyCode::PageDown),
+    ("pgdn", KeyCode::PageDown),
+    ("pgdown", KeyCode::PageDown),
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("space", KeyCode::Space),
+    ("kp0", KeyCode::Keypad(KeypadKey::Digit(0))),
+    ("kp1", KeyCode::Keypad(KeypadKey::Digit(1))),
+    ("kp2", KeyCode::Keypad(KeypadKey::Digit(2))),
+    ("kp3", KeyCode::Keypad(KeypadKey::Digit(3))),
+    ("kp4", KeyCode::Keypad(KeypadKey::Digit(4))),
+    ("kp5", KeyCode::Keypad(KeypadKey::Digit(5))),
+    ("kp6", KeyCode::Keypad(KeypadKey::Digit(6))),
+    ("kp7", KeyCode::Keypad(KeypadKey::Digit(7))),
+    ("kp8", KeyCode::Keypad(KeypadKey::Digit(8))),
+    ("kp9", KeyCode::Keypad(KeypadKey::Digit(9))),
+    ("kpenter", KeyCode::Keypad(KeypadKey::Enter)),
+    ("kpplus", KeyCode::Keypad(KeypadKey::Plus)),
+    ("kpadd", KeyCode::Keypad(KeypadKey::Plus)),
+    ("kpminus", KeyCode::Keypad(KeypadKey::Minus)),
+    ("kpsubtract", KeyCode::Keypad(KeypadKey::Minus)),
+    ("kpmultiply", KeyCode::Keypad(KeypadKey::Multiply)),
+    ("kpdivide", KeyCode::Keypad(KeypadKey::Divide)),
+    ("kpdecimal", KeyCode::Keypad(KeypadKey::Decimal)),
+    ("kpequal", KeyCode::Keypad(KeypadKey::Equal)),
+    ("comma", KeyCode::Char(',')),
+    ("period", KeyCode::Char('.')),
+    ("dot", KeyCode::Char('.')),
+    ("slash", KeyCode::Char('/')),
+    ("backslash", KeyCode::Char('\\')),
+    ("bslash", KeyCode::Char('\\')),
+    ("semicolon", KeyCode::Char(';')),
+    ("colon", KeyCode::Char(':')),
+    ("quote", KeyCode::Char('\'')),
+    ("apostrophe", KeyCode::Char('\'')),
+    ("doublequote", KeyCode::Char('"')),
+    ("backtick", KeyCode::Char('`')),
+    ("grave", KeyCode::Char('`')),
+    ("minus", KeyCode::Char('-')),
+    ("dash", KeyCode::Char('-')),
+    ("equal", KeyCode::Char('=')),
+    ("equals", KeyCode::Char('=')),
+    ("plus", KeyCode::Char('+')),
+    ("lbracket", KeyCode::Char('[')),
+    ("rbracket", KeyCode::Char(']')),
+    ("lbrace", KeyCode::Char('{')),
+    ("rbrace", KeyCode::Char('}')),
+    ("lparen", KeyCode::Char('(')),
+    ("rparen", KeyCode::Char(')')),
+    ("lt", KeyCode::Char('<')),
+    ("less", KeyCode::Char('<')),
+    ("gt", KeyCode::Char('>')),
+    ("greater", KeyCode::Char('>')),
+    ("caret", KeyCode::Char('^')),
+    ("underscore", KeyCode::Char('_')),
+    ("tilde", KeyCode::Char('~')),
+    ("bang", KeyCode::Char('!')),
+    ("exclamation", KeyCode::Char('!')),
+    ("at", KeyCode::Char('@')),
+    ("hash", KeyCode::Char('#')),
+    ("dollar", KeyCode::Char('$')),
+    ("percent", KeyCode::Char('%')),
+    ("ampersand", KeyCode::Char('&')),
+    ("asterisk", KeyCode::Char('*')),
+    ("star", KeyCode::Char('*')),
+    ("pipe", KeyCode::Char('|')),
+    ("bar", KeyCode::Char('|')),
+    ("question", KeyCode::Char('?')),
+];
+
+/// Parse a key specification.
+///
+/// Accepted syntaxes:
+/// - bracketed: `[ctrl][shift][j]`
+/// - separated: `ctrl+shift+j`, `C-S-j`
+/// - vim style: `<C-j>`
+///
+/// Short modifier prefixes in the separated and vim styles are `C` (ctrl),
+/// `S` (shift), `A`/`M` (alt) and `D` (super).
 pub fn parse(raw: &str) -> Result<KeyCombination, ParseKeyError> {
-    let raw_lower = raw.to_ascii_lowercase();
+    let trimmed = raw.trim();
+    if trimmed.starts_with('[') {
+        parse_bracketed(raw, trimmed)
+    } else if trimmed.len() > 2 && trimmed.starts_with('<') && trimmed.ends_with('>') {
+        parse_separated(raw, &trimmed[1..trimmed.len() - 1], &['-'])
+    } else {
+        parse_separated(raw, trimmed, &['+', '-'])
+    }
+}
+
+fn parse_bracketed(raw: &str, spec: &str) -> Result<KeyCombination, ParseKeyError> {
+    let raw_lower = spec.to_ascii_lowercase();
     let mut modifiers = Modifiers::default();
     let mut key_code: Option<KeyCode> = None;
 
@@ -126,16 +346,11 @@ pub fn parse(raw: &str) -> Result<KeyCombination, ParseKeyError> {
         let token: String = chars[start..end].iter().collect();
         i = end + 1;
 
-        match token.as_str() {
-            "ctrl" | "control" => modifiers.ctrl = true,
-            "shift" => modifiers.shift = true,
-            "alt" => modifiers.alt = true,
-            _ => {
-                if key_code.is_some() {
-                    return Err(ParseKeyError::new(raw, "multiple key codes specified"));
-                }
-                key_code = Some(parse_key_code(&token, raw)?);
+        if !apply_modifier(&token, &mut modifiers, false) {
+            if key_code.is_some() {
+                return Err(ParseKeyError::new(raw, "multiple key codes specified"));
             }
+            key_code = Some(parse_key_code(&token, raw)?);
         }
     }
 
@@ -145,48 +360,123 @@ pub fn parse(raw: &str) -> Result<KeyCombination, ParseKeyError> {
     }
 }
 
+fn parse_separated(
+    raw: &str,
+    spec: &str,
+    separators: &[char],
+) -> Result<KeyCombination, ParseKeyError> {
+    let mut modifiers = Modifiers::default();
+    let mut rest = spec;
+
+    // A separator at the very start belongs to the key itself, e.g. `ctrl++`
+    while let Some(idx) = rest.find(|c| separators.contains(&c)) {
+        if idx == 0 || !apply_modifier(&rest[..idx], &mut modifiers, true) {
+            break;
+        }
+        rest = &rest[idx + 1..];
+    }
+
+    if rest.is_empty() {
+        return Err(ParseKeyError::new(raw, "no key code specified"));
+    }
+
+    let code = parse_key_code(&rest.to_lowercase(), raw)?;
+    Ok(KeyCombination { code, modifiers })
+}
+
+fn apply_modifier(name: &str, modifiers: &mut Modifiers, allow_short: bool) -> bool {
+    match name.to_ascii_lowercase().as_str() {
+        "ctrl" | "control" => modifiers.ctrl = true,
+        "shift" => modifiers.shift = true,
+        "alt" | "option" => modifiers.alt = true,
+        "meta" => modifiers.meta = true,
+        "super" | "cmd" => modifiers.super_key = true,
+        "c" if allow_short => modifiers.ctrl = true,
+        "s" if allow_short => modifiers.shift = true,
+        "a" | "m" if allow_short => modifiers.alt = true,
+        "d" if allow_short => modifiers.super_key = true,
+        _ => return false,
+    }
+    true
+}
+
 fn parse_key_code(token: &str, raw: &str) -> Result<KeyCode, ParseKeyError> {
-    let code = match token {
-        "[" => KeyCode::Char('['),
-        "]" => KeyCode::Char(']'),
-        "esc" | "escape" => KeyCode::Esc,
-        "enter" | "return" => KeyCode::Enter,
-        "tab" => KeyCode::Tab,
-        "backspace" | "bs" => KeyCode::Backspace,
-        "delete" | "del" => KeyCode::Delete,
-        "insert" | "ins" => KeyCode::Insert,
-        "home" => KeyCode::Home,
-        "end" => KeyCode::End,
-        "pageup" | "pgup" => KeyCode::PageUp,
-        "pagedown" | "pgdn" | "pgdown" => KeyCode::PageDown,
-        "up" => KeyCode::Up,
-        "down" => KeyCode::Down,
-        "left" => KeyCode::Left,
-        "right" => KeyCode::Right,
-        "space" => KeyCode::Space,
-        "f1" => KeyCode::F(1),
-        "f2" => KeyCode::F(2),
-        "f3" => KeyCode::F(3),
-        "f4" => KeyCode::F(4),
-        "f5" => KeyCode::F(5),
-        "f6" => KeyCode::F(6),
-        "f7" => KeyCode::F(7),
-        "f8" => KeyCode::F(8),
-        "f9" => KeyCode::F(9),
-        "f10" => KeyCode::F(10),
-        "f11" => KeyCode::F(11),
-        "f12" => KeyCode::F(12),
-        s if s.len() == 1 => KeyCode::Char(s.chars().next().unwrap_or(' ')),
-        _ => return Err(ParseKeyError::new(raw, format!("unknown key: {}", token))),
-    };
-    Ok(code)
+    if let Some((_, code)) = NAMED_KEYS.iter().find(|(name, _)| *name == token) {
+        return Ok(code.clone());
+    }
+
+    if let Some(n) = token.strip_prefix('f').and_then(|d| d.parse::<u8>().ok()) {
+        if (1..=MAX_FUNCTION_KEY).contains(&n) {
+            return Ok(KeyCode::F(n));
+        }
+        return Err(ParseKeyError::new(
+            raw,
+            format!("function keys range from f1 to f{}", MAX_FUNCTION_KEY),
+        ));
+    }
+
+    let mut chars = token.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(KeyCode::Char(c));
+    }
+
+    Err(ParseKeyError::new(raw, format!("unknown key: {}", token)).with_suggestion(suggest(token)))
+}
+
+/// Closest known key or modifier name to an unrecognized token.
+fn suggest(token: &str) -> Option<String> {
+    let function_keys = (1..=MAX_FUNCTION_KEY).map(|n| format!("f{}", n));
+    let candidates = NAMED_KEYS
+        .iter()
+        .map(|(name, _)| name.to_string())
+        .chain(MODIFIER_NAMES.iter().map(|name| name.to_string()))
+        .chain(function_keys)
+        .filter(|name| name.chars().count() > 1);
+
+    candidates
+        .map(|name| (edit_distance(token, &name), name))
+        .filter(|(distance, _)| *distance <= MAX_SUGGESTION_DISTANCE && *distance < token.len())
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name)
+}
+
+/// Edit distance counting adjacent transpositions as a single edit, since
+/// swapped letters are the most common typo in key names.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            d[i][j] = (d[i - 1][j - 1] + cost)
+                .min(d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    d[a.len()][b.len()]
 }
 
 fn key_to_escape_sequence(code: &KeyCode, modifiers: &Modifiers) -> Vec<u8> {
-    let modifier_code = match (modifiers.ctrl, modifiers.shift, modifiers.alt) {
-        (false, false, false) => 0,
-        _ => 1 + modifiers.shift as u8 + (modifiers.alt as u8 * 2) + (modifiers.ctrl as u8 * 4),
-    };
+    let modifier_code = modifiers.code();
+
+    if modifiers.is_extended()
+        && let Some(codepoint) = csi_u_codepoint(code)
+    {
+        return csi_u(codepoint, modifier_code);
+    }
 
     match code {
         KeyCode::PageUp => modified_key(b"5", modifier_code),
@@ -212,6 +502,13 @@ fn key_to_escape_sequence(code: &KeyCode, modifiers: &Modifiers) -> Vec<u8> {
         KeyCode::Insert => modified_key(b"2", modifier_code),
         KeyCode::Delete => modified_key(b"3", modifier_code),
         KeyCode::F(n) => function_key(*n, modifier_code),
+        KeyCode::Keypad(key) => {
+            if modifier_code == 0 {
+                vec![0x1b, b'O', key.ss3_final()]
+            } else {
+                csi_u(key.csi_u_code(), modifier_code)
+            }
+        }
         KeyCode::Enter => {
             if modifiers.alt {
                 b"\x1b\r".to_vec()
@@ -245,6 +542,28 @@ fn key_to_escape_sequence(code: &KeyCode, modifiers: &Modifiers) -> Vec<u8> {
     }
 }
 
+/// Codepoint used for keys that are encoded as `CSI codepoint ; modifiers u`
+/// when a modifier without a legacy encoding is held.
+fn csi_u_codepoint(code: &KeyCode) -> Option<u32> {
+    match code {
+        KeyCode::Char(c) => Some(*c as u32),
+        KeyCode::Enter => Some(13),
+        KeyCode::Esc => Some(27),
+        KeyCode::Tab => Some(9),
+        KeyCode::Backspace => Some(127),
+        KeyCode::Space => Some(32),
+        _ => None,
+    }
+}
+
+fn csi_u(codepoint: u32, modifier: u8) -> Vec<u8> {
+    if modifier == 0 {
+        format!("\x1b[{}u", codepoint).into_bytes()
+    } else {
+        format!("\x1b[{};{}u", codepoint, modifier).into_bytes()
+    }
+}
+
 fn char_to_escape_sequence(c: char, modifiers: &Modifiers) -> Vec<u8> {
     if modifiers.ctrl {
         let ctrl_byte = match c {
@@ -271,12 +590,19 @@ fn char_to_escape_sequence(c: char, modifiers: &Modifiers) -> Vec<u8> {
             };
         }
     }
+    let c = if modifiers.shift {
+        c.to_uppercase().next().unwrap_or(c)
+    } else {
+        c
+    };
+    let mut utf8 = [0u8; 4];
+    let encoded = c.encode_utf8(&mut utf8).as_bytes();
     if modifiers.alt {
-        vec![0x1b, c as u8]
-    } else if modifiers.shift {
-        vec![c.to_ascii_uppercase() as u8]
+        let mut sequence = vec![0x1b];
+        sequence.extend_from_slice(encoded);
+        sequence
     } else {
-        vec![c as u8]
+        encoded.to_vec()
     }
 }
 
@@ -315,7 +641,17 @@ fn function_key(n: u8, modifier: u8) -> Vec<u8> {
         10 => 21,
         11 => 23,
         12 => 24,
-        _ => return b"\x1b[24~".to_vec(),
+        13 => 25,
+        14 => 26,
+        15 => 28,
+        16 => 29,
+        17 => 31,
+        18 => 32,
+        19 => 33,
+        20 => 34,
+        // No legacy sequence exists past F20, use the kitty protocol codes
+        21..=MAX_FUNCTION_KEY => return csi_u(CSI_U_F13 + (n - 13) as u32, modifier),
+        _ => return Vec::new(),
     };
 
     if modifier == 0 {
@@ -427,4 +763,115 @@ mod tests {
         let key = parse("[ctrl][\\]").unwrap();
         assert_eq!(key.to_escape_sequence(), vec![0x1C]);
     }
+
+    #[test]
+    fn test_function_keys_above_f12() {
+        assert_eq!(parse("[f13]").unwrap().to_escape_sequence(), b"\x1b[25~");
+        assert_eq!(parse("[f20]").unwrap().to_escape_sequence(), b"\x1b[34~");
+        assert_eq!(parse("[f24]").unwrap().to_escape_sequence(), b"\x1b[57387u");
+        assert_eq!(
+            parse("[shift][f21]").unwrap().to_escape_sequence(),
+            b"\x1b[57384;2u"
+        );
+    }
+
+    #[test]
+    fn test_function_key_out_of_range() {
+        let err = parse("[f25]").unwrap_err();
+        assert!(err.reason.contains("f1 to f24"));
+        assert!(parse("[f0]").is_err());
+        assert!(function_key(30, 0).is_empty());
+    }
+
+    #[test]
+    fn test_keypad_keys() {
+        assert_eq!(parse("[kp5]").unwrap().to_escape_sequence(), b"\x1bOu");
+        assert_eq!(parse("[kpenter]").unwrap().to_escape_sequence(), b"\x1bOM");
+        assert_eq!(
+            parse("[ctrl][kpplus]").unwrap().to_escape_sequence(),
+            b"\x1b[57413;5u"
+        );
+    }
+
+    #[test]
+    fn test_unicode_char() {
+        let key = parse("[é]").unwrap();
+        assert_eq!(key.code, KeyCode::Char('é'));
+        assert_eq!(key.to_escape_sequence(), "é".as_bytes());
+        let key = parse("[alt][ñ]").unwrap();
+        assert_eq!(key.to_escape_sequence(), "\x1bñ".as_bytes());
+    }
+
+    #[test]
+    fn test_super_and_meta_use_csi_u() {
+        assert_eq!(
+            parse("[super][j]").unwrap().to_escape_sequence(),
+            b"\x1b[106;9u"
+        );
+        assert_eq!(
+            parse("[meta][enter]").unwrap().to_escape_sequence(),
+            b"\x1b[13;33u"
+        );
+        assert_eq!(
+            parse("[ctrl][super][up]").unwrap().to_escape_sequence(),
+            b"\x1b[1;13A"
+        );
+    }
+
+    #[test]
+    fn test_named_punctuation() {
+        assert_eq!(parse("[comma]").unwrap().code, KeyCode::Char(','));
+        assert_eq!(
+            parse("[ctrl][backslash]").unwrap().to_escape_sequence(),
+            vec![0x1C]
+        );
+        assert_eq!(parse("[rbracket]").unwrap().code, KeyCode::Char(']'));
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        for spec in ["[ctrl][rbracket]", "[super][meta][kp0]", "[shift][f22]"] {
+            let key = parse(spec).unwrap();
+            assert_eq!(parse(&key.to_string()).unwrap(), key);
+        }
+    }
+
+    #[test]
+    fn test_plus_syntax() {
+        let key = parse("ctrl+shift+j").unwrap();
+        assert_eq!(key, parse("[ctrl][shift][j]").unwrap());
+        assert_eq!(parse("ctrl++").unwrap().code, KeyCode::Char('+'));
+    }
+
+    #[test]
+    fn test_emacs_syntax() {
+        let key = parse("C-S-j").unwrap();
+        assert_eq!(key, parse("[ctrl][shift][j]").unwrap());
+        assert_eq!(parse("M-x").unwrap(), parse("[alt][x]").unwrap());
+        assert_eq!(parse("C--").unwrap().code, KeyCode::Char('-'));
+    }
+
+    #[test]
+    fn test_vim_syntax() {
+        assert_eq!(parse("<C-j>").unwrap(), parse("[ctrl][j]").unwrap());
+        assert_eq!(parse("<S-F5>").unwrap(), parse("[shift][f5]").unwrap());
+        assert_eq!(parse("<CR>").unwrap().code, KeyCode::Enter);
+    }
+
+    #[test]
+    fn test_did_you_mean() {
+        let err = parse("[pagup]").unwrap_err();
+        assert_eq!(err.suggestion.as_deref(), Some("pageup"));
+        let err = parse("[ctlr][j]").unwrap_err();
+        assert_eq!(err.suggestion.as_deref(), Some("ctrl"));
+        assert!(err.to_string().contains("did you mean \"ctrl\""));
+        let err = parse("ctrl+pagedwn").unwrap_err();
+        assert_eq!(err.suggestion.as_deref(), Some("pagedown"));
+    }
+
+    #[test]
+    fn test_no_suggestion_for_garbage() {
+        let err = parse("[qqqqqqqq]").unwrap_err();
+        assert_eq!(err.suggestion, None);
+    }
 }
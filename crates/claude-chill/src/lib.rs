@@ -1,7 +1,26 @@
+pub mod asciicast;
+pub mod attach;
+pub mod bell;
 pub mod config;
+pub mod control;
+pub mod detector;
 pub mod escape_filter;
 pub mod escape_sequences;
+pub mod hooks;
+pub mod input_decoder;
+pub mod input_recorder;
 pub mod key_parser;
 pub mod line_buffer;
+pub mod lookback_view;
+pub mod macros;
+pub mod mirror;
+pub mod mouse;
+pub mod notification;
 pub mod proxy;
+pub mod proxy_core;
 pub mod redraw_throttler;
+pub mod replay;
+pub mod session;
+#[cfg(test)]
+mod test_support;
+pub mod watchdog;
@@ -0,0 +1,304 @@
+use crate::escape_sequences::{SYNC_END, SYNC_START};
+
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Scrollable view of the history buffer, rendered by the proxy itself while
+/// lookback mode is driven by the mouse.
+///
+/// History is replayed into a dedicated VT emulator with enough scrollback to
+/// hold every line, so scrolling is just moving the emulator's viewport. The
+/// bottom row of the terminal is reserved for a status line.
+pub struct LookbackView {
+    parser: vt100::Parser,
+    selection: Option<Selection>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Selection {
+    anchor: (u16, u16),
+    cursor: (u16, u16),
+}
+
+impl Selection {
+    /// Returns (start, end) in reading order, end exclusive.
+    fn bounds(&self) -> ((u16, u16), (u16, u16)) {
+        let (start, end) = if self.anchor <= self.cursor {
+            (self.anchor, self.cursor)
+        } else {
+            (self.cursor, self.anchor)
+        };
+        (start, (end.0, end.1.saturating_add(1)))
+    }
+
+    fn contains(&self, row: u16, col: u16) -> bool {
+        let (start, end) = self.bounds();
+        (row, col) >= start && (row, col) < end
+    }
+}
+
+impl LookbackView {
+    pub fn new(rows: u16, cols: u16, history: &[u8], scrollback_lines: usize) -> Self {
+        let view_rows = rows.saturating_sub(1).max(1);
+        let mut parser = vt100::Parser::new(view_rows, cols, scrollback_lines);
+        parser.process(history);
+        Self {
+            parser,
+            selection: None,
+        }
+    }
+
+    pub fn rows(&self) -> u16 {
+        self.parser.screen().size().0
+    }
+
+    /// Lines scrolled up from the bottom of history.
+    pub fn offset(&self) -> usize {
+        self.parser.screen().scrollback()
+    }
+
+    pub fn scroll_up(&mut self, lines: usize) {
+        let offset = self.offset().saturating_add(lines);
+        self.parser.screen_mut().set_scrollback(offset);
+        self.selection = None;
+    }
+
+    pub fn scroll_down(&mut self, lines: usize) {
+        let offset = self.offset().saturating_sub(lines);
+        self.parser.screen_mut().set_scrollback(offset);
+        self.selection = None;
+    }
+
+    pub fn start_selection(&mut self, row: u16, col: u16) {
+        let point = self.clamp(row, col);
+        self.selection = Some(Selection {
+            anchor: point,
+            cursor: point,
+        });
+    }
+
+    pub fn extend_selection(&mut self, row: u16, col: u16) {
+        let point = self.clamp(row, col);
+        if let Some(selection) = &mut self.selection {
+            selection.cursor = point;
+        }
+    }
+
+    /// All of history as plain text, oldest line first. Long lines come out
+    /// wrapped at the view's width.
+    pub fn text(&mut self) -> String {
+        let screen = self.parser.screen_mut();
+        let offset = screen.scrollback();
+        let cols = screen.size().1;
+        screen.set_scrollback(usize::MAX);
+        let mut lines = Vec::new();
+        for top in (1..=screen.scrollback()).rev() {
+            screen.set_scrollback(top);
+            lines.extend(screen.rows(0, cols).next());
+        }
+        screen.set_scrollback(0);
+        lines.extend(screen.rows(0, cols));
+        screen.set_scrollback(offset);
+
+        while lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+        lines.join("\n")
+    }
+
+    /// Text covered by the current selection, if it spans more than a click.
+    pub fn selected_text(&self) -> Option<String> {
+        let selection = self.selection?;
+        if selection.anchor == selection.cursor {
+            return None;
+        }
+        let ((start_row, start_col), (end_row, end_col)) = selection.bounds();
+        let text = self
+            .parser
+            .screen()
+            .contents_between(start_row, start_col, end_row, end_col);
+        if text.is_empty() { None } else { Some(text) }
+    }
+
+    fn clamp(&self, row: u16, col: u16) -> (u16, u16) {
+        let (rows, cols) = self.parser.screen().size();
+        (
+            row.min(rows.saturating_sub(1)),
+            col.min(cols.saturating_sub(1)),
+        )
+    }
+
+    /// Render the visible part of history plus a status line.
+    pub fn render(&self, output: &mut Vec<u8>, status: &str) {
+        let screen = self.parser.screen();
+        let (rows, cols) = screen.size();
+
+        output.extend_from_slice(SYNC_START);
+        output.extend_from_slice(b"\x1b[?25l");
+
+        for (row, formatted) in screen.rows_formatted(0, cols).enumerate() {
+            let row = row as u16;
+            output.extend_from_slice(format!("\x1b[{};1H\x1b[0m\x1b[2K", row + 1).as_bytes());
+            match self.selection {
+                Some(selection) if row_selected(&selection, row) => {
+                    self.write_selected_row(output, &selection, row, cols);
+                }
+                _ => output.extend_from_slice(&formatted),
+            }
+        }
+
+        output.extend_from_slice(format!("\x1b[{};1H\x1b[0m\x1b[2K\x1b[7m", rows + 1).as_bytes());
+        output.extend_from_slice(truncate(status, cols as usize).as_bytes());
+        output.extend_from_slice(b"\x1b[0m");
+        output.extend_from_slice(SYNC_END);
+    }
+
+    fn write_selected_row(&self, output: &mut Vec<u8>, selection: &Selection, row: u16, cols: u16) {
+        let screen = self.parser.screen();
+        let mut highlighted = false;
+        for col in 0..cols {
+            let Some(cell) = screen.cell(row, col) else {
+                break;
+            };
+            if cell.is_wide_continuation() {
+                continue;
+            }
+            let selected = selection.contains(row, col);
+            if selected != highlighted {
+                output.extend_from_slice(if selected { b"\x1b[7m" } else { b"\x1b[27m" });
+                highlighted = selected;
+            }
+            if cell.has_contents() {
+                output.extend_from_slice(cell.contents().as_bytes());
+            } else {
+                output.push(b' ');
+            }
+        }
+        if highlighted {
+            output.extend_from_slice(b"\x1b[27m");
+        }
+    }
+}
+
+fn row_selected(selection: &Selection, row: u16) -> bool {
+    let (start, end) = selection.bounds();
+    row >= start.0 && row <= end.0
+}
+
+fn truncate(text: &str, width: usize) -> &str {
+    match text.char_indices().nth(width) {
+        Some((idx, _)) => &text[..idx],
+        None => text,
+    }
+}
+
+/// Build an OSC 52 sequence asking the terminal to put `text` on the clipboard.
+pub fn osc52_copy(text: &str) -> Vec<u8> {
+    let mut output = b"\x1b]52;c;".to_vec();
+    base64_encode(text.as_bytes(), &mut output);
+    output.push(0x07);
+    output
+}
+
+fn base64_encode(input: &[u8], output: &mut Vec<u8>) {
+    for chunk in input.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        output.push(BASE64_ALPHABET[(n >> 18) as usize & 0x3f]);
+        output.push(BASE64_ALPHABET[(n >> 12) as usize & 0x3f]);
+        if chunk.len() > 1 {
+            output.push(BASE64_ALPHABET[(n >> 6) as usize & 0x3f]);
+        } else {
+            output.push(b'=');
+        }
+        if chunk.len() > 2 {
+            output.push(BASE64_ALPHABET[n as usize & 0x3f]);
+        } else {
+            output.push(b'=');
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(lines: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        for i in 0..lines {
+            data.extend_from_slice(format!("line{}\r\n", i).as_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn test_scroll_clamps_to_history() {
+        let mut view = LookbackView::new(5, 20, &history(10), 100);
+        assert_eq!(view.rows(), 4);
+        assert_eq!(view.offset(), 0);
+        view.scroll_up(3);
+        assert_eq!(view.offset(), 3);
+        view.scroll_up(100);
+        assert_eq!(view.offset(), 7);
+        view.scroll_down(100);
+        assert_eq!(view.offset(), 0);
+    }
+
+    #[test]
+    fn test_selection_text() {
+        let mut view = LookbackView::new(5, 20, &history(10), 100);
+        view.scroll_up(100);
+        view.start_selection(0, 0);
+        view.extend_selection(1, 4);
+        assert_eq!(view.selected_text().as_deref(), Some("line0\nline1"));
+    }
+
+    #[test]
+    fn test_text_covers_all_history() {
+        let mut view = LookbackView::new(5, 20, &history(10), 100);
+        view.scroll_up(2);
+        let text = view.text();
+        assert_eq!(text.lines().count(), 10);
+        assert!(text.starts_with("line0\nline1\n"));
+        assert!(text.ends_with("line9"));
+        assert_eq!(view.offset(), 2);
+    }
+
+    #[test]
+    fn test_click_without_drag_selects_nothing() {
+        let mut view = LookbackView::new(5, 20, &history(10), 100);
+        view.start_selection(1, 1);
+        assert_eq!(view.selected_text(), None);
+    }
+
+    #[test]
+    fn test_scroll_clears_selection() {
+        let mut view = LookbackView::new(5, 20, &history(10), 100);
+        view.start_selection(0, 0);
+        view.extend_selection(0, 3);
+        view.scroll_up(1);
+        assert_eq!(view.selected_text(), None);
+    }
+
+    #[test]
+    fn test_render_includes_status_line() {
+        let view = LookbackView::new(5, 20, &history(10), 100);
+        let mut output = Vec::new();
+        view.render(&mut output, "status");
+        let mut parser = vt100::Parser::new(5, 20, 0);
+        parser.process(&output);
+        let rows: Vec<String> = parser.screen().rows(0, 20).collect();
+        assert_eq!(rows[0], "line7");
+        assert_eq!(rows[4], "status");
+    }
+
+    #[test]
+    fn test_osc52_copy() {
+        assert_eq!(osc52_copy("hi"), b"\x1b]52;c;aGk=\x07".to_vec());
+        assert_eq!(osc52_copy("abc"), b"\x1b]52;c;YWJj\x07".to_vec());
+    }
+}
@@ -0,0 +1,186 @@
+use crate::key_parser;
+use anyhow::Result;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsePayloadError {
+    pub raw: String,
+    pub reason: String,
+}
+
+impl ParsePayloadError {
+    pub fn new(raw: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self {
+            raw: raw.into(),
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for ParsePayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "cannot parse {:?} as macro payload: {}",
+            self.raw, self.reason
+        )
+    }
+}
+
+impl std::error::Error for ParsePayloadError {}
+
+/// A key bound to bytes that are sent to the child as if typed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputMacro {
+    /// Key spec as written in the config, for display.
+    pub key: String,
+    /// Escape sequence the terminal sends for the key.
+    pub sequence: Vec<u8>,
+    /// Bytes written to the PTY when the key is pressed.
+    pub payload: Vec<u8>,
+}
+
+impl InputMacro {
+    pub fn parse(key: &str, payload: &str) -> Result<Self> {
+        let combination = key_parser::parse(key)?;
+        let payload = parse_payload(payload)?;
+        Ok(Self {
+            key: key.to_string(),
+            sequence: combination.to_escape_sequence(),
+            payload,
+        })
+    }
+}
+
+/// Expand a macro payload into raw bytes.
+///
+/// Supported escapes:
+/// - `\r`, `\n`, `\t`, `\e` (ESC), `\\`
+/// - `\xHH` for an arbitrary byte
+/// - `\<key>` for the sequence of any key spec, e.g. `\<enter>` or `\<C-u>`
+pub fn parse_payload(raw: &str) -> Result<Vec<u8>, ParsePayloadError> {
+    let mut output = Vec::with_capacity(raw.len());
+    let mut chars = raw.char_indices();
+
+    while let Some((_, c)) = chars.next() {
+        if c != '\\' {
+            let mut utf8 = [0u8; 4];
+            output.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            continue;
+        }
+
+        let Some((idx, escape)) = chars.next() else {
+            return Err(ParsePayloadError::new(raw, "trailing backslash"));
+        };
+        match escape {
+            'r' => output.push(b'\r'),
+            'n' => output.push(b'\n'),
+            't' => output.push(b'\t'),
+            'e' => output.push(0x1b),
+            '\\' => output.push(b'\\'),
+            'x' => {
+                let hex: String = (0..2)
+                    .filter_map(|_| chars.next().map(|(_, c)| c))
+                    .collect();
+                // from_str_radix alone would take one digit or a sign
+                let valid = hex.len() == 2 && hex.chars().all(|c| c.is_ascii_hexdigit());
+                let byte = u8::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| valid)
+                    .ok_or_else(|| {
+                        ParsePayloadError::new(
+                            raw,
+                            format!("invalid hex escape at position {}", idx),
+                        )
+                    })?;
+                output.push(byte);
+            }
+            '<' => {
+                let mut spec = String::new();
+                let mut closed = false;
+                for (_, c) in chars.by_ref() {
+                    if c == '>' && !spec.is_empty() {
+                        closed = true;
+                        break;
+                    }
+                    spec.push(c);
+                }
+                if !closed {
+                    return Err(ParsePayloadError::new(
+                        raw,
+                        format!("unclosed key at position {}", idx),
+                    ));
+                }
+                let key = key_parser::parse(&spec)
+                    .map_err(|e| ParsePayloadError::new(raw, e.to_string()))?;
+                output.extend_from_slice(&key.to_escape_sequence());
+            }
+            other => {
+                return Err(ParsePayloadError::new(
+                    raw,
+                    format!("unknown escape \\{} at position {}", other, idx),
+                ));
+            }
+        }
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_text() {
+        assert_eq!(parse_payload("continue").unwrap(), b"continue");
+    }
+
+    #[test]
+    fn test_escapes() {
+        assert_eq!(
+            parse_payload(r"a\rb\nc\td\e\\").unwrap(),
+            b"a\rb\nc\td\x1b\\"
+        );
+        assert_eq!(parse_payload(r"\x03").unwrap(), vec![0x03]);
+    }
+
+    #[test]
+    fn test_key_escape() {
+        assert_eq!(parse_payload(r"/compact\<enter>").unwrap(), b"/compact\r");
+        assert_eq!(parse_payload(r"\<C-u>").unwrap(), vec![0x15]);
+        assert_eq!(
+            parse_payload(r"\<[ctrl][shift][pageup]>").unwrap(),
+            b"\x1b[5;6~"
+        );
+    }
+
+    #[test]
+    fn test_key_escape_greater_than() {
+        assert_eq!(parse_payload(r"\<>>").unwrap(), b">");
+    }
+
+    #[test]
+    fn test_unicode_passthrough() {
+        assert_eq!(parse_payload("héllo").unwrap(), "héllo".as_bytes());
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(parse_payload("oops\\").is_err());
+        assert!(parse_payload(r"\q").is_err());
+        assert!(parse_payload(r"\xzz").is_err());
+        assert!(parse_payload(r"\x4").is_err());
+        assert!(parse_payload(r"\x+1").is_err());
+        assert!(parse_payload(r"\<enter").is_err());
+        assert!(parse_payload(r"\<notakey>").is_err());
+    }
+
+    #[test]
+    fn test_input_macro() {
+        let m = InputMacro::parse("[f5]", r"/compact\<enter>").unwrap();
+        assert_eq!(m.sequence, b"\x1b[15~");
+        assert_eq!(m.payload, b"/compact\r");
+        assert!(InputMacro::parse("[nope]", "x").is_err());
+    }
+}
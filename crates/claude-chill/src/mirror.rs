@@ -0,0 +1,103 @@
+//! Drawing a `vt100` screen on a terminal as a diff against what that
+//! terminal already shows.
+
+use crate::escape_sequences::{SYNC_END, SYNC_START};
+
+/// Append the escape sequences that take a terminal showing `prev` to
+/// showing `screen`, or draw `screen` from scratch if `prev` is `None`.
+pub fn render_screen(out: &mut Vec<u8>, screen: &vt100::Screen, prev: Option<&vt100::Screen>) {
+    out.extend_from_slice(SYNC_START);
+    match prev {
+        Some(prev) => out.extend_from_slice(&screen.contents_diff(prev)),
+        None => out.extend_from_slice(&screen.contents_formatted()),
+    }
+    out.extend_from_slice(&screen.cursor_state_formatted());
+    // Positioning the cursor can redraw cells with other attributes, and
+    // the next diff assumes the terminal's pen matches this screen's
+    out.extend_from_slice(&screen.attributes_formatted());
+    out.extend_from_slice(SYNC_END);
+}
+
+/// Keeps one extra terminal, such as a read-only viewer, in step with a
+/// screen. Each mirror diffs against its own copy of what it last drew.
+#[derive(Default)]
+pub struct ScreenMirror {
+    prev: Option<vt100::Screen>,
+}
+
+impl ScreenMirror {
+    /// Bytes that bring the terminal up to date with `screen`, empty if
+    /// nothing changed.
+    pub fn render(&mut self, screen: &vt100::Screen) -> Vec<u8> {
+        let prev = self
+            .prev
+            .as_ref()
+            .filter(|prev| prev.size() == screen.size());
+        if let Some(prev) = prev
+            && prev.contents_diff(screen).is_empty()
+            && prev.cursor_position() == screen.cursor_position()
+            && prev.hide_cursor() == screen.hide_cursor()
+        {
+            return Vec::new();
+        }
+        let mut out = Vec::new();
+        render_screen(&mut out, screen, prev);
+        self.prev = Some(screen.clone());
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shown(output: &[u8]) -> vt100::Parser {
+        let mut parser = vt100::Parser::new(24, 80, 0);
+        parser.process(output);
+        parser
+    }
+
+    #[test]
+    fn test_mirrors_diff_independently() {
+        let mut screen = vt100::Parser::new(24, 80, 0);
+        screen.process(b"one\r\n");
+        let mut early = ScreenMirror::default();
+        let mut terminal = shown(&early.render(screen.screen()));
+
+        screen.process(b"\x1b[1mtwo\x1b[m\r\n");
+        let mut late = ScreenMirror::default();
+        let late_terminal = shown(&late.render(screen.screen()));
+
+        let diff = early.render(screen.screen());
+        assert!(!diff.windows(3).any(|w| w == b"one"));
+        terminal.process(&diff);
+        assert_eq!(
+            terminal.screen().contents_formatted(),
+            late_terminal.screen().contents_formatted()
+        );
+        assert_eq!(terminal.screen().contents(), "one\ntwo");
+    }
+
+    #[test]
+    fn test_unchanged_screen_renders_nothing() {
+        let mut screen = vt100::Parser::new(24, 80, 0);
+        screen.process(b"hello");
+        let mut mirror = ScreenMirror::default();
+        assert!(!mirror.render(screen.screen()).is_empty());
+        assert!(mirror.render(screen.screen()).is_empty());
+        screen.process(b"\x1b[H");
+        assert!(!mirror.render(screen.screen()).is_empty());
+    }
+
+    #[test]
+    fn test_resize_redraws_from_scratch() {
+        let mut screen = vt100::Parser::new(24, 80, 0);
+        screen.process(b"hello");
+        let mut mirror = ScreenMirror::default();
+        mirror.render(screen.screen());
+        screen.screen_mut().set_size(10, 40);
+        let output = mirror.render(screen.screen());
+        assert_eq!(shown(&output).screen().contents(), "hello");
+        assert!(output.windows(3).any(|w| w == b"\x1b[H"));
+    }
+}
@@ -0,0 +1,171 @@
+//! Parser for SGR (1006) mouse reports sent by the terminal on stdin.
+//!
+//! Reports look like `ESC [ < Cb ; Cx ; Cy M` for press/motion and
+//! `ESC [ < Cb ; Cx ; Cy m` for release. Coordinates are 1-based.
+
+const SGR_PREFIX: &[u8] = b"\x1b[<";
+
+const BUTTON_MASK: u16 = 0b11;
+const MOTION_FLAG: u16 = 32;
+const WHEEL_FLAG: u16 = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseEventKind {
+    Press(MouseButton),
+    Release,
+    Drag(MouseButton),
+    Motion,
+    WheelUp,
+    WheelDown,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub kind: MouseEventKind,
+    /// Zero-based column.
+    pub col: u16,
+    /// Zero-based row.
+    pub row: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SgrParse {
+    /// A complete report was parsed, consuming this many bytes.
+    Complete(MouseEvent, usize),
+    /// Input is a prefix of a report; more bytes are needed.
+    Partial,
+    /// Input does not start with a report.
+    None,
+}
+
+/// Try to parse an SGR mouse report at the start of `data`.
+pub fn parse_sgr(data: &[u8]) -> SgrParse {
+    if data.len() < SGR_PREFIX.len() {
+        return if SGR_PREFIX.starts_with(data) {
+            SgrParse::Partial
+        } else {
+            SgrParse::None
+        };
+    }
+    if !data.starts_with(SGR_PREFIX) {
+        return SgrParse::None;
+    }
+
+    let mut params = [0u16; 3];
+    let mut index = 0;
+    let mut has_digit = false;
+
+    for (i, &byte) in data.iter().enumerate().skip(SGR_PREFIX.len()) {
+        match byte {
+            b'0'..=b'9' => {
+                params[index] = params[index]
+                    .saturating_mul(10)
+                    .saturating_add((byte - b'0') as u16);
+                has_digit = true;
+            }
+            b';' if index < 2 && has_digit => {
+                index += 1;
+                has_digit = false;
+            }
+            b'M' | b'm' if index == 2 && has_digit => {
+                let event = MouseEvent {
+                    kind: event_kind(params[0], byte == b'm'),
+                    col: params[1].saturating_sub(1),
+                    row: params[2].saturating_sub(1),
+                };
+                return SgrParse::Complete(event, i + 1);
+            }
+            _ => return SgrParse::None,
+        }
+    }
+
+    SgrParse::Partial
+}
+
+/// Decode the button byte shared by the SGR and legacy X10 encodings.
+pub(crate) fn event_kind(code: u16, release: bool) -> MouseEventKind {
+    if code & WHEEL_FLAG != 0 {
+        return if code & BUTTON_MASK == 0 {
+            MouseEventKind::WheelUp
+        } else {
+            MouseEventKind::WheelDown
+        };
+    }
+    if release {
+        return MouseEventKind::Release;
+    }
+    let button = match code & BUTTON_MASK {
+        0 => MouseButton::Left,
+        1 => MouseButton::Middle,
+        2 => MouseButton::Right,
+        _ => return MouseEventKind::Motion,
+    };
+    if code & MOTION_FLAG != 0 {
+        MouseEventKind::Drag(button)
+    } else {
+        MouseEventKind::Press(button)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn complete(data: &[u8]) -> (MouseEvent, usize) {
+        match parse_sgr(data) {
+            SgrParse::Complete(event, len) => (event, len),
+            other => panic!("expected complete parse, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_wheel_up() {
+        let (event, len) = complete(b"\x1b[<64;10;5M");
+        assert_eq!(event.kind, MouseEventKind::WheelUp);
+        assert_eq!((event.col, event.row), (9, 4));
+        assert_eq!(len, 11);
+    }
+
+    #[test]
+    fn test_wheel_down() {
+        let (event, _) = complete(b"\x1b[<65;1;1M");
+        assert_eq!(event.kind, MouseEventKind::WheelDown);
+    }
+
+    #[test]
+    fn test_press_drag_release() {
+        let (press, _) = complete(b"\x1b[<0;3;4M");
+        assert_eq!(press.kind, MouseEventKind::Press(MouseButton::Left));
+        let (drag, _) = complete(b"\x1b[<32;5;4M");
+        assert_eq!(drag.kind, MouseEventKind::Drag(MouseButton::Left));
+        let (release, _) = complete(b"\x1b[<0;5;4m");
+        assert_eq!(release.kind, MouseEventKind::Release);
+    }
+
+    #[test]
+    fn test_partial() {
+        assert_eq!(parse_sgr(b"\x1b"), SgrParse::Partial);
+        assert_eq!(parse_sgr(b"\x1b[<"), SgrParse::Partial);
+        assert_eq!(parse_sgr(b"\x1b[<64;10"), SgrParse::Partial);
+    }
+
+    #[test]
+    fn test_not_mouse() {
+        assert_eq!(parse_sgr(b"a"), SgrParse::None);
+        assert_eq!(parse_sgr(b"\x1b[A"), SgrParse::None);
+        assert_eq!(parse_sgr(b"\x1b[<64;;1M"), SgrParse::None);
+    }
+
+    #[test]
+    fn test_trailing_bytes_not_consumed() {
+        let (_, len) = complete(b"\x1b[<64;1;1Mabc");
+        assert_eq!(len, 10);
+    }
+}
@@ -0,0 +1,85 @@
+//! Desktop notifications through the terminal itself, using the escape
+//! sequences that iTerm2, kitty, foot, WezTerm, Ghostty and others turn into
+//! native notifications.
+
+use serde::{Deserialize, Serialize};
+
+/// Which notification escape the terminal understands.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotificationStyle {
+    #[default]
+    Off,
+    /// `OSC 9 ; body`: iTerm2, WezTerm, Ghostty, Windows Terminal.
+    Osc9,
+    /// `OSC 777 ; notify ; title ; body`: foot, urxvt, WezTerm, Ghostty.
+    Osc777,
+    /// `OSC 99`: kitty's notification protocol.
+    Osc99,
+}
+
+/// The escape sequence that shows a notification, empty if notifications
+/// are off. Control characters are dropped so the text can't end the
+/// sequence early.
+pub fn notification(style: NotificationStyle, title: &str, body: &str) -> Vec<u8> {
+    let title = sanitize(title);
+    let body = sanitize(body);
+    let sequence = match style {
+        NotificationStyle::Off => return Vec::new(),
+        NotificationStyle::Osc9 => format!("\x1b]9;{}: {}\x07", title, body),
+        // The title is a field of its own here, so it can't contain `;`
+        NotificationStyle::Osc777 => {
+            format!("\x1b]777;notify;{};{}\x07", title.replace(';', ","), body)
+        }
+        NotificationStyle::Osc99 => format!(
+            "\x1b]99;i=claude-chill:d=0;{}\x1b\\\x1b]99;i=claude-chill:d=1:p=body;{}\x1b\\",
+            title, body
+        ),
+    };
+    sequence.into_bytes()
+}
+
+fn sanitize(text: &str) -> String {
+    text.chars().filter(|c| !c.is_control()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notification_styles() {
+        assert!(notification(NotificationStyle::Off, "t", "b").is_empty());
+        assert_eq!(
+            notification(NotificationStyle::Osc9, "claude-chill", "Waiting"),
+            b"\x1b]9;claude-chill: Waiting\x07"
+        );
+        assert_eq!(
+            notification(NotificationStyle::Osc777, "a;b", "Waiting; really"),
+            b"\x1b]777;notify;a,b;Waiting; really\x07"
+        );
+        assert_eq!(
+            notification(NotificationStyle::Osc99, "t", "b"),
+            b"\x1b]99;i=claude-chill:d=0;t\x1b\\\x1b]99;i=claude-chill:d=1:p=body;b\x1b\\"
+        );
+    }
+
+    #[test]
+    fn test_control_characters_are_dropped() {
+        assert_eq!(
+            notification(NotificationStyle::Osc9, "t", "a\x07b\x1b\\c\n"),
+            b"\x1b]9;t: ab\\c\x07"
+        );
+    }
+
+    #[test]
+    fn test_parse_style() {
+        #[derive(Deserialize)]
+        struct Wrapper {
+            style: NotificationStyle,
+        }
+        let parsed: Wrapper = toml::from_str("style = \"osc777\"").unwrap();
+        assert_eq!(parsed.style, NotificationStyle::Osc777);
+        assert!(toml::from_str::<Wrapper>("style = \"growl\"").is_err());
+    }
+}
@@ -1,37 +1,42 @@
-use crate::escape_filter::TerminalQueryFilter;
-use crate::escape_sequences::{
-    ALT_SCREEN_ENTER, ALT_SCREEN_ENTER_LEGACY, ALT_SCREEN_EXIT, ALT_SCREEN_EXIT_LEGACY,
-    CLEAR_SCREEN, CURSOR_HOME, INPUT_BUFFER_CAPACITY, OUTPUT_BUFFER_CAPACITY, SYNC_BUFFER_CAPACITY,
-    SYNC_END, SYNC_START,
+use crate::asciicast::AsciicastWriter;
+use crate::config::{EnvConfig, RestartConfig};
+use crate::control::{
+    self, Attached, ControlServer, Controller, Mode, Request, Response, State, TextFormat,
 };
-use crate::line_buffer::LineBuffer;
+use crate::escape_sequences::LOOKBACK_MOUSE_DISABLE;
+use crate::hooks::{Hook, Hooks};
+use crate::input_recorder::InputRecorder;
+use crate::mirror::ScreenMirror;
+pub use crate::proxy_core::ProxyConfig;
+use crate::proxy_core::{ProxyCore, SessionEvent};
+use crate::session::{self, ClientMessage, Connection, ServerMessage, SessionServer};
+use crate::watchdog::{CpuMonitor, Watchdog};
 use anyhow::{Context, Result};
 use log::debug;
-use memchr::memmem;
 use nix::errno::Errno;
 use nix::fcntl::{FcntlArg, OFlag, fcntl};
 use nix::poll::{PollFd, PollFlags, PollTimeout, poll};
 use nix::pty::{Winsize, openpty};
 use nix::sys::signal::{SaFlags, SigAction, SigHandler, SigSet, Signal, kill, sigaction};
 use nix::sys::termios::{SetArg, Termios, cfmakeraw, tcgetattr, tcsetattr};
-use nix::unistd::{Pid, isatty, read, write};
+use nix::sys::wait::{WaitStatus, waitpid};
+use nix::unistd::{ForkResult, Pid, fork, isatty, read, setsid, write};
+use std::fs::File;
 use std::io;
-use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
 use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
 use std::process::{Child, Command, ExitStatus};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
 use std::time::{Duration, Instant};
 
 static SIGWINCH_RECEIVED: AtomicBool = AtomicBool::new(false);
 static SIGINT_RECEIVED: AtomicBool = AtomicBool::new(false);
 static SIGTERM_RECEIVED: AtomicBool = AtomicBool::new(false);
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum SequenceMatch {
-    Complete,
-    Partial,
-    None,
-}
+static SIGHUP_RECEIVED: AtomicBool = AtomicBool::new(false);
+/// The session's process, as seen by the original one standing in for it.
+static SESSION_PID: AtomicI32 = AtomicI32::new(0);
 
 extern "C" fn handle_sigwinch(_: libc::c_int) {
     SIGWINCH_RECEIVED.store(true, Ordering::SeqCst);
@@ -45,30 +50,24 @@ extern "C" fn handle_sigterm(_: libc::c_int) {
     SIGTERM_RECEIVED.store(true, Ordering::SeqCst);
 }
 
-pub struct ProxyConfig {
-    pub max_history_lines: usize,
-    pub lookback_key: String,
-    pub lookback_sequence: Vec<u8>,
-    pub auto_lookback_timeout_ms: u64,
+extern "C" fn handle_sighup(_: libc::c_int) {
+    SIGHUP_RECEIVED.store(true, Ordering::SeqCst);
 }
 
-impl Default for ProxyConfig {
-    fn default() -> Self {
-        Self {
-            max_history_lines: 100_000,
-            lookback_key: "[ctrl][6]".to_string(),
-            lookback_sequence: vec![0x1E],
-            auto_lookback_timeout_ms: 5000,
-        }
+extern "C" fn forward_to_session(signal: libc::c_int) {
+    let pid = SESSION_PID.load(Ordering::SeqCst);
+    if pid > 0 {
+        unsafe { libc::kill(pid, signal) };
     }
 }
 
-struct TerminalGuard {
+/// Puts the terminal in raw mode, restoring it on drop.
+pub(crate) struct TerminalGuard {
     original_termios: Option<Termios>,
 }
 
 impl TerminalGuard {
-    fn new() -> Result<Self> {
+    pub(crate) fn new() -> Result<Self> {
         let original_termios = setup_raw_mode()?;
         Ok(Self { original_termios })
     }
@@ -86,720 +85,1159 @@ impl Drop for TerminalGuard {
     }
 }
 
-const RENDER_DELAY_MS: u64 = 5;
-const SYNC_BLOCK_DELAY_MS: u64 = 50;
+const MAX_POLL_TIMEOUT_MS: u16 = 100;
+
+/// A terminal attached over the session socket.
+struct AttachedClient {
+    connection: Connection,
+    winsize: Winsize,
+}
+
+/// A read-only terminal connected with `claude-chill watch`.
+struct Viewer {
+    connection: Connection,
+    mirror: ScreenMirror,
+}
+
+/// What to run in the PTY, and how.
+#[derive(Clone)]
+struct ChildSpec {
+    command: String,
+    args: Vec<String>,
+    env: EnvConfig,
+    cwd: Option<PathBuf>,
+    session_id: Option<String>,
+    control_path: Option<PathBuf>,
+}
 
+/// A failed command waiting to be restarted.
+struct PendingRestart {
+    at: Instant,
+    exit_code: i32,
+}
+
+/// Runs a command in a PTY and drives a `ProxyCore` from the terminal, the
+/// PTY and signals.
+///
+/// The session can outlive the terminal it started in: on the detach key or
+/// a hangup the proxy lets go of the terminal and keeps running, and
+/// `claude-chill attach` can connect another one through the session socket.
+/// Any number of viewers can watch alongside.
 pub struct Proxy {
-    config: ProxyConfig,
+    core: ProxyCore,
     pty_master: OwnedFd,
     child: Child,
     original_termios: Option<Termios>,
-    history: LineBuffer,
-    history_filter: TerminalQueryFilter,
-    vt_parser: vt100::Parser,
-    vt_prev_screen: Option<vt100::Screen>,
-    last_output_time: Option<Instant>,
-    last_render_time: Option<Instant>,
-    auto_lookback_timeout: Duration,
-    sync_buffer: Vec<u8>,
-    in_sync_block: bool,
-    in_lookback_mode: bool,
-    in_alternate_screen: bool,
-    vt_render_pending: bool,
-    lookback_cache: Vec<u8>,
-    lookback_input_buffer: Vec<u8>,
-    output_buffer: Vec<u8>,
-    sync_start_finder: memmem::Finder<'static>,
-    sync_end_finder: memmem::Finder<'static>,
-    clear_screen_finder: memmem::Finder<'static>,
-    cursor_home_finder: memmem::Finder<'static>,
-    alt_screen_enter_finder: memmem::Finder<'static>,
-    alt_screen_exit_finder: memmem::Finder<'static>,
-    alt_screen_enter_legacy_finder: memmem::Finder<'static>,
-    alt_screen_exit_legacy_finder: memmem::Finder<'static>,
+    terminal_recording: Option<AsciicastWriter>,
+    child_recording: Option<AsciicastWriter>,
+    id: String,
+    session: Option<SessionServer>,
+    control: Option<ControlServer>,
+    controllers: Vec<Controller>,
+    /// Whether the terminal the proxy started in is still attached.
+    local: bool,
+    client: Option<AttachedClient>,
+    /// Connections waiting for their `Attach` or `Watch`.
+    pending: Vec<Connection>,
+    viewers: Vec<Viewer>,
+    hooks: Hooks,
+    watchdog: Watchdog,
+    /// Samples the child's CPU usage, if the watchdog looks at it.
+    cpu_monitor: Option<CpuMonitor>,
+    child_spec: ChildSpec,
+    restart: RestartConfig,
+    restarts: u32,
+    /// Set while a failed command counts down to its restart.
+    pending_restart: Option<PendingRestart>,
+    /// Exit code to finish with once the command won't be restarted.
+    exit_code: Option<i32>,
+    /// Whether we were asked to stop, so a failing command stays down.
+    stopping: bool,
+    /// Link to the original process standing in for the session: it's told
+    /// when we detach, so it can exit and give the shell its terminal back,
+    /// and hangs up if the original is killed.
+    stand_in: Option<OwnedFd>,
 }
 
 impl Proxy {
-    pub fn spawn(command: &str, args: &[&str], config: ProxyConfig) -> Result<Self> {
+    /// Start `command` in a new session. A session that can detach forks:
+    /// it runs in the copy, which is the command's parent and so can tell
+    /// its exit code even after detaching, and the original never returns.
+    pub fn spawn(command: &str, args: &[&str], mut config: ProxyConfig) -> Result<Self> {
+        // Named after the process the shell knows
+        let id = std::process::id().to_string();
+        let session_dir = session::session_dir();
+        let mut session = match SessionServer::bind(&session_dir, &id) {
+            Ok(session) => Some(session),
+            Err(e) => {
+                debug!("Proxy::spawn: detaching disabled: {:#}", e);
+                config.detach_sequence.clear();
+                None
+            }
+        };
+        let stand_in = match session.as_mut() {
+            Some(session) => {
+                let stand_in = fork_session()?;
+                session.adopt();
+                Some(stand_in)
+            }
+            None => None,
+        };
+        let id = session
+            .as_ref()
+            .map_or(id, |session| session.id().to_string());
+
+        let input_recorder = config
+            .record_input
+            .as_deref()
+            .map(InputRecorder::create)
+            .transpose()?;
+
         let winsize = get_terminal_size()?;
-        let pty = openpty(&winsize, None).context("openpty failed")?;
+        let terminal_recording = config
+            .record
+            .as_deref()
+            .map(|path| AsciicastWriter::create(path, winsize.ws_col, winsize.ws_row))
+            .transpose()?;
+        let child_recording = config
+            .record_raw
+            .as_deref()
+            .map(|path| AsciicastWriter::create(path, winsize.ws_col, winsize.ws_row))
+            .transpose()?;
+
+        let control = match ControlServer::bind(&session_dir, &id) {
+            Ok(control) => Some(control),
+            Err(e) => {
+                debug!("Proxy::spawn: control socket disabled: {:#}", e);
+                None
+            }
+        };
 
         let terminal_guard = TerminalGuard::new()?;
         setup_signal_handlers()?;
+        if session.is_some() {
+            setup_signal_handler(Signal::SIGHUP, handle_sighup)?;
+        }
 
-        let slave_fd = pty.slave.as_raw_fd();
-
-        let child = unsafe {
-            Command::new(command)
-                .args(args)
-                .pre_exec(move || {
-                    if libc::setsid() == -1 {
-                        return Err(io::Error::last_os_error());
-                    }
-                    if libc::ioctl(slave_fd, libc::TIOCSCTTY as libc::c_ulong, 0) == -1 {
-                        return Err(io::Error::last_os_error());
-                    }
-                    if libc::dup2(slave_fd, 0) == -1 {
-                        return Err(io::Error::last_os_error());
-                    }
-                    if libc::dup2(slave_fd, 1) == -1 {
-                        return Err(io::Error::last_os_error());
-                    }
-                    if libc::dup2(slave_fd, 2) == -1 {
-                        return Err(io::Error::last_os_error());
-                    }
-                    if slave_fd > 2 {
-                        libc::close(slave_fd);
-                    }
-                    Ok(())
-                })
-                .spawn()
-                .context("spawn failed")?
+        let child_spec = ChildSpec {
+            command: command.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            env: config.env.clone(),
+            cwd: config.cwd.clone(),
+            session_id: session.as_ref().map(|session| session.id().to_string()),
+            control_path: control.as_ref().map(|control| control.path().to_path_buf()),
         };
-
-        drop(pty.slave);
-        set_nonblocking(&pty.master)?;
-
-        let vt_parser = vt100::Parser::new(winsize.ws_row, winsize.ws_col, 0);
-
-        // Seed history with clear screen so replay starts fresh
-        let mut history = LineBuffer::new(config.max_history_lines);
-        history.push_bytes(CLEAR_SCREEN);
-        history.push_bytes(CURSOR_HOME);
-
-        let auto_lookback_timeout = Duration::from_millis(config.auto_lookback_timeout_ms);
+        let (pty_master, child) = spawn_child(&child_spec, &winsize)?;
+
+        let hooks = Hooks::new(config.hooks.clone());
+        let config_restart = config.restart.clone();
+        let watchdog = Watchdog::new(&config.watchdog);
+        let cpu_monitor = watchdog.watches_cpu().then(|| CpuMonitor::new(child.id()));
+        let mut core = ProxyCore::new(config, winsize.ws_row, winsize.ws_col, Instant::now());
+        if let Some(recorder) = input_recorder {
+            core.set_input_recorder(recorder);
+        }
 
         debug!("Proxy::spawn: command={} args={:?}", command, args);
 
         Ok(Self {
-            history,
-            history_filter: TerminalQueryFilter::new(),
-            config,
-            pty_master: pty.master,
+            core,
+            pty_master,
             child,
             original_termios: terminal_guard.take(),
-            vt_parser,
-            vt_prev_screen: None,
-            last_output_time: None,
-            last_render_time: None,
-            auto_lookback_timeout,
-            sync_buffer: Vec::with_capacity(SYNC_BUFFER_CAPACITY),
-            in_sync_block: false,
-            in_lookback_mode: false,
-            in_alternate_screen: false,
-            vt_render_pending: false,
-            lookback_cache: Vec::new(),
-            lookback_input_buffer: Vec::with_capacity(INPUT_BUFFER_CAPACITY),
-            output_buffer: Vec::with_capacity(OUTPUT_BUFFER_CAPACITY),
-            sync_start_finder: memmem::Finder::new(SYNC_START),
-            sync_end_finder: memmem::Finder::new(SYNC_END),
-            clear_screen_finder: memmem::Finder::new(CLEAR_SCREEN),
-            cursor_home_finder: memmem::Finder::new(CURSOR_HOME),
-            alt_screen_enter_finder: memmem::Finder::new(ALT_SCREEN_ENTER),
-            alt_screen_exit_finder: memmem::Finder::new(ALT_SCREEN_EXIT),
-            alt_screen_enter_legacy_finder: memmem::Finder::new(ALT_SCREEN_ENTER_LEGACY),
-            alt_screen_exit_legacy_finder: memmem::Finder::new(ALT_SCREEN_EXIT_LEGACY),
+            terminal_recording,
+            child_recording,
+            id,
+            session,
+            control,
+            controllers: Vec::new(),
+            local: true,
+            client: None,
+            pending: Vec::new(),
+            viewers: Vec::new(),
+            hooks,
+            watchdog,
+            cpu_monitor,
+            child_spec,
+            restart: config_restart,
+            restarts: 0,
+            pending_restart: None,
+            exit_code: None,
+            stopping: false,
+            stand_in,
         })
     }
 
     pub fn run(&mut self) -> Result<i32> {
         let stdin_fd = io::stdin();
-        let stdout_fd = io::stdout();
 
         let mut buf = [0u8; 65536];
 
         loop {
             if SIGWINCH_RECEIVED.swap(false, Ordering::SeqCst) {
-                self.forward_winsize()?;
+                self.forward_winsize();
             }
             if SIGINT_RECEIVED.swap(false, Ordering::SeqCst) {
                 self.forward_signal(Signal::SIGINT);
             }
             if SIGTERM_RECEIVED.swap(false, Ordering::SeqCst) {
+                self.stopping = true;
+                self.cancel_restart();
                 self.forward_signal(Signal::SIGTERM);
             }
+            if SIGHUP_RECEIVED.swap(false, Ordering::SeqCst) && self.local {
+                self.hang_up();
+            }
+            if self
+                .pending_restart
+                .as_ref()
+                .is_some_and(|restart| Instant::now() >= restart.at)
+            {
+                self.restart_child()?;
+            }
+            if self.exit_code.is_some() {
+                break;
+            }
 
             let master_fd = unsafe { BorrowedFd::borrow_raw(self.pty_master.as_raw_fd()) };
             let stdin_borrowed = unsafe { BorrowedFd::borrow_raw(stdin_fd.as_raw_fd()) };
 
-            let mut poll_fds = [
-                PollFd::new(master_fd, PollFlags::POLLIN),
-                PollFd::new(stdin_borrowed, PollFlags::POLLIN),
-            ];
+            // The PTY of a command that has exited only hangs up
+            let mut poll_fds = Vec::new();
+            let pty_index = self.pending_restart.is_none().then(|| {
+                poll_fds.push(PollFd::new(master_fd, PollFlags::POLLIN));
+                poll_fds.len() - 1
+            });
+            let stdin_index = self.local.then(|| {
+                poll_fds.push(PollFd::new(stdin_borrowed, PollFlags::POLLIN));
+                poll_fds.len() - 1
+            });
+            // The original never writes, so anything here means it's gone
+            let stand_in_index = self.stand_in.as_ref().map(|stand_in| {
+                poll_fds.push(PollFd::new(stand_in.as_fd(), PollFlags::POLLIN));
+                poll_fds.len() - 1
+            });
+            let listener_index = self.session.as_ref().map(|session| {
+                let fd = unsafe { BorrowedFd::borrow_raw(session.listener().as_raw_fd()) };
+                poll_fds.push(PollFd::new(fd, PollFlags::POLLIN));
+                poll_fds.len() - 1
+            });
+            let client_index = self.client.as_ref().map(|client| {
+                let fd = unsafe { BorrowedFd::borrow_raw(client.connection.stream().as_raw_fd()) };
+                let mut flags = PollFlags::POLLIN;
+                if client.connection.wants_write() {
+                    flags |= PollFlags::POLLOUT;
+                }
+                poll_fds.push(PollFd::new(fd, flags));
+                poll_fds.len() - 1
+            });
+            let pending_start = poll_fds.len();
+            for connection in &self.pending {
+                let fd = unsafe { BorrowedFd::borrow_raw(connection.stream().as_raw_fd()) };
+                poll_fds.push(PollFd::new(fd, PollFlags::POLLIN));
+            }
+            let pending_end = poll_fds.len();
+            let control_index = self.control.as_ref().map(|control| {
+                let fd = unsafe { BorrowedFd::borrow_raw(control.listener().as_raw_fd()) };
+                poll_fds.push(PollFd::new(fd, PollFlags::POLLIN));
+                poll_fds.len() - 1
+            });
+            let controllers_start = poll_fds.len();
+            for controller in &self.controllers {
+                let fd = unsafe { BorrowedFd::borrow_raw(controller.stream().as_raw_fd()) };
+                let mut flags = PollFlags::POLLIN;
+                if controller.wants_write() {
+                    flags |= PollFlags::POLLOUT;
+                }
+                poll_fds.push(PollFd::new(fd, flags));
+            }
+            let viewers_start = poll_fds.len();
+            for viewer in &self.viewers {
+                let fd = unsafe { BorrowedFd::borrow_raw(viewer.connection.stream().as_raw_fd()) };
+                let mut flags = PollFlags::POLLIN;
+                if viewer.connection.wants_write() {
+                    flags |= PollFlags::POLLOUT;
+                }
+                poll_fds.push(PollFd::new(fd, flags));
+            }
 
             let poll_timeout_ms = self
-                .time_until_render()
-                .map(|d| d.as_millis().min(100) as u16)
-                .unwrap_or(100);
+                .core
+                .next_deadline()
+                .map(|deadline| {
+                    let wait = deadline.saturating_duration_since(Instant::now());
+                    // Round up so we don't wake just before the deadline
+                    wait.as_micros()
+                        .div_ceil(1000)
+                        .min(MAX_POLL_TIMEOUT_MS as u128) as u16
+                })
+                .unwrap_or(MAX_POLL_TIMEOUT_MS);
 
             match poll(&mut poll_fds, PollTimeout::from(poll_timeout_ms)) {
                 Ok(0) => {
-                    self.flush_pending_vt_render(&stdout_fd)?;
-                    self.check_auto_lookback(&stdout_fd)?;
+                    self.core.tick(Instant::now());
+                    self.flush()?;
+                    self.check_watchdog()?;
                     continue;
                 }
                 Ok(_) => {}
                 Err(Errno::EINTR) => continue,
                 Err(e) => anyhow::bail!("poll failed: {}", e),
             }
+            let revents: Vec<PollFlags> = poll_fds
+                .iter()
+                .map(|fd| fd.revents().unwrap_or(PollFlags::empty()))
+                .collect();
+            drop(poll_fds);
+
+            // Viewers can be dropped by any write to them, so they're looked
+            // up by fd when served
+            let ready_viewers: Vec<(RawFd, PollFlags)> = self
+                .viewers
+                .iter()
+                .zip(&revents[viewers_start..])
+                .filter(|(_, revents)| !revents.is_empty())
+                .map(|(viewer, revents)| (viewer.connection.stream().as_raw_fd(), *revents))
+                .collect();
+
+            if let Some(index) = stand_in_index
+                && !revents[index].is_empty()
+            {
+                stand_in_killed();
+            }
 
-            self.flush_pending_vt_render(&stdout_fd)?;
+            self.core.tick(Instant::now());
+            self.flush()?;
+            self.check_watchdog()?;
 
-            if let Some(revents) = poll_fds[0].revents() {
-                if revents.contains(PollFlags::POLLIN) {
+            if let Some(index) = pty_index {
+                let mut hung_up = revents[index].contains(PollFlags::POLLHUP);
+                if revents[index].contains(PollFlags::POLLIN) {
                     match nix_read(&self.pty_master, &mut buf) {
-                        Ok(0) => break,
-                        Ok(n) => self.process_output(&buf[..n], &stdout_fd)?,
+                        Ok(0) | Err(Errno::EIO) => hung_up = true,
+                        Ok(n) => {
+                            record_output(&mut self.child_recording, &buf[..n]);
+                            self.core.output(&buf[..n], Instant::now());
+                            self.flush()?;
+                        }
                         Err(Errno::EAGAIN) => {}
-                        Err(Errno::EIO) => break,
                         Err(e) => anyhow::bail!("read from pty failed: {}", e),
                     }
                 }
-                if revents.contains(PollFlags::POLLHUP) {
-                    break;
+                if hung_up {
+                    self.child_exited()?;
+                    if self.exit_code.is_some() {
+                        break;
+                    }
+                    continue;
                 }
             }
 
-            if let Some(revents) = poll_fds[1].revents()
-                && revents.contains(PollFlags::POLLIN)
+            if let Some(index) = stdin_index
+                && revents[index].intersects(PollFlags::POLLIN | PollFlags::POLLHUP)
             {
                 match nix_read(&stdin_fd, &mut buf) {
+                    // A terminal in raw mode only ends when it hangs up
+                    Ok(0) | Err(Errno::EIO)
+                        if self.session.is_some() && self.original_termios.is_some() =>
+                    {
+                        self.hang_up();
+                    }
                     Ok(0) => break,
-                    Ok(n) => self.process_input(&buf[..n], &stdout_fd)?,
+                    Ok(n) => {
+                        self.core.input(&buf[..n], Instant::now());
+                        self.flush()?;
+                    }
                     Err(Errno::EAGAIN) => {}
                     Err(e) => anyhow::bail!("read from stdin failed: {}", e),
                 }
             }
-        }
 
-        // Final render before exit
-        if self.vt_render_pending {
-            self.render_vt_screen(&stdout_fd)?;
-        }
-
-        self.wait_child()
-    }
+            if let Some(index) = client_index
+                && !revents[index].is_empty()
+            {
+                self.serve_client(revents[index])?;
+            }
 
-    fn process_output<F: AsFd>(&mut self, data: &[u8], stdout_fd: &F) -> Result<()> {
-        self.process_output_inner(data, stdout_fd, true)
-    }
+            for index in (0..viewers_start - controllers_start).rev() {
+                if !revents[controllers_start + index].is_empty() {
+                    self.serve_controller(index, revents[controllers_start + index])?;
+                }
+            }
+            for (fd, revents) in ready_viewers {
+                self.serve_viewer(fd, revents);
+            }
+            // Backwards, so serving one doesn't move the others still to go
+            for index in (0..pending_end - pending_start).rev() {
+                if !revents[pending_start + index].is_empty() {
+                    self.serve_pending(index)?;
+                }
+            }
 
-    fn process_output_inner<F: AsFd>(
-        &mut self,
-        data: &[u8],
-        stdout_fd: &F,
-        feed_vt: bool,
-    ) -> Result<()> {
-        debug!(
-            "process_output: len={} in_alt={} in_lookback={} feed_vt={}",
-            data.len(),
-            self.in_alternate_screen,
-            self.in_lookback_mode,
-            feed_vt
-        );
+            if let Some(index) = listener_index
+                && revents[index].contains(PollFlags::POLLIN)
+            {
+                self.accept_client();
+            }
+            if let Some(index) = control_index
+                && revents[index].contains(PollFlags::POLLIN)
+            {
+                self.accept_controller();
+            }
+        }
 
-        if self.in_alternate_screen {
-            // Feed VT to track screen state, but don't add to history.
-            // Alternate screen content is ephemeral and shouldn't be in scrollback.
-            // This also prevents bells and other control chars from being replayed.
-            if feed_vt {
-                self.vt_parser.process(data);
+        let exit_code = match self.exit_code {
+            Some(exit_code) => exit_code,
+            None => {
+                // Final render before exit
+                self.core.finish();
+                self.flush()?;
+                self.wait_child()?
             }
-            return self.process_output_alt_screen(data, stdout_fd);
+        };
+        self.run_hook(
+            Hook::Exit,
+            vec![("CLAUDE_CHILL_EXIT_CODE", exit_code.to_string())],
+        );
+        if let Some(client) = self.client.take() {
+            client
+                .connection
+                .close_with(&ServerMessage::Exited(exit_code));
+        }
+        for viewer in self.viewers.drain(..) {
+            viewer
+                .connection
+                .close_with(&ServerMessage::Exited(exit_code));
         }
+        Ok(exit_code)
+    }
 
-        if self.in_lookback_mode {
-            debug!("process_output: caching {} bytes for lookback", data.len());
-            self.lookback_cache.extend_from_slice(data);
-            return Ok(());
+    /// Write out whatever the core queued for the terminal and the PTY.
+    fn flush(&mut self) -> Result<()> {
+        if self.core.take_detach_request() {
+            self.detach()?;
         }
 
-        // Feed data to VT emulator (unless already fed by caller)
-        if feed_vt {
-            self.vt_parser.process(data);
+        if self.core.take_winsize_sync()
+            && let Some(winsize) = self.terminal_size()
+            && (winsize.ws_row, winsize.ws_col) != self.core.screen().size()
+        {
+            self.resize(winsize);
+            self.core.redraw();
         }
-        self.vt_render_pending = true;
-        self.last_output_time = Some(Instant::now());
 
-        // Process sync blocks for history management
-        let mut pos = 0;
-        while pos < data.len() {
-            // Check for alt screen enter
-            if let Some(alt_pos) = self.find_alt_screen_enter(&data[pos..]) {
-                debug!(
-                    "process_output: ALT_SCREEN_ENTER detected at pos={}",
-                    pos + alt_pos
-                );
-                // Add ALL remaining data to history (including alt screen enter and content)
-                // This ensures history matches VT exactly
-                let remaining = &data[pos..];
-                if self.in_sync_block {
-                    self.sync_buffer.extend_from_slice(remaining);
-                    self.flush_sync_block_to_history();
-                    self.in_sync_block = false;
-                } else {
-                    self.push_to_history(remaining);
-                }
-                self.in_alternate_screen = true;
-                let seq_len = self.alt_screen_enter_len(&data[pos + alt_pos..]);
-                // Write alt screen enter directly
-                write_all(stdout_fd, &data[pos + alt_pos..pos + alt_pos + seq_len])?;
-                return self.process_output_alt_screen(&data[pos + alt_pos + seq_len..], stdout_fd);
-            }
-
-            if self.in_sync_block {
-                if let Some(idx) = self.sync_end_finder.find(&data[pos..]) {
-                    debug!("process_output: SYNC_END at pos={}", pos + idx);
-                    self.sync_buffer.extend_from_slice(&data[pos..pos + idx]);
-                    self.sync_buffer.extend_from_slice(SYNC_END);
-                    self.flush_sync_block_to_history();
-                    self.in_sync_block = false;
-                    pos += idx + SYNC_END.len();
-                } else {
-                    self.sync_buffer.extend_from_slice(&data[pos..]);
-                    break;
+        let terminal_output = self.core.take_terminal_output();
+        if !terminal_output.is_empty() {
+            if self.local {
+                record_output(&mut self.terminal_recording, &terminal_output);
+                write_all(&io::stdout(), &terminal_output)?;
+            } else if let Some(client) = self.client.as_mut() {
+                record_output(&mut self.terminal_recording, &terminal_output);
+                if let Err(e) = client
+                    .connection
+                    .send(&ServerMessage::Output(terminal_output))
+                {
+                    debug!("flush: dropping client: {:#}", e);
+                    self.client = None;
                 }
-            } else if let Some(idx) = self.sync_start_finder.find(&data[pos..]) {
-                debug!("process_output: SYNC_START at pos={}", pos + idx);
-                // Add any data before SYNC_START to history
-                if idx > 0 {
-                    self.push_to_history(&data[pos..pos + idx]);
-                }
-                self.in_sync_block = true;
-                self.sync_buffer.clear();
-                self.sync_buffer.extend_from_slice(SYNC_START);
-                pos += idx + SYNC_START.len();
+            }
+            // With nothing attached the output is dropped; the core's screen
+            // is drawn in full for the next terminal to attach
+            self.update_viewers();
+        }
+        let pty_output = self.core.take_pty_output();
+        if !pty_output.is_empty() {
+            if self.pending_restart.is_some() {
+                // Any key cancels the restart
+                self.cancel_restart();
             } else {
-                // No sync block, just add to history
-                self.push_to_history(&data[pos..]);
-                break;
+                write_all(&self.pty_master, &pty_output)?;
             }
         }
 
+        for event in self.core.take_events() {
+            match event {
+                SessionEvent::Idle { active, .. } => self.run_hook(
+                    Hook::Idle,
+                    vec![("CLAUDE_CHILL_ACTIVE_MS", active.as_millis().to_string())],
+                ),
+                SessionEvent::Bell => self.run_hook(Hook::Bell, Vec::new()),
+                SessionEvent::AltScreen(entered) => self.run_hook(
+                    Hook::AltScreen,
+                    vec![("CLAUDE_CHILL_ALT_SCREEN", u8::from(entered).to_string())],
+                ),
+            }
+        }
         Ok(())
     }
 
-    fn process_output_alt_screen<F: AsFd>(&mut self, data: &[u8], stdout_fd: &F) -> Result<()> {
-        if let Some(exit_pos) = self.find_alt_screen_exit(data) {
-            debug!(
-                "process_output_alt_screen: ALT_SCREEN_EXIT detected at pos={}",
-                exit_pos
-            );
-            write_all(stdout_fd, &data[..exit_pos])?;
-            let seq_len = self.alt_screen_exit_len(&data[exit_pos..]);
-            write_all(stdout_fd, &data[exit_pos..exit_pos + seq_len])?;
-            self.in_alternate_screen = false;
-
-            // Force full VT render to restore main screen content
-            debug!("process_output_alt_screen: rendering VT screen after alt exit");
-            self.vt_prev_screen = None;
-            self.render_vt_screen(stdout_fd)?;
-
-            // Data after ALT_EXIT was already fed to VT and history when we processed
-            // the alt screen chunk, so we just need to check for more alt screen transitions
-            let remaining = &data[exit_pos + seq_len..];
-            if !remaining.is_empty() {
-                // Check if there's another alt screen enter in the remaining data
-                if self.find_alt_screen_enter(remaining).is_some() {
-                    // Need to process for alt screen detection, but skip VT/history feed
-                    return self.process_output_check_alt_only(remaining, stdout_fd);
-                }
-            }
+    /// Show a banner, and send the configured signal, if the child looks
+    /// hung.
+    fn check_watchdog(&mut self) -> Result<()> {
+        if self.pending_restart.is_some() {
             return Ok(());
         }
-        write_all(stdout_fd, data)
-    }
-
-    /// Check for alt screen transitions without re-feeding VT/history
-    fn process_output_check_alt_only<F: AsFd>(&mut self, data: &[u8], stdout_fd: &F) -> Result<()> {
-        if let Some(alt_pos) = self.find_alt_screen_enter(data) {
-            debug!(
-                "process_output_check_alt_only: ALT_SCREEN_ENTER at pos={}",
-                alt_pos
-            );
-            self.in_alternate_screen = true;
-            let seq_len = self.alt_screen_enter_len(&data[alt_pos..]);
-            write_all(stdout_fd, &data[alt_pos..alt_pos + seq_len])?;
-            return self.process_output_alt_screen(&data[alt_pos + seq_len..], stdout_fd);
+        let now = Instant::now();
+        let cpu_percent = self
+            .cpu_monitor
+            .as_mut()
+            .and_then(|monitor| monitor.sample(now));
+        let Some(hang) = self.watchdog.check(
+            now,
+            self.core.idle_time(now),
+            self.core.activity(),
+            cpu_percent,
+        ) else {
+            return Ok(());
+        };
+        let mut message = format!("claude-chill: command looks hung: {}", hang.description());
+        if let Some(signal) = self.watchdog.signal() {
+            self.forward_signal(signal);
+            message.push_str(&format!(", sent {}", signal));
         }
-        Ok(())
+        debug!("check_watchdog: {}", message);
+        self.core.show_banner(&message);
+        self.flush()
     }
 
-    fn find_alt_screen_enter(&self, data: &[u8]) -> Option<usize> {
-        let pos1 = self.alt_screen_enter_finder.find(data);
-        let pos2 = self.alt_screen_enter_legacy_finder.find(data);
-        match (pos1, pos2) {
-            (Some(a), Some(b)) => Some(a.min(b)),
-            (Some(a), None) => Some(a),
-            (None, Some(b)) => Some(b),
-            (None, None) => None,
+    /// Run `hook` with the session's context plus `env`, unless it has no
+    /// command or is still running from last time.
+    fn run_hook(&mut self, hook: Hook, mut env: Vec<(&'static str, String)>) {
+        if !self.hooks.ready(hook) {
+            return;
         }
-    }
-
-    fn find_alt_screen_exit(&self, data: &[u8]) -> Option<usize> {
-        let pos1 = self.alt_screen_exit_finder.find(data);
-        let pos2 = self.alt_screen_exit_legacy_finder.find(data);
-        match (pos1, pos2) {
-            (Some(a), Some(b)) => Some(a.min(b)),
-            (Some(a), None) => Some(a),
-            (None, Some(b)) => Some(b),
-            (None, None) => None,
+        env.push(("CLAUDE_CHILL_SESSION", self.id.clone()));
+        if let Some(control) = &self.control {
+            env.push(("CLAUDE_CHILL_CONTROL", control.path().display().to_string()));
+        }
+        if let Some(idle) = self.core.idle_time(Instant::now()) {
+            env.push(("CLAUDE_CHILL_IDLE_MS", idle.as_millis().to_string()));
+        }
+        if let Some(activity) = self.core.activity() {
+            env.push(("CLAUDE_CHILL_STATE", activity.description().to_string()));
+        }
+        let lines = self.hooks.context_lines();
+        if lines > 0 {
+            env.push((
+                "CLAUDE_CHILL_LAST_LINES",
+                self.core.history_text(Some(lines)),
+            ));
+        }
+        if let Err(e) = self.hooks.run(hook, &env) {
+            debug!("run_hook: {:#}", e);
         }
     }
 
-    fn alt_screen_enter_len(&self, data: &[u8]) -> usize {
-        if data.starts_with(ALT_SCREEN_ENTER) {
-            ALT_SCREEN_ENTER.len()
+    /// Size of whichever terminal is attached.
+    fn terminal_size(&self) -> Option<Winsize> {
+        if self.local {
+            get_terminal_size().ok()
         } else {
-            ALT_SCREEN_ENTER_LEGACY.len()
+            self.client.as_ref().map(|client| client.winsize)
         }
     }
 
-    fn alt_screen_exit_len(&self, data: &[u8]) -> usize {
-        if data.starts_with(ALT_SCREEN_EXIT) {
-            ALT_SCREEN_EXIT.len()
-        } else {
-            ALT_SCREEN_EXIT_LEGACY.len()
+    fn forward_winsize(&mut self) {
+        if self.local
+            && let Ok(winsize) = get_terminal_size()
+        {
+            self.resize(winsize);
         }
     }
 
-    fn flush_sync_block_to_history(&mut self) {
-        let has_clear_screen = self.clear_screen_finder.find(&self.sync_buffer).is_some();
-        let has_cursor_home = self.cursor_home_finder.find(&self.sync_buffer).is_some();
-        let is_full_redraw = has_clear_screen && has_cursor_home;
-
-        debug!(
-            "flush_sync_block: len={} full_redraw={}",
-            self.sync_buffer.len(),
-            is_full_redraw
-        );
-
-        if is_full_redraw {
-            debug!("CLEARING HISTORY");
-            self.history.clear();
-            // Re-seed with clear screen after clearing
-            self.history.push_bytes(CLEAR_SCREEN);
-            self.history.push_bytes(CURSOR_HOME);
+    fn resize(&mut self, winsize: Winsize) {
+        debug!("resize: rows={} cols={}", winsize.ws_row, winsize.ws_col);
+        self.core.resize(winsize.ws_row, winsize.ws_col);
+        record_resize(&mut self.terminal_recording, &winsize);
+        record_resize(&mut self.child_recording, &winsize);
+        // Forward to child process
+        unsafe {
+            libc::ioctl(
+                self.pty_master.as_raw_fd(),
+                libc::TIOCSWINSZ as libc::c_ulong,
+                &winsize,
+            );
         }
-        self.push_to_history(&self.sync_buffer.clone());
-        self.sync_buffer.clear();
     }
 
-    /// Push data to history, filtering out terminal query sequences that would
-    /// cause the terminal to respond when replayed.
-    fn push_to_history(&mut self, data: &[u8]) {
-        let filtered = self.history_filter.filter(data);
-        self.history.push_bytes(&filtered);
+    /// The user pressed the detach key in whichever terminal is attached.
+    fn detach(&mut self) -> Result<()> {
+        if self.local {
+            self.detach_local()
+        } else {
+            self.release_client(ServerMessage::Detached);
+            Ok(())
+        }
     }
 
-    fn flush_pending_vt_render<F: AsFd>(&mut self, stdout_fd: &F) -> Result<()> {
-        if !self.vt_render_pending || self.in_lookback_mode || self.in_alternate_screen {
+    /// Give the terminal back to the shell and carry on in the background: a
+    /// forked copy keeps the session while the original process exits.
+    fn detach_local(&mut self) -> Result<()> {
+        let Some(session) = self.session.as_ref() else {
             return Ok(());
-        }
-
-        let elapsed = self
-            .last_output_time
-            .map(|t| t.elapsed())
-            .unwrap_or(Duration::MAX);
-
-        // Wait longer if in sync block (more data likely coming)
-        let delay = if self.in_sync_block {
-            Duration::from_millis(SYNC_BLOCK_DELAY_MS)
-        } else {
-            Duration::from_millis(RENDER_DELAY_MS)
         };
-
-        if elapsed >= delay {
-            self.render_vt_screen(stdout_fd)?;
+        let id = session.id().to_string();
+        debug!("detach_local: session={}", id);
+
+        self.core.release_terminal();
+        let mut output = self.core.take_terminal_output();
+        output.extend_from_slice(
+            format!(
+                "\r\n[detached from session {}, reattach with: claude-chill attach {}]\r\n",
+                id, id
+            )
+            .as_bytes(),
+        );
+        write_all(&io::stdout(), &output)?;
+        if let Some(termios) = self.original_termios.take() {
+            let _ = tcsetattr(io::stdin(), SetArg::TCSANOW, &termios);
         }
 
+        // The shell waits for the original process, which exits once told
+        let _ = setsid();
+        release_stdio()?;
+        if let Some(stand_in) = self.stand_in.take() {
+            write_all(&stand_in, b"d")?;
+        }
+        self.local = false;
         Ok(())
     }
 
-    fn time_until_render(&self) -> Option<Duration> {
-        if !self.vt_render_pending || self.in_lookback_mode || self.in_alternate_screen {
-            return None;
+    /// The terminal went away without detaching, e.g. a dropped SSH
+    /// connection. Keep the session running for `attach`.
+    fn hang_up(&mut self) {
+        debug!("hang_up: terminal lost, detaching");
+        self.original_termios = None;
+        self.local = false;
+        if let Err(e) = release_stdio() {
+            debug!("hang_up: {:#}", e);
         }
+    }
 
-        let elapsed = self
-            .last_output_time
-            .map(|t| t.elapsed())
-            .unwrap_or(Duration::MAX);
-
-        let delay = if self.in_sync_block {
-            Duration::from_millis(SYNC_BLOCK_DELAY_MS)
-        } else {
-            Duration::from_millis(RENDER_DELAY_MS)
+    fn accept_client(&mut self) {
+        let Some(session) = self.session.as_ref() else {
+            return;
         };
-
-        if elapsed >= delay {
-            Some(Duration::ZERO)
-        } else {
-            Some(delay - elapsed)
+        match session.accept() {
+            Ok(Some(connection)) => self.pending.push(connection),
+            Ok(None) => {}
+            Err(e) => debug!("accept_client: {:#}", e),
         }
     }
 
-    fn render_vt_screen<F: AsFd>(&mut self, stdout_fd: &F) -> Result<()> {
-        let is_diff = self.vt_prev_screen.is_some();
-        self.output_buffer.clear();
-        self.output_buffer.extend_from_slice(SYNC_START);
-
-        match &self.vt_prev_screen {
-            Some(prev) => {
-                // Diff-based render: only send changes
-                self.output_buffer
-                    .extend_from_slice(&self.vt_parser.screen().contents_diff(prev));
+    /// Read from a connection that hasn't attached yet.
+    fn serve_pending(&mut self, index: usize) -> Result<()> {
+        let mut connection = self.pending.swap_remove(index);
+        let messages = match connection.receive() {
+            Ok(Some(messages)) => messages,
+            // Closed without attaching, e.g. `claude-chill list` checking
+            // the session is alive
+            Ok(None) => return Ok(()),
+            Err(e) => {
+                debug!("serve_pending: dropping connection: {:#}", e);
+                return Ok(());
             }
-            None => {
-                // First render: full screen
-                self.output_buffer
-                    .extend_from_slice(&self.vt_parser.screen().contents_formatted());
+        };
+        let mut messages = messages.into_iter();
+        match messages.next() {
+            None => self.pending.push(connection),
+            Some(ClientMessage::Attach { rows, cols }) => {
+                self.attach_client(connection, rows, cols)?;
+                self.handle_client_messages(messages)?;
             }
+            Some(ClientMessage::Watch) => self.add_viewer(connection),
+            Some(message) => debug!("serve_pending: expected attach, got {:?}", message),
         }
-
-        self.output_buffer
-            .extend_from_slice(&self.vt_parser.screen().cursor_state_formatted());
-        self.output_buffer.extend_from_slice(SYNC_END);
-
-        debug!(
-            "render_vt_screen: diff={} output_len={}\n",
-            is_diff,
-            self.output_buffer.len()
-        );
-        write_all(stdout_fd, &self.output_buffer)?;
-
-        // Store current screen for next diff
-        self.vt_prev_screen = Some(self.vt_parser.screen().clone());
-        self.vt_render_pending = false;
-        self.last_render_time = Some(Instant::now());
         Ok(())
     }
 
-    fn check_auto_lookback<F: AsFd>(&mut self, stdout_fd: &F) -> Result<()> {
-        if self.auto_lookback_timeout.is_zero() {
+    fn attach_client(&mut self, connection: Connection, rows: u16, cols: u16) -> Result<()> {
+        if self.local {
+            connection.close_with(&ServerMessage::Refused(
+                "the session is attached to a terminal, detach it first".to_string(),
+            ));
             return Ok(());
         }
-        if self.in_lookback_mode || self.in_alternate_screen {
-            return Ok(());
+        debug!("attach_client: rows={} cols={}", rows, cols);
+
+        // The newest client wins, like reattaching from another machine
+        self.flush()?;
+        self.release_client(ServerMessage::Detached);
+        let winsize = client_winsize(rows, cols);
+        self.client = Some(AttachedClient {
+            connection,
+            winsize,
+        });
+        self.resize(winsize);
+        self.core.full_render();
+        self.flush()
+    }
+
+    /// Hand the client's terminal back and disconnect it with `message`.
+    fn release_client(&mut self, message: ServerMessage) {
+        let Some(mut client) = self.client.take() else {
+            return;
+        };
+        self.core.release_terminal();
+        let output = self.core.take_terminal_output();
+        if client
+            .connection
+            .send(&ServerMessage::Output(output))
+            .is_ok()
+        {
+            client.connection.close_with(&message);
         }
-        let Some(render_time) = self.last_render_time else {
+    }
+
+    fn serve_client(&mut self, revents: PollFlags) -> Result<()> {
+        let Some(client) = self.client.as_mut() else {
             return Ok(());
         };
-        if render_time.elapsed() < self.auto_lookback_timeout {
+        if revents.contains(PollFlags::POLLOUT)
+            && let Err(e) = client.connection.flush()
+        {
+            debug!("serve_client: dropping client: {:#}", e);
+            self.client = None;
             return Ok(());
         }
-        self.dump_history(stdout_fd)?;
-        self.last_render_time = None;
-        Ok(())
+        match client.connection.receive() {
+            Ok(Some(messages)) => self.handle_client_messages(messages.into_iter()),
+            Ok(None) => {
+                debug!("serve_client: client disconnected");
+                self.client = None;
+                Ok(())
+            }
+            Err(e) => {
+                debug!("serve_client: dropping client: {:#}", e);
+                self.client = None;
+                Ok(())
+            }
+        }
     }
 
-    fn dump_history<F: AsFd>(&mut self, stdout_fd: &F) -> Result<()> {
-        debug!(
-            "dump_history: history_bytes={} lines={}",
-            self.history.total_bytes(),
-            self.history.line_count()
-        );
-        self.output_buffer.clear();
-        self.history.append_all(&mut self.output_buffer);
-
-        // Filter out bell characters to prevent audible alerts on replay
-        self.output_buffer.retain(|&b| b != 0x07);
-
-        write_all(stdout_fd, CLEAR_SCREEN)?;
-        write_all(stdout_fd, CURSOR_HOME)?;
-        write_all(stdout_fd, &self.output_buffer)?;
-
-        // Force full VT render on next output since terminal now shows history
-        self.vt_prev_screen = None;
+    fn handle_client_messages(
+        &mut self,
+        messages: impl Iterator<Item = ClientMessage>,
+    ) -> Result<()> {
+        for message in messages {
+            if self.client.is_none() {
+                // Detached by an earlier message
+                break;
+            }
+            match message {
+                ClientMessage::Input(data) => {
+                    self.core.input(&data, Instant::now());
+                    self.flush()?;
+                }
+                ClientMessage::Resize { rows, cols } => {
+                    let winsize = client_winsize(rows, cols);
+                    if let Some(client) = self.client.as_mut() {
+                        client.winsize = winsize;
+                    }
+                    self.resize(winsize);
+                }
+                ClientMessage::Attach { .. } | ClientMessage::Watch => {}
+            }
+        }
         Ok(())
     }
 
-    fn process_input<F: AsFd>(&mut self, data: &[u8], stdout_fd: &F) -> Result<()> {
-        if self.in_alternate_screen {
-            return write_all(&self.pty_master, data);
-        }
+    fn add_viewer(&mut self, connection: Connection) {
+        debug!("add_viewer: viewers={}", self.viewers.len() + 1);
+        self.viewers.push(Viewer {
+            connection,
+            mirror: ScreenMirror::default(),
+        });
+        self.update_viewers();
+    }
 
-        for &byte in data {
-            if self.in_lookback_mode && byte == 0x03 {
-                self.lookback_input_buffer.clear();
-                self.exit_lookback_mode(stdout_fd)?;
-                continue;
+    /// Send each viewer whatever changed on the screen since its last frame.
+    fn update_viewers(&mut self) {
+        let screen = self.core.screen();
+        self.viewers.retain_mut(|viewer| {
+            let output = viewer.mirror.render(screen);
+            if output.is_empty() {
+                return true;
+            }
+            match viewer.connection.send(&ServerMessage::Output(output)) {
+                Ok(()) => true,
+                Err(e) => {
+                    debug!("update_viewers: dropping viewer: {:#}", e);
+                    false
+                }
             }
+        });
+    }
 
-            let lookback_action = self.check_sequence_match(
-                byte,
-                &mut self.lookback_input_buffer.clone(),
-                &self.config.lookback_sequence.clone(),
-            );
+    /// Viewers only read, so anything they send is dropped; this just notices
+    /// when they leave.
+    fn serve_viewer(&mut self, fd: RawFd, revents: PollFlags) {
+        let Some(index) = self
+            .viewers
+            .iter()
+            .position(|viewer| viewer.connection.stream().as_raw_fd() == fd)
+        else {
+            return;
+        };
+        let connection = &mut self.viewers[index].connection;
+        let flushed = if revents.contains(PollFlags::POLLOUT) {
+            connection.flush()
+        } else {
+            Ok(())
+        };
+        let gone = match flushed.and_then(|()| connection.receive()) {
+            Ok(Some(_)) => false,
+            Ok(None) => {
+                debug!("serve_viewer: viewer left");
+                true
+            }
+            Err(e) => {
+                debug!("serve_viewer: dropping viewer: {:#}", e);
+                true
+            }
+        };
+        if gone {
+            self.viewers.swap_remove(index);
+        }
+    }
 
-            self.lookback_input_buffer.push(byte);
+    fn accept_controller(&mut self) {
+        let Some(control) = self.control.as_ref() else {
+            return;
+        };
+        match control.accept() {
+            Ok(Some(controller)) => self.controllers.push(controller),
+            Ok(None) => {}
+            Err(e) => debug!("accept_controller: {:#}", e),
+        }
+    }
 
-            if self.lookback_input_buffer.len() > self.config.lookback_sequence.len() {
-                let excess = self.lookback_input_buffer.len() - self.config.lookback_sequence.len();
-                self.lookback_input_buffer.drain(..excess);
+    fn serve_controller(&mut self, index: usize, revents: PollFlags) -> Result<()> {
+        let mut controller = self.controllers.swap_remove(index);
+        if revents.contains(PollFlags::POLLOUT)
+            && let Err(e) = controller.flush()
+        {
+            debug!("serve_controller: dropping controller: {:#}", e);
+            return Ok(());
+        }
+        let lines = match controller.receive() {
+            Ok(Some(lines)) => lines,
+            Ok(None) => {
+                controller.close();
+                return Ok(());
             }
+            Err(e) => {
+                debug!("serve_controller: dropping controller: {:#}", e);
+                return Ok(());
+            }
+        };
+        for line in lines {
+            let (id, request) = control::parse_request(&line);
+            let result = match request {
+                Ok(request) => self.control(request),
+                Err(e) => Err(e),
+            };
+            if let Err(e) = controller.send(&control::format_reply(id.as_ref(), &result)) {
+                debug!("serve_controller: dropping controller: {:#}", e);
+                return Ok(());
+            }
+        }
+        self.controllers.push(controller);
+        Ok(())
+    }
 
-            match lookback_action {
-                SequenceMatch::Complete => {
-                    self.lookback_input_buffer.clear();
-                    if self.in_lookback_mode {
-                        self.exit_lookback_mode(stdout_fd)?;
+    /// Carry out a control socket request.
+    fn control(&mut self, request: Request) -> Result<Response> {
+        debug!("control: {:?}", request);
+        match request {
+            Request::GetState {} => {
+                let (rows, cols) = self.core.screen().size();
+                let lookback = self.core.in_lookback_mode();
+                let alt_screen = self.core.in_alternate_screen();
+                Ok(Response::State(State {
+                    session: self.id.clone(),
+                    mode: if alt_screen {
+                        Mode::AltScreen
+                    } else if lookback {
+                        Mode::Lookback
                     } else {
-                        self.enter_lookback_mode()?;
+                        Mode::Normal
+                    },
+                    alt_screen,
+                    lookback,
+                    activity: self.core.activity(),
+                    idle_ms: self
+                        .core
+                        .idle_time(Instant::now())
+                        .map(|idle| idle.as_millis() as u64),
+                    rows,
+                    cols,
+                    history_lines: self.core.stats().history_lines,
+                    attached: if self.local {
+                        Attached::Terminal
+                    } else if self.client.is_some() {
+                        Attached::Client
+                    } else {
+                        Attached::None
+                    },
+                    viewers: self.viewers.len(),
+                }))
+            }
+            Request::DumpHistory { format, lines } => {
+                let history = match format {
+                    TextFormat::Ansi => {
+                        String::from_utf8_lossy(&self.core.history(lines)).into_owned()
                     }
-                    continue;
-                }
-                SequenceMatch::Partial => {}
-                SequenceMatch::None => {
-                    if !self
-                        .config
-                        .lookback_sequence
-                        .starts_with(&self.lookback_input_buffer)
-                    {
-                        self.lookback_input_buffer.clear();
+                    TextFormat::Text => self.core.history_text(lines),
+                };
+                Ok(Response::History { history })
+            }
+            Request::GetScreenText { format } => {
+                let screen = self.core.screen();
+                let text = match format {
+                    TextFormat::Text => screen.contents(),
+                    TextFormat::Ansi => {
+                        String::from_utf8_lossy(&screen.contents_formatted()).into_owned()
                     }
-                }
+                };
+                let (row, col) = screen.cursor_position();
+                Ok(Response::Screen {
+                    text,
+                    cursor: [row, col],
+                })
             }
-
-            if lookback_action == SequenceMatch::None && !self.in_lookback_mode {
-                write_all(&self.pty_master, &[byte])?;
+            Request::ToggleLookback {} => {
+                anyhow::ensure!(
+                    !self.core.in_alternate_screen(),
+                    "lookback is not available while the alternate screen is up"
+                );
+                self.core.toggle_lookback();
+                self.flush()?;
+                Ok(Response::Lookback {
+                    lookback: self.core.in_lookback_mode(),
+                })
+            }
+            Request::SendInput { data } => {
+                self.core.send_input(data.as_bytes());
+                self.flush()?;
+                Ok(Response::Done {})
+            }
+            Request::Resize { rows, cols } => {
+                anyhow::ensure!(rows > 0 && cols > 0, "rows and cols must be positive");
+                self.resize(client_winsize(rows, cols));
+                self.core.redraw();
+                self.flush()?;
+                Ok(Response::Done {})
             }
         }
-        Ok(())
     }
 
-    fn check_sequence_match(
-        &self,
-        byte: u8,
-        buffer: &mut Vec<u8>,
-        sequence: &[u8],
-    ) -> SequenceMatch {
-        buffer.push(byte);
-        if buffer.len() > sequence.len() {
-            let excess = buffer.len() - sequence.len();
-            buffer.drain(..excess);
-        }
-        if buffer.as_slice() == sequence {
-            SequenceMatch::Complete
-        } else if sequence.starts_with(buffer) {
-            SequenceMatch::Partial
-        } else {
-            SequenceMatch::None
+    /// The command's PTY hung up: finish its output and either restart it
+    /// after a countdown or set the exit code to finish with.
+    fn child_exited(&mut self) -> Result<()> {
+        self.core.finish();
+        self.flush()?;
+        let exit_code = self.wait_child()?;
+        let restart = self.restart.enabled
+            && exit_code != 0
+            && !self.stopping
+            && self.restarts < self.restart.max_restarts;
+        if !restart {
+            self.exit_code = Some(exit_code);
+            return Ok(());
         }
-    }
-
-    fn enter_lookback_mode(&mut self) -> Result<()> {
-        debug!(
-            "enter_lookback_mode: history_bytes={} lines={}",
-            self.history.total_bytes(),
-            self.history.line_count()
-        );
-        self.in_lookback_mode = true;
-        self.lookback_cache.clear();
-        self.vt_render_pending = false;
-
-        self.output_buffer.clear();
-        self.history.append_all(&mut self.output_buffer);
-
-        // Filter out bell characters to prevent audible alerts on replay
-        self.output_buffer.retain(|&b| b != 0x07);
-
+        let countdown = Duration::from_millis(self.restart.countdown_ms);
         debug!(
-            "enter_lookback_mode: output_buffer_len={}",
-            self.output_buffer.len()
+            "child_exited: exit_code={} restarting in {:?}",
+            exit_code, countdown
         );
-
-        let stdout_fd = io::stdout();
-        write_all(&stdout_fd, CLEAR_SCREEN)?;
-        write_all(&stdout_fd, CURSOR_HOME)?;
-        write_all(&stdout_fd, &self.output_buffer)?;
-
-        let exit_msg = format!(
-            "\r\n\x1b[7m--- LOOKBACK MODE: press {} or Ctrl+C to exit ---\x1b[0m\r\n",
-            self.config.lookback_key
-        );
-        write_all(&stdout_fd, exit_msg.as_bytes())?;
-
-        Ok(())
+        self.pending_restart = Some(PendingRestart {
+            at: Instant::now() + countdown,
+            exit_code,
+        });
+        self.core.show_banner(&format!(
+            "claude-chill: exited with status {}, restarting in {}s (press any key to cancel)",
+            exit_code,
+            self.restart.countdown_ms.div_ceil(1000)
+        ));
+        self.flush()
     }
 
-    fn exit_lookback_mode<F: AsFd>(&mut self, stdout_fd: &F) -> Result<()> {
-        debug!(
-            "exit_lookback_mode: cached_len={}",
-            self.lookback_cache.len()
-        );
-        self.in_lookback_mode = false;
-
-        // Process cached output through VT to update screen state
-        let cached = std::mem::take(&mut self.lookback_cache);
-        if !cached.is_empty() {
-            debug!(
-                "exit_lookback_mode: processing {} cached bytes",
-                cached.len()
-            );
-            self.process_output(&cached, stdout_fd)?;
+    /// Give up on restarting the command and finish with its exit code.
+    fn cancel_restart(&mut self) {
+        if let Some(restart) = self.pending_restart.take() {
+            debug!("cancel_restart: exit_code={}", restart.exit_code);
+            self.exit_code = Some(restart.exit_code);
         }
-
-        // Reset sync block state
-        self.in_sync_block = false;
-        self.sync_buffer.clear();
-
-        self.forward_winsize()?;
-
-        // Force full render since terminal was showing history
-        debug!("exit_lookback_mode: rendering VT screen");
-        self.vt_prev_screen = None;
-        self.render_vt_screen(stdout_fd)?;
-
-        Ok(())
     }
 
-    fn forward_winsize(&mut self) -> Result<()> {
-        if let Ok(winsize) = get_terminal_size() {
-            debug!(
-                "forward_winsize: rows={} cols={}",
-                winsize.ws_row, winsize.ws_col
-            );
-            // Resize VT emulator
-            self.vt_parser
-                .screen_mut()
-                .set_size(winsize.ws_row, winsize.ws_col);
-            // Force full render on next frame since size changed
-            self.vt_prev_screen = None;
-            // Forward to child process
-            unsafe {
-                libc::ioctl(
-                    self.pty_master.as_raw_fd(),
-                    libc::TIOCSWINSZ as libc::c_ulong,
-                    &winsize,
-                );
-            }
+    /// Start the command again on a new PTY, carrying on the same session.
+    fn restart_child(&mut self) -> Result<()> {
+        self.pending_restart = None;
+        self.restarts += 1;
+        let spec = ChildSpec {
+            args: self
+                .restart
+                .args
+                .clone()
+                .unwrap_or_else(|| self.child_spec.args.clone()),
+            ..self.child_spec.clone()
+        };
+        let (rows, cols) = self.core.screen().size();
+        let winsize = client_winsize(rows, cols);
+        let (pty_master, child) = spawn_child(&spec, &winsize)?;
+        debug!(
+            "restart_child: restart {} pid={} args={:?}",
+            self.restarts,
+            child.id(),
+            spec.args
+        );
+        self.pty_master = pty_master;
+        self.child = child;
+        if self.cpu_monitor.is_some() {
+            self.cpu_monitor = Some(CpuMonitor::new(self.child.id()));
         }
-        Ok(())
+        let command_line = std::iter::once(spec.command.as_str())
+            .chain(spec.args.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.core.child_restarted(&command_line);
+        self.flush()
     }
 
     fn forward_signal(&self, signal: Signal) {
+        if self.pending_restart.is_some() {
+            // The command is gone and its pid may belong to someone else
+            return;
+        }
         let pid = Pid::from_raw(self.child.id() as i32);
         let _ = kill(pid, signal);
     }
 
     fn wait_child(&mut self) -> Result<i32> {
-        match self.child.wait() {
-            Ok(status) => Ok(exit_code_from_status(status)),
-            Err(e) => anyhow::bail!("wait failed: {}", e),
-        }
+        let status = self.child.wait().context("wait failed")?;
+        Ok(exit_code_from_status(status))
     }
 }
 
 impl Drop for Proxy {
     fn drop(&mut self) {
+        if self.local && self.core.lookback_mouse_active() {
+            let _ = write_all(&io::stdout(), LOOKBACK_MOUSE_DISABLE);
+        }
         if let Some(ref termios) = self.original_termios {
             let _ = tcsetattr(io::stdin(), SetArg::TCSANOW, termios);
         }
     }
 }
 
-fn get_terminal_size() -> Result<Winsize> {
+/// Fork the process that runs the session, returning in it with its link
+/// to the original. The original stays behind as the shell's job, passing
+/// signals on, and exits along with the session: with its exit code, or
+/// with 0 once it detaches.
+fn fork_session() -> Result<OwnedFd> {
+    let (session_end, original_end) = UnixStream::pair().context("socketpair failed")?;
+    match unsafe { fork() }.context("fork failed")? {
+        ForkResult::Parent { child } => {
+            drop(session_end);
+            stand_in(original_end.into(), child)
+        }
+        ForkResult::Child => {
+            drop(original_end);
+            Ok(session_end.into())
+        }
+    }
+}
+
+/// The original process was killed outright, handing the terminal back to
+/// the shell. Go down with it, as the whole proxy used to, taking the
+/// command with us by closing its PTY.
+fn stand_in_killed() -> ! {
+    debug!("stand_in_killed: exiting");
+    std::process::exit(1)
+}
+
+/// Wait in the original process for the session to detach or exit.
+fn stand_in(notice: OwnedFd, session: Pid) -> ! {
+    SESSION_PID.store(session.as_raw(), Ordering::SeqCst);
+    for signal in [Signal::SIGINT, Signal::SIGTERM, Signal::SIGHUP] {
+        let _ = setup_signal_handler(signal, forward_to_session);
+    }
+    let mut byte = [0u8; 1];
+    loop {
+        match read(&notice, &mut byte) {
+            Ok(0) => break,
+            Ok(_) => std::process::exit(0),
+            Err(Errno::EINTR) => continue,
+            Err(_) => break,
+        }
+    }
+    loop {
+        match waitpid(session, None) {
+            Ok(WaitStatus::Exited(_, code)) => std::process::exit(code),
+            Ok(WaitStatus::Signaled(_, signal, _)) => std::process::exit(128 + signal as i32),
+            Ok(_) | Err(Errno::EINTR) => continue,
+            Err(_) => std::process::exit(1),
+        }
+    }
+}
+
+/// Start the command on a new PTY of size `winsize`, returning the PTY's
+/// master side.
+fn spawn_child(spec: &ChildSpec, winsize: &Winsize) -> Result<(OwnedFd, Child)> {
+    let pty = openpty(winsize, None).context("openpty failed")?;
+    let slave_fd = pty.slave.as_raw_fd();
+
+    let mut cmd = Command::new(&spec.command);
+    cmd.args(&spec.args);
+    for name in &spec.env.unset {
+        cmd.env_remove(name);
+    }
+    cmd.envs(&spec.env.set);
+    if let Some(cwd) = &spec.cwd {
+        cmd.current_dir(cwd);
+    }
+    // So tools run inside the session can tell, and find their way back to
+    // it
+    cmd.env("CLAUDE_CHILL", "1");
+    if let Some(id) = &spec.session_id {
+        cmd.env("CLAUDE_CHILL_SESSION", id);
+    }
+    if let Some(path) = &spec.control_path {
+        cmd.env("CLAUDE_CHILL_CONTROL", path);
+    }
+    let child = unsafe {
+        cmd.pre_exec(move || {
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::ioctl(slave_fd, libc::TIOCSCTTY as libc::c_ulong, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::dup2(slave_fd, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::dup2(slave_fd, 1) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::dup2(slave_fd, 2) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            if slave_fd > 2 {
+                libc::close(slave_fd);
+            }
+            Ok(())
+        })
+        .spawn()
+        .with_context(|| format!("failed to run {}", spec.command))?
+    };
+
+    drop(pty.slave);
+    set_nonblocking(&pty.master)?;
+    Ok((pty.master, child))
+}
+
+pub(crate) fn get_terminal_size() -> Result<Winsize> {
     let mut ws: Winsize = unsafe { std::mem::zeroed() };
     let ret = unsafe {
         libc::ioctl(
@@ -839,7 +1277,10 @@ fn setup_raw_mode() -> Result<Option<Termios>> {
     Ok(Some(original))
 }
 
-fn setup_signal_handler(signal: Signal, handler: extern "C" fn(libc::c_int)) -> Result<()> {
+pub(crate) fn setup_signal_handler(
+    signal: Signal,
+    handler: extern "C" fn(libc::c_int),
+) -> Result<()> {
     let action = SigAction::new(
         SigHandler::Handler(handler),
         SaFlags::SA_RESTART,
@@ -864,7 +1305,7 @@ fn set_nonblocking<Fd: AsFd>(fd: &Fd) -> Result<()> {
     Ok(())
 }
 
-fn write_all<F: AsFd>(fd: &F, data: &[u8]) -> Result<()> {
+pub(crate) fn write_all<F: AsFd>(fd: &F, data: &[u8]) -> Result<()> {
     let mut written = 0;
     while written < data.len() {
         match write(fd, &data[written..]) {
@@ -876,6 +1317,55 @@ fn write_all<F: AsFd>(fd: &F, data: &[u8]) -> Result<()> {
     Ok(())
 }
 
+/// Point stdin, stdout and stderr at /dev/null so nothing touches a terminal
+/// that's gone or been given back.
+fn release_stdio() -> Result<()> {
+    let null = File::options()
+        .read(true)
+        .write(true)
+        .open("/dev/null")
+        .context("failed to open /dev/null")?;
+    for fd in 0..=2 {
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+            return Err(io::Error::last_os_error()).context("dup2 failed");
+        }
+    }
+    Ok(())
+}
+
+/// A client's terminal size, or the usual default if it couldn't tell.
+fn client_winsize(rows: u16, cols: u16) -> Winsize {
+    let (rows, cols) = if rows == 0 || cols == 0 {
+        (24, 80)
+    } else {
+        (rows, cols)
+    };
+    Winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+fn record_output(recording: &mut Option<AsciicastWriter>, data: &[u8]) {
+    if let Some(writer) = recording.as_mut()
+        && let Err(e) = writer.output(data)
+    {
+        debug!("record_output: disabling recording: {:#}", e);
+        *recording = None;
+    }
+}
+
+fn record_resize(recording: &mut Option<AsciicastWriter>, winsize: &Winsize) {
+    if let Some(writer) = recording.as_mut()
+        && let Err(e) = writer.resize(winsize.ws_col, winsize.ws_row)
+    {
+        debug!("record_resize: disabling recording: {:#}", e);
+        *recording = None;
+    }
+}
+
 fn nix_read<F: AsFd>(fd: &F, buf: &mut [u8]) -> Result<usize, Errno> {
     read(fd.as_fd(), buf)
 }
@@ -0,0 +1,1740 @@
+//! The proxy's state machine, free of file descriptors, signals and clocks.
+//!
+//! `ProxyCore` consumes child output, user input and timer ticks, each with
+//! the time it happened, and queues bytes for the terminal and for the PTY.
+//! The caller drains those queues and decides when to tick using
+//! `next_deadline`, so the same core runs behind a real PTY in `Proxy`,
+//! behind a recording in `replay`, and in tests with a fake clock.
+
+use crate::bell::{BellAction, BellScanner};
+use crate::config::{
+    BellConfig, EnvConfig, HooksConfig, NotificationsConfig, RestartConfig, WatchdogConfig,
+};
+use crate::detector::{self, ActivityState, Detector};
+use crate::escape_filter::TerminalQueryFilter;
+use crate::escape_sequences::{
+    ALT_SCREEN_ENTER, ALT_SCREEN_ENTER_LEGACY, ALT_SCREEN_EXIT, ALT_SCREEN_EXIT_LEGACY,
+    CLEAR_SCREEN, CURSOR_HOME, INPUT_MODES_RESET, LOOKBACK_MOUSE_DISABLE, LOOKBACK_MOUSE_ENABLE,
+    OUTPUT_BUFFER_CAPACITY, SYNC_BUFFER_CAPACITY, SYNC_END, SYNC_START, VISUAL_BELL_OFF,
+    VISUAL_BELL_ON,
+};
+use crate::input_decoder::{self, DecodedInput, InputDecoder, InputEvent};
+use crate::input_recorder::{InputAction, InputRecorder};
+use crate::key_parser::{KeyCode, KeyCombination, Modifiers};
+use crate::line_buffer::LineBuffer;
+use crate::lookback_view::{self, LookbackView};
+use crate::macros::InputMacro;
+use crate::mirror;
+use crate::mouse::{MouseButton, MouseEvent, MouseEventKind};
+use crate::notification::{self, NotificationStyle};
+use log::debug;
+use memchr::memmem;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const RENDER_DELAY_MS: u64 = 5;
+const SYNC_BLOCK_DELAY_MS: u64 = 50;
+const WHEEL_SCROLL_LINES: usize = 3;
+const INPUT_ESCAPE_TIMEOUT_MS: u64 = 20;
+const VISUAL_BELL_MS: u64 = 100;
+
+/// Sequences `process_output` looks for in the child's output.
+const OUTPUT_MARKERS: [&[u8]; 6] = [
+    SYNC_START,
+    SYNC_END,
+    ALT_SCREEN_ENTER,
+    ALT_SCREEN_EXIT,
+    ALT_SCREEN_ENTER_LEGACY,
+    ALT_SCREEN_EXIT_LEGACY,
+];
+
+/// Settings for a session. `ProxyCore` uses what shapes the screen and the
+/// input; the rest is for whatever drives it, like `Proxy`.
+#[derive(Clone)]
+pub struct ProxyConfig {
+    /// Environment changes for the command.
+    pub env: EnvConfig,
+    /// Working directory for the command, or ours.
+    pub cwd: Option<PathBuf>,
+    pub max_history_lines: usize,
+    pub lookback_key: String,
+    pub lookback_sequence: Vec<u8>,
+    pub auto_lookback_timeout_ms: u64,
+    pub lookback_mouse: bool,
+    /// Detaches the terminal from the session; empty to disable.
+    pub detach_sequence: Vec<u8>,
+    /// Quiet time after which the child counts as idle; 0 to never.
+    pub idle_timeout_ms: u64,
+    pub macros: Vec<InputMacro>,
+    pub hooks: HooksConfig,
+    pub notifications: NotificationsConfig,
+    pub bell: BellConfig,
+    pub watchdog: WatchdogConfig,
+    pub restart: RestartConfig,
+    /// Tell what the child is doing from its screen; none by default.
+    pub detectors: Vec<Detector>,
+    pub record_input: Option<PathBuf>,
+    /// asciicast recording of what the proxy writes to the terminal.
+    pub record: Option<PathBuf>,
+    /// asciicast recording of the child's raw output.
+    pub record_raw: Option<PathBuf>,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            env: EnvConfig::default(),
+            cwd: None,
+            max_history_lines: 100_000,
+            lookback_key: "[ctrl][6]".to_string(),
+            lookback_sequence: vec![0x1E],
+            auto_lookback_timeout_ms: 5000,
+            lookback_mouse: true,
+            detach_sequence: Vec::new(),
+            idle_timeout_ms: 5000,
+            macros: Vec::new(),
+            hooks: HooksConfig::default(),
+            notifications: NotificationsConfig::default(),
+            bell: BellConfig::default(),
+            watchdog: WatchdogConfig::default(),
+            restart: RestartConfig::default(),
+            detectors: Vec::new(),
+            record_input: None,
+            record: None,
+            record_raw: None,
+        }
+    }
+}
+
+/// Counters describing what the proxy did with the child's output.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ProxyStats {
+    /// Bytes read from the child.
+    pub output_bytes: u64,
+    /// Bytes written to the terminal.
+    pub terminal_bytes: u64,
+    pub renders: u64,
+    pub sync_blocks: u64,
+    /// Sync blocks that cleared the screen and reset history.
+    pub full_redraws: u64,
+    pub alt_screen_entries: u64,
+    /// Bells acted on, after rate limiting.
+    pub bells: u64,
+    pub history_lines: usize,
+    pub history_bytes: usize,
+}
+
+/// Something the session did that hooks can react to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionEvent {
+    /// The child has been quiet for `idle` after being busy for `active`
+    /// since the last key press.
+    Idle { idle: Duration, active: Duration },
+    /// The child rang the bell; OSC terminators don't count.
+    Bell,
+    /// The child entered (`true`) or left the alternate screen.
+    AltScreen(bool),
+}
+
+/// Bytes queued for the terminal.
+#[derive(Default)]
+struct TerminalOutput {
+    buffer: Vec<u8>,
+    bytes_written: u64,
+}
+
+impl TerminalOutput {
+    fn write(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+        self.bytes_written += data.len() as u64;
+    }
+}
+
+pub struct ProxyCore {
+    config: ProxyConfig,
+    history: LineBuffer,
+    history_filter: TerminalQueryFilter,
+    vt_parser: vt100::Parser,
+    vt_prev_screen: Option<vt100::Screen>,
+    /// Tail of the last read that may be the start of an output marker.
+    output_carry: Vec<u8>,
+    /// Time of the event being handled.
+    now: Instant,
+    last_output_time: Option<Instant>,
+    last_render_time: Option<Instant>,
+    /// Time of the child's last output, in any mode.
+    last_activity: Option<Instant>,
+    /// Start of the child's output since the last key press or idle report.
+    active_since: Option<Instant>,
+    auto_lookback_timeout: Duration,
+    idle_timeout: Duration,
+    /// Whether the current quiet spell has been reported.
+    idle_reported: bool,
+    bell_scanner: BellScanner,
+    /// Time of the last bell that wasn't rate-limited.
+    last_bell: Option<Instant>,
+    /// When to turn off the visual bell, while it is on.
+    visual_bell_until: Option<Instant>,
+    events: Vec<SessionEvent>,
+    sync_buffer: Vec<u8>,
+    in_sync_block: bool,
+    in_lookback_mode: bool,
+    in_alternate_screen: bool,
+    vt_render_pending: bool,
+    /// What the detectors made of the screen at the last render.
+    activity: Option<ActivityState>,
+    lookback_cache: Vec<u8>,
+    input_decoder: InputDecoder,
+    input_pending_since: Option<Instant>,
+    lookback_event: Option<InputEvent>,
+    detach_event: Option<InputEvent>,
+    macro_events: Vec<(InputEvent, Vec<u8>)>,
+    input_recorder: Option<InputRecorder>,
+    lookback_view: Option<LookbackView>,
+    output_buffer: Vec<u8>,
+    terminal: TerminalOutput,
+    pty_output: Vec<u8>,
+    winsize_sync_requested: bool,
+    detach_requested: bool,
+    stats: ProxyStats,
+    sync_start_finder: memmem::Finder<'static>,
+    sync_end_finder: memmem::Finder<'static>,
+    clear_screen_finder: memmem::Finder<'static>,
+    cursor_home_finder: memmem::Finder<'static>,
+    alt_screen_enter_finder: memmem::Finder<'static>,
+    alt_screen_exit_finder: memmem::Finder<'static>,
+    alt_screen_enter_legacy_finder: memmem::Finder<'static>,
+    alt_screen_exit_legacy_finder: memmem::Finder<'static>,
+}
+
+impl ProxyCore {
+    pub fn new(config: ProxyConfig, rows: u16, cols: u16, now: Instant) -> Self {
+        let vt_parser = vt100::Parser::new(rows, cols, 0);
+
+        // Seed history with clear screen so replay starts fresh
+        let mut history = LineBuffer::new(config.max_history_lines);
+        history.push_bytes(CLEAR_SCREEN);
+        history.push_bytes(CURSOR_HOME);
+
+        let auto_lookback_timeout = Duration::from_millis(config.auto_lookback_timeout_ms);
+        let idle_timeout = Duration::from_millis(config.idle_timeout_ms);
+
+        // Decode the lookback key once so input is matched as events rather
+        // than raw bytes
+        let lookback_event = input_decoder::decode_single(&config.lookback_sequence);
+        if lookback_event.is_none() {
+            debug!(
+                "ProxyCore::new: lookback sequence {:?} is not a single key",
+                config.lookback_sequence
+            );
+        }
+        let detach_event = if config.detach_sequence.is_empty() {
+            None
+        } else {
+            input_decoder::decode_single(&config.detach_sequence)
+        };
+        let macro_events = config
+            .macros
+            .iter()
+            .filter_map(|m| {
+                let event = input_decoder::decode_single(&m.sequence);
+                if event.is_none() {
+                    debug!("ProxyCore::new: macro key {} is not a single key", m.key);
+                }
+                Some((event?, m.payload.clone()))
+            })
+            .collect();
+
+        Self {
+            history,
+            history_filter: TerminalQueryFilter::new(),
+            config,
+            vt_parser,
+            vt_prev_screen: None,
+            output_carry: Vec::new(),
+            now,
+            last_output_time: None,
+            last_render_time: None,
+            last_activity: None,
+            active_since: None,
+            auto_lookback_timeout,
+            idle_timeout,
+            idle_reported: false,
+            bell_scanner: BellScanner::new(),
+            last_bell: None,
+            visual_bell_until: None,
+            events: Vec::new(),
+            sync_buffer: Vec::with_capacity(SYNC_BUFFER_CAPACITY),
+            in_sync_block: false,
+            in_lookback_mode: false,
+            in_alternate_screen: false,
+            vt_render_pending: false,
+            activity: None,
+            lookback_cache: Vec::new(),
+            input_decoder: InputDecoder::new(),
+            input_pending_since: None,
+            lookback_event,
+            detach_event,
+            macro_events,
+            input_recorder: None,
+            lookback_view: None,
+            output_buffer: Vec::with_capacity(OUTPUT_BUFFER_CAPACITY),
+            terminal: TerminalOutput::default(),
+            pty_output: Vec::new(),
+            winsize_sync_requested: false,
+            detach_requested: false,
+            stats: ProxyStats::default(),
+            sync_start_finder: memmem::Finder::new(SYNC_START),
+            sync_end_finder: memmem::Finder::new(SYNC_END),
+            clear_screen_finder: memmem::Finder::new(CLEAR_SCREEN),
+            cursor_home_finder: memmem::Finder::new(CURSOR_HOME),
+            alt_screen_enter_finder: memmem::Finder::new(ALT_SCREEN_ENTER),
+            alt_screen_exit_finder: memmem::Finder::new(ALT_SCREEN_EXIT),
+            alt_screen_enter_legacy_finder: memmem::Finder::new(ALT_SCREEN_ENTER_LEGACY),
+            alt_screen_exit_legacy_finder: memmem::Finder::new(ALT_SCREEN_EXIT_LEGACY),
+        }
+    }
+
+    /// Record every piece of input and what was done with it.
+    pub fn set_input_recorder(&mut self, recorder: InputRecorder) {
+        self.input_recorder = Some(recorder);
+    }
+
+    /// Handle bytes the child wrote to the PTY.
+    pub fn output(&mut self, data: &[u8], now: Instant) {
+        self.now = now;
+        self.stats.output_bytes += data.len() as u64;
+        if self.idle_reported || self.active_since.is_none() {
+            self.active_since = Some(now);
+        }
+        self.last_activity = Some(now);
+        self.idle_reported = false;
+
+        // Hold back a trailing partial marker so a read that ends partway
+        // through e.g. SYNC_START is still seen as one sequence
+        let joined;
+        let data = if self.output_carry.is_empty() {
+            data
+        } else {
+            let mut carry = std::mem::take(&mut self.output_carry);
+            carry.extend_from_slice(data);
+            joined = carry;
+            &joined
+        };
+        let (ready, partial) = data.split_at(data.len() - partial_marker_len(data));
+        self.output_carry.extend_from_slice(partial);
+
+        // Bells are handled here, where they are, and never reach the VT or
+        // history. None of the markers contain BEL, so splitting around them
+        // can't split a marker.
+        let mut start = 0;
+        for pos in self.bell_scanner.scan(ready) {
+            if pos > start {
+                self.process_output(&ready[start..pos]);
+            }
+            self.ring_bell();
+            start = pos + 1;
+        }
+        if start < ready.len() {
+            self.process_output(&ready[start..]);
+        }
+    }
+
+    /// Handle bytes the user typed.
+    pub fn input(&mut self, data: &[u8], now: Instant) {
+        self.now = now;
+        self.active_since = None;
+        if self.in_alternate_screen {
+            // Keys go to the child raw here, so only a read that is exactly
+            // the detach key counts
+            if !self.config.detach_sequence.is_empty() && data == self.config.detach_sequence {
+                self.record_input(data, None, InputAction::Detach, None);
+                self.detach_requested = true;
+                return;
+            }
+            let pending = self.input_decoder.take_pending();
+            if !pending.is_empty() {
+                self.record_input(&pending, None, InputAction::Forward, None);
+                self.pty_output.extend_from_slice(&pending);
+            }
+            self.input_pending_since = None;
+            self.record_input(data, None, InputAction::Forward, None);
+            self.pty_output.extend_from_slice(data);
+            return;
+        }
+
+        let decoded = self.input_decoder.feed(data);
+        self.input_pending_since = if self.input_decoder.has_pending() {
+            self.input_pending_since.or(Some(now))
+        } else {
+            None
+        };
+        for input in decoded {
+            self.handle_input_event(input);
+        }
+    }
+
+    /// Run whatever timers are due: ambiguous input, pending renders,
+    /// auto-lookback and idle reports.
+    pub fn tick(&mut self, now: Instant) {
+        self.now = now;
+        self.flush_input_decoder();
+        self.flush_pending_vt_render();
+        self.check_auto_lookback();
+        self.check_idle();
+        self.check_visual_bell();
+    }
+
+    /// When `tick` next has something to do, if anything is waiting.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        let input_flush = self
+            .input_pending_since
+            .map(|since| since + Duration::from_millis(INPUT_ESCAPE_TIMEOUT_MS));
+        let render = self
+            .render_delay()
+            .map(|delay| self.last_output_time.unwrap_or(self.now) + delay);
+        let auto_lookback = self.auto_lookback_deadline();
+        let idle = self.idle_deadline();
+        [
+            input_flush,
+            render,
+            auto_lookback,
+            idle,
+            self.visual_bell_until,
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    /// Render anything still pending, e.g. when the child has exited.
+    pub fn finish(&mut self) {
+        let carry = std::mem::take(&mut self.output_carry);
+        if !carry.is_empty() {
+            self.process_output(&carry);
+        }
+        if self.vt_render_pending {
+            self.render_vt_screen();
+        }
+    }
+
+    /// Render the whole screen from scratch rather than as a diff.
+    pub fn redraw(&mut self) {
+        self.vt_prev_screen = None;
+        self.render_vt_screen();
+    }
+
+    /// Show `text` in reverse video across the top row until the next full
+    /// render, without moving the cursor.
+    pub fn show_banner(&mut self, text: &str) {
+        let cols = self.vt_parser.screen().size().1 as usize;
+        let text: String = text.chars().take(cols).collect();
+        let banner = format!("\x1b7\x1b[H\x1b[7m{}\x1b[K\x1b[0m\x1b8", text);
+        self.terminal.write(banner.as_bytes());
+        // The screen no longer matches what was rendered
+        self.vt_prev_screen = None;
+    }
+
+    /// A new generation of the child has started in place of one that
+    /// exited. Put the screen back the way a fresh child expects it and mark
+    /// the restart in history, which carries on.
+    pub fn child_restarted(&mut self, command_line: &str) {
+        if self.in_sync_block {
+            self.flush_sync_block_to_history();
+            self.in_sync_block = false;
+        }
+        self.output_carry.clear();
+        self.bell_scanner = BellScanner::new();
+        self.activity = None;
+        if self.in_alternate_screen {
+            self.process_output(ALT_SCREEN_EXIT);
+        }
+        self.vt_parser.process(INPUT_MODES_RESET);
+        if !self.in_lookback_mode {
+            // Lookback mode puts the child's modes back when it ends
+            self.terminal.write(INPUT_MODES_RESET);
+        }
+        let marker = format!("\r\n\x1b[7m--- restarted: {} ---\x1b[0m\r\n", command_line);
+        self.process_output(marker.as_bytes());
+    }
+
+    /// Bring a terminal that has never seen this session up to date: leave
+    /// lookback mode, switch screens and input modes to match the child and
+    /// draw everything.
+    pub fn full_render(&mut self) {
+        if self.in_lookback_mode {
+            self.exit_lookback_mode();
+        }
+        if self.in_alternate_screen {
+            self.terminal.write(ALT_SCREEN_ENTER);
+        }
+        self.terminal
+            .write(&self.vt_parser.screen().input_mode_formatted());
+        self.redraw();
+    }
+
+    /// Hand a terminal back before detaching it: leave lookback mode and the
+    /// alternate screen and turn off the input modes the child enabled.
+    pub fn release_terminal(&mut self) {
+        if self.in_lookback_mode {
+            self.exit_lookback_mode();
+        }
+        if self.visual_bell_until.take().is_some() {
+            self.terminal.write(VISUAL_BELL_OFF);
+        }
+        self.terminal.write(INPUT_MODES_RESET);
+        if self.in_alternate_screen {
+            self.terminal.write(ALT_SCREEN_EXIT);
+        }
+    }
+
+    /// Enter lookback mode, or leave it if already there, as the lookback
+    /// key would.
+    pub fn toggle_lookback(&mut self) {
+        if self.in_lookback_mode {
+            self.exit_lookback_mode();
+        } else {
+            self.enter_lookback_mode();
+        }
+    }
+
+    /// Send `data` to the child as if typed, bypassing key bindings and
+    /// lookback mode.
+    pub fn send_input(&mut self, data: &[u8]) {
+        self.active_since = None;
+        self.record_input(data, None, InputAction::Forward, None);
+        self.pty_output.extend_from_slice(data);
+    }
+
+    /// Raw history with its escape sequences, or only the last `lines`.
+    pub fn history(&self, lines: Option<usize>) -> Vec<u8> {
+        let mut output = Vec::with_capacity(self.history.total_bytes());
+        match lines {
+            Some(lines) => self.history.append_last_n_lines(lines, &mut output),
+            None => self.history.append_all(&mut output),
+        }
+        output
+    }
+
+    /// History as plain text, as the lookback view shows it, or only the
+    /// last `lines`. Only those lines are rendered, so a short tail of a
+    /// long history is cheap.
+    pub fn history_text(&self, lines: Option<usize>) -> String {
+        let Some(lines) = lines else {
+            return self.build_lookback_view().text();
+        };
+        let (rows, cols) = self.vt_parser.screen().size();
+        let mut tail = Vec::new();
+        self.history.append_last_n_lines(lines, &mut tail);
+        // A line can wrap onto several rows
+        last_lines(&LookbackView::new(rows, cols, &tail, lines).text(), lines)
+    }
+
+    /// Time since the child last wrote anything, `None` if it never has.
+    pub fn idle_time(&self, now: Instant) -> Option<Duration> {
+        self.last_activity
+            .map(|last| now.saturating_duration_since(last))
+    }
+
+    pub fn resize(&mut self, rows: u16, cols: u16) {
+        debug!("resize: rows={} cols={}", rows, cols);
+        // Resize VT emulator
+        self.vt_parser.screen_mut().set_size(rows, cols);
+        // Force full render on next frame since size changed
+        self.vt_prev_screen = None;
+        self.lookback_view = None;
+    }
+
+    /// Bytes to write to the terminal since the last call.
+    pub fn take_terminal_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.terminal.buffer)
+    }
+
+    /// Bytes to write to the PTY since the last call.
+    pub fn take_pty_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.pty_output)
+    }
+
+    /// Whether the terminal size should be re-read and passed to `resize`,
+    /// e.g. after leaving lookback mode. Cleared by the call.
+    pub fn take_winsize_sync(&mut self) -> bool {
+        std::mem::take(&mut self.winsize_sync_requested)
+    }
+
+    /// Whether the user pressed the detach key. Cleared by the call.
+    pub fn take_detach_request(&mut self) -> bool {
+        std::mem::take(&mut self.detach_requested)
+    }
+
+    pub fn take_events(&mut self) -> Vec<SessionEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    pub fn in_alternate_screen(&self) -> bool {
+        self.in_alternate_screen
+    }
+
+    pub fn in_lookback_mode(&self) -> bool {
+        self.in_lookback_mode
+    }
+
+    /// What the child appears to be doing, if the detectors can tell.
+    pub fn activity(&self) -> Option<ActivityState> {
+        self.activity
+    }
+
+    /// Whether the terminal is reporting mouse events for lookback mode and
+    /// needs `LOOKBACK_MOUSE_DISABLE` if the proxy goes away.
+    pub fn lookback_mouse_active(&self) -> bool {
+        self.in_lookback_mode && self.config.lookback_mouse
+    }
+
+    /// The child's screen as the proxy sees it.
+    pub fn screen(&self) -> &vt100::Screen {
+        self.vt_parser.screen()
+    }
+
+    pub fn stats(&self) -> ProxyStats {
+        ProxyStats {
+            terminal_bytes: self.terminal.bytes_written,
+            history_lines: self.history.line_count(),
+            history_bytes: self.history.total_bytes(),
+            ..self.stats.clone()
+        }
+    }
+
+    /// Handle child output in stream order, so the result doesn't depend on
+    /// where reads happened to split it.
+    fn process_output(&mut self, data: &[u8]) {
+        debug!(
+            "process_output: len={} in_alt={} in_lookback={}",
+            data.len(),
+            self.in_alternate_screen,
+            self.in_lookback_mode
+        );
+
+        if self.in_alternate_screen {
+            return self.process_output_alt_screen(data);
+        }
+
+        if self.in_lookback_mode {
+            debug!("process_output: caching {} bytes for lookback", data.len());
+            self.lookback_cache.extend_from_slice(data);
+            return;
+        }
+
+        // Everything up to and including an alt screen enter is main screen
+        // output; the rest belongs to the alternate screen
+        let alt_enter = self.find_alt_screen_enter(data);
+        let main_len = alt_enter.map_or(data.len(), |pos| {
+            pos + self.alt_screen_enter_len(&data[pos..])
+        });
+        self.vt_parser.process(&data[..main_len]);
+        self.vt_render_pending = true;
+        self.last_output_time = Some(self.now);
+        self.track_sync_blocks(&data[..alt_enter.unwrap_or(data.len())]);
+
+        if let Some(pos) = alt_enter {
+            debug!("process_output: ALT_SCREEN_ENTER detected at pos={}", pos);
+            // The alternate screen is ephemeral and stays out of history, so
+            // an unfinished sync block ends here
+            if self.in_sync_block {
+                self.flush_sync_block_to_history();
+                self.in_sync_block = false;
+            }
+            self.in_alternate_screen = true;
+            self.stats.alt_screen_entries += 1;
+            self.events.push(SessionEvent::AltScreen(true));
+            self.terminal.write(&data[pos..main_len]);
+            self.process_output_alt_screen(&data[main_len..]);
+        }
+    }
+
+    /// Add main screen output to history, buffering sync blocks so full
+    /// redraws can reset it.
+    fn track_sync_blocks(&mut self, data: &[u8]) {
+        let mut pos = 0;
+        while pos < data.len() {
+            if self.in_sync_block {
+                if let Some(idx) = self.sync_end_finder.find(&data[pos..]) {
+                    debug!("process_output: SYNC_END at pos={}", pos + idx);
+                    self.sync_buffer.extend_from_slice(&data[pos..pos + idx]);
+                    self.sync_buffer.extend_from_slice(SYNC_END);
+                    self.flush_sync_block_to_history();
+                    self.in_sync_block = false;
+                    pos += idx + SYNC_END.len();
+                } else {
+                    self.sync_buffer.extend_from_slice(&data[pos..]);
+                    break;
+                }
+            } else if let Some(idx) = self.sync_start_finder.find(&data[pos..]) {
+                debug!("process_output: SYNC_START at pos={}", pos + idx);
+                // Add any data before SYNC_START to history
+                if idx > 0 {
+                    self.push_to_history(&data[pos..pos + idx]);
+                }
+                self.in_sync_block = true;
+                self.sync_buffer.clear();
+                self.sync_buffer.extend_from_slice(SYNC_START);
+                pos += idx + SYNC_START.len();
+            } else {
+                // No sync block, just add to history
+                self.push_to_history(&data[pos..]);
+                break;
+            }
+        }
+    }
+
+    /// Pass alternate screen output straight through. It still goes to the VT
+    /// so the main screen can be redrawn on exit, but never to history.
+    fn process_output_alt_screen(&mut self, data: &[u8]) {
+        let Some(exit_pos) = self.find_alt_screen_exit(data) else {
+            self.vt_parser.process(data);
+            self.terminal.write(data);
+            return;
+        };
+        debug!(
+            "process_output_alt_screen: ALT_SCREEN_EXIT detected at pos={}",
+            exit_pos
+        );
+        let end = exit_pos + self.alt_screen_exit_len(&data[exit_pos..]);
+        self.vt_parser.process(&data[..end]);
+        self.terminal.write(&data[..end]);
+        self.in_alternate_screen = false;
+        self.events.push(SessionEvent::AltScreen(false));
+
+        // Force full VT render to restore main screen content
+        debug!("process_output_alt_screen: rendering VT screen after alt exit");
+        self.vt_prev_screen = None;
+        self.render_vt_screen();
+
+        if end < data.len() {
+            self.process_output(&data[end..]);
+        }
+    }
+
+    fn find_alt_screen_enter(&self, data: &[u8]) -> Option<usize> {
+        let pos1 = self.alt_screen_enter_finder.find(data);
+        let pos2 = self.alt_screen_enter_legacy_finder.find(data);
+        match (pos1, pos2) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (Some(a), None) => Some(a),
+            (None, Some(b)) => Some(b),
+            (None, None) => None,
+        }
+    }
+
+    fn find_alt_screen_exit(&self, data: &[u8]) -> Option<usize> {
+        let pos1 = self.alt_screen_exit_finder.find(data);
+        let pos2 = self.alt_screen_exit_legacy_finder.find(data);
+        match (pos1, pos2) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (Some(a), None) => Some(a),
+            (None, Some(b)) => Some(b),
+            (None, None) => None,
+        }
+    }
+
+    fn alt_screen_enter_len(&self, data: &[u8]) -> usize {
+        if data.starts_with(ALT_SCREEN_ENTER) {
+            ALT_SCREEN_ENTER.len()
+        } else {
+            ALT_SCREEN_ENTER_LEGACY.len()
+        }
+    }
+
+    fn alt_screen_exit_len(&self, data: &[u8]) -> usize {
+        if data.starts_with(ALT_SCREEN_EXIT) {
+            ALT_SCREEN_EXIT.len()
+        } else {
+            ALT_SCREEN_EXIT_LEGACY.len()
+        }
+    }
+
+    fn flush_sync_block_to_history(&mut self) {
+        let has_clear_screen = self.clear_screen_finder.find(&self.sync_buffer).is_some();
+        let has_cursor_home = self.cursor_home_finder.find(&self.sync_buffer).is_some();
+        let is_full_redraw = has_clear_screen && has_cursor_home;
+
+        debug!(
+            "flush_sync_block: len={} full_redraw={}",
+            self.sync_buffer.len(),
+            is_full_redraw
+        );
+
+        self.stats.sync_blocks += 1;
+        if is_full_redraw {
+            debug!("CLEARING HISTORY");
+            self.stats.full_redraws += 1;
+            self.history.clear();
+            // Re-seed with clear screen after clearing
+            self.history.push_bytes(CLEAR_SCREEN);
+            self.history.push_bytes(CURSOR_HOME);
+        }
+        self.push_to_history(&self.sync_buffer.clone());
+        self.sync_buffer.clear();
+    }
+
+    /// Push data to history, filtering out terminal query sequences that would
+    /// cause the terminal to respond when replayed.
+    fn push_to_history(&mut self, data: &[u8]) {
+        let filtered = self.history_filter.filter(data);
+        self.history.push_bytes(&filtered);
+    }
+
+    fn flush_pending_vt_render(&mut self) {
+        let Some(delay) = self.render_delay() else {
+            return;
+        };
+        let elapsed = self
+            .last_output_time
+            .map(|t| self.now.saturating_duration_since(t))
+            .unwrap_or(Duration::MAX);
+        if elapsed >= delay {
+            self.render_vt_screen();
+        }
+    }
+
+    /// How long output must be quiet before a pending render happens, or None
+    /// if nothing is waiting to be rendered.
+    fn render_delay(&self) -> Option<Duration> {
+        if !self.vt_render_pending || self.in_lookback_mode || self.in_alternate_screen {
+            return None;
+        }
+        // Wait longer if in sync block (more data likely coming)
+        if self.in_sync_block {
+            Some(Duration::from_millis(SYNC_BLOCK_DELAY_MS))
+        } else {
+            Some(Duration::from_millis(RENDER_DELAY_MS))
+        }
+    }
+
+    fn render_vt_screen(&mut self) {
+        let is_diff = self.vt_prev_screen.is_some();
+        self.output_buffer.clear();
+        mirror::render_screen(
+            &mut self.output_buffer,
+            self.vt_parser.screen(),
+            self.vt_prev_screen.as_ref(),
+        );
+
+        debug!(
+            "render_vt_screen: diff={} output_len={}\n",
+            is_diff,
+            self.output_buffer.len()
+        );
+        self.terminal.write(&self.output_buffer);
+
+        // Store current screen for next diff
+        self.vt_prev_screen = Some(self.vt_parser.screen().clone());
+        self.vt_render_pending = false;
+        self.last_render_time = Some(self.now);
+        self.stats.renders += 1;
+        self.detect_activity();
+    }
+
+    fn detect_activity(&mut self) {
+        if self.config.detectors.is_empty() {
+            return;
+        }
+        let activity = detector::classify(&self.config.detectors, self.vt_parser.screen());
+        if activity == self.activity {
+            return;
+        }
+        debug!("detect_activity: {:?} -> {:?}", self.activity, activity);
+        self.activity = activity;
+        if activity == Some(ActivityState::WaitingForApproval)
+            && self.config.notifications.on_approval
+        {
+            self.notify("Waiting for approval");
+        }
+    }
+
+    fn auto_lookback_deadline(&self) -> Option<Instant> {
+        if self.auto_lookback_timeout.is_zero() {
+            return None;
+        }
+        if self.in_lookback_mode || self.in_alternate_screen {
+            return None;
+        }
+        // Quiet thinking isn't idle
+        if self.activity == Some(ActivityState::Working) {
+            return None;
+        }
+        Some(self.last_render_time? + self.auto_lookback_timeout)
+    }
+
+    fn check_auto_lookback(&mut self) {
+        let Some(deadline) = self.auto_lookback_deadline() else {
+            return;
+        };
+        if self.now < deadline {
+            return;
+        }
+        self.dump_history();
+        self.last_render_time = None;
+    }
+
+    fn idle_deadline(&self) -> Option<Instant> {
+        if self.idle_timeout.is_zero() || self.idle_reported {
+            return None;
+        }
+        if self.activity == Some(ActivityState::Working) {
+            return None;
+        }
+        Some(self.last_activity? + self.idle_timeout)
+    }
+
+    /// Report the child going quiet, once per quiet spell.
+    fn check_idle(&mut self) {
+        let Some(deadline) = self.idle_deadline() else {
+            return;
+        };
+        if self.now < deadline {
+            return;
+        }
+        self.idle_reported = true;
+        let (Some(last), Some(since)) = (self.last_activity, self.active_since) else {
+            return;
+        };
+        let idle = self.now.saturating_duration_since(last);
+        let active = last.saturating_duration_since(since);
+        self.events.push(SessionEvent::Idle { idle, active });
+
+        // Approval prompts were notified about when they appeared
+        let notifications = &self.config.notifications;
+        if notifications.on_idle
+            && active >= Duration::from_millis(notifications.min_active_ms)
+            && self.activity != Some(ActivityState::WaitingForApproval)
+        {
+            self.notify(&format!(
+                "Waiting for input after {}s of work",
+                active.as_secs()
+            ));
+        }
+    }
+
+    /// Ask the terminal to show a desktop notification, if configured.
+    fn notify(&mut self, body: &str) {
+        let style = self.config.notifications.style;
+        if style != NotificationStyle::Off {
+            debug!("notify: {}", body);
+            self.terminal
+                .write(&notification::notification(style, "claude-chill", body));
+        }
+    }
+
+    /// Act on a bell from the child as the bell policy says, unless it
+    /// comes too soon after the last one.
+    fn ring_bell(&mut self) {
+        let min_interval = Duration::from_millis(self.config.bell.min_interval_ms);
+        if let Some(last) = self.last_bell
+            && self.now.saturating_duration_since(last) < min_interval
+        {
+            debug!("ring_bell: rate-limited");
+            return;
+        }
+        self.last_bell = Some(self.now);
+        self.stats.bells += 1;
+        self.events.push(SessionEvent::Bell);
+
+        let action = self.config.bell.action;
+        debug!("ring_bell: {:?}", action);
+        match action {
+            BellAction::Forward => self.terminal.write(b"\x07"),
+            BellAction::Suppress | BellAction::Notify => {}
+            BellAction::Flash => {
+                if self.visual_bell_until.is_none() {
+                    self.terminal.write(VISUAL_BELL_ON);
+                }
+                self.visual_bell_until = Some(self.now + Duration::from_millis(VISUAL_BELL_MS));
+            }
+        }
+        if action == BellAction::Notify || self.config.notifications.on_bell {
+            self.notify("Bell");
+        }
+    }
+
+    /// Turn the visual bell back off once it has been on long enough.
+    fn check_visual_bell(&mut self) {
+        if let Some(until) = self.visual_bell_until
+            && self.now >= until
+        {
+            self.visual_bell_until = None;
+            self.terminal.write(VISUAL_BELL_OFF);
+        }
+    }
+
+    fn dump_history(&mut self) {
+        debug!(
+            "dump_history: history_bytes={} lines={}",
+            self.history.total_bytes(),
+            self.history.line_count()
+        );
+        self.output_buffer.clear();
+        self.history.append_all(&mut self.output_buffer);
+
+        self.terminal.write(CLEAR_SCREEN);
+        self.terminal.write(CURSOR_HOME);
+        self.terminal.write(&self.output_buffer);
+
+        // Force full VT render on next output since terminal now shows history
+        self.vt_prev_screen = None;
+    }
+
+    /// Resolve input that is still ambiguous after the escape timeout, e.g. a
+    /// lone ESC that could have been the start of a longer sequence.
+    fn flush_input_decoder(&mut self) {
+        let Some(since) = self.input_pending_since else {
+            return;
+        };
+        if self.now.saturating_duration_since(since)
+            < Duration::from_millis(INPUT_ESCAPE_TIMEOUT_MS)
+        {
+            return;
+        }
+        self.input_pending_since = None;
+        for input in self.input_decoder.flush() {
+            self.handle_input_event(input);
+        }
+    }
+
+    fn handle_input_event(&mut self, input: DecodedInput) {
+        debug!("input: {}", input.event);
+
+        if self.detach_event.as_ref() == Some(&input.event) {
+            self.record_input(&input.raw, Some(&input.event), InputAction::Detach, None);
+            self.detach_requested = true;
+            return;
+        }
+
+        if self.lookback_event.as_ref() == Some(&input.event) {
+            if self.in_lookback_mode {
+                self.record_input(
+                    &input.raw,
+                    Some(&input.event),
+                    InputAction::LookbackExit,
+                    None,
+                );
+                self.exit_lookback_mode();
+            } else {
+                self.record_input(
+                    &input.raw,
+                    Some(&input.event),
+                    InputAction::LookbackEnter,
+                    None,
+                );
+                self.enter_lookback_mode();
+            }
+            return;
+        }
+
+        if !self.in_lookback_mode {
+            if let Some((_, payload)) = self
+                .macro_events
+                .iter()
+                .find(|(event, _)| *event == input.event)
+            {
+                let payload = payload.clone();
+                debug!("input: macro payload len={}", payload.len());
+                self.record_input(
+                    &input.raw,
+                    Some(&input.event),
+                    InputAction::Macro,
+                    Some(&payload),
+                );
+                self.pty_output.extend_from_slice(&payload);
+                return;
+            }
+            self.record_input(&input.raw, Some(&input.event), InputAction::Forward, None);
+            self.pty_output.extend_from_slice(&input.raw);
+            return;
+        }
+
+        match input.event {
+            InputEvent::Key(ref key) if is_ctrl_c(key) => {
+                self.record_input(
+                    &input.raw,
+                    Some(&input.event),
+                    InputAction::LookbackExit,
+                    None,
+                );
+                self.exit_lookback_mode();
+            }
+            InputEvent::Mouse(event) if self.config.lookback_mouse => {
+                self.record_input(
+                    &input.raw,
+                    Some(&input.event),
+                    InputAction::LookbackMouse,
+                    None,
+                );
+                self.handle_lookback_mouse(event);
+            }
+            _ => {
+                self.record_input(&input.raw, Some(&input.event), InputAction::Dropped, None);
+            }
+        }
+    }
+
+    /// Append to the input recording, if enabled. A failing recorder is
+    /// dropped rather than taking the session down with it.
+    fn record_input(
+        &mut self,
+        input: &[u8],
+        event: Option<&InputEvent>,
+        action: InputAction,
+        written: Option<&[u8]>,
+    ) {
+        if let Some(recorder) = &mut self.input_recorder
+            && let Err(e) = recorder.record(input, event, action, written)
+        {
+            debug!("record_input: disabling recorder: {:#}", e);
+            self.input_recorder = None;
+        }
+    }
+
+    fn enter_lookback_mode(&mut self) {
+        debug!(
+            "enter_lookback_mode: history_bytes={} lines={}",
+            self.history.total_bytes(),
+            self.history.line_count()
+        );
+        self.in_lookback_mode = true;
+        self.lookback_cache.clear();
+        self.vt_render_pending = false;
+
+        self.output_buffer.clear();
+        self.history.append_all(&mut self.output_buffer);
+
+        debug!(
+            "enter_lookback_mode: output_buffer_len={}",
+            self.output_buffer.len()
+        );
+
+        self.terminal.write(CLEAR_SCREEN);
+        self.terminal.write(CURSOR_HOME);
+        self.terminal.write(&self.output_buffer);
+
+        let exit_msg = format!(
+            "\r\n\x1b[7m--- {}: press {} or Ctrl+C to exit ---\x1b[0m\r\n",
+            self.lookback_title(),
+            self.config.lookback_key
+        );
+        self.terminal.write(exit_msg.as_bytes());
+
+        if self.config.lookback_mouse {
+            self.terminal.write(LOOKBACK_MOUSE_ENABLE);
+        }
+    }
+
+    /// Lookback mode's banner, with what Claude was doing if known.
+    fn lookback_title(&self) -> String {
+        match self.activity {
+            Some(activity) => format!("LOOKBACK MODE (Claude is {})", activity.description()),
+            None => "LOOKBACK MODE".to_string(),
+        }
+    }
+
+    fn handle_lookback_mouse(&mut self, event: MouseEvent) {
+        debug!("handle_lookback_mouse: {:?}", event);
+        let view = match self.lookback_view.take() {
+            Some(view) => view,
+            None => self.build_lookback_view(),
+        };
+        let view = self.lookback_view.insert(view);
+
+        match event.kind {
+            MouseEventKind::WheelUp => view.scroll_up(WHEEL_SCROLL_LINES),
+            MouseEventKind::WheelDown => view.scroll_down(WHEEL_SCROLL_LINES),
+            MouseEventKind::Press(MouseButton::Left) => view.start_selection(event.row, event.col),
+            MouseEventKind::Drag(MouseButton::Left) => view.extend_selection(event.row, event.col),
+            MouseEventKind::Release => {
+                if let Some(text) = view.selected_text() {
+                    self.terminal.write(&lookback_view::osc52_copy(&text));
+                }
+            }
+            _ => return,
+        }
+
+        self.render_lookback_view();
+    }
+
+    fn build_lookback_view(&self) -> LookbackView {
+        let (rows, cols) = self.vt_parser.screen().size();
+        let mut replay = Vec::with_capacity(self.history.total_bytes());
+        self.history.append_all(&mut replay);
+        LookbackView::new(rows, cols, &replay, self.history.line_count())
+    }
+
+    fn render_lookback_view(&mut self) {
+        let Some(view) = &self.lookback_view else {
+            return;
+        };
+        let status = format!(
+            "--- {}: {} lines up, wheel to scroll, drag to copy, {} or Ctrl+C to exit ---",
+            self.lookback_title(),
+            view.offset(),
+            self.config.lookback_key
+        );
+        self.output_buffer.clear();
+        view.render(&mut self.output_buffer, &status);
+        self.terminal.write(&self.output_buffer);
+    }
+
+    fn exit_lookback_mode(&mut self) {
+        debug!(
+            "exit_lookback_mode: cached_len={}",
+            self.lookback_cache.len()
+        );
+        self.in_lookback_mode = false;
+
+        if self.config.lookback_mouse {
+            // Drop our mouse reporting and put back whatever the child asked for
+            self.lookback_view = None;
+            self.terminal.write(LOOKBACK_MOUSE_DISABLE);
+            self.terminal
+                .write(&self.vt_parser.screen().input_mode_formatted());
+        }
+
+        // Process cached output through VT to update screen state
+        let cached = std::mem::take(&mut self.lookback_cache);
+        if !cached.is_empty() {
+            debug!(
+                "exit_lookback_mode: processing {} cached bytes",
+                cached.len()
+            );
+            self.process_output(&cached);
+        }
+
+        // Reset sync block state
+        self.in_sync_block = false;
+        self.sync_buffer.clear();
+
+        // The terminal may have been resized while we weren't rendering
+        self.winsize_sync_requested = true;
+
+        // Force full render since terminal was showing history
+        debug!("exit_lookback_mode: rendering VT screen");
+        self.vt_prev_screen = None;
+        self.render_vt_screen();
+    }
+}
+
+/// Length of the longest suffix of `data` that is a proper prefix of one of
+/// the output markers.
+fn partial_marker_len(data: &[u8]) -> usize {
+    let longest = OUTPUT_MARKERS.iter().map(|m| m.len()).max().unwrap_or(1) - 1;
+    (1..=longest.min(data.len()))
+        .rev()
+        .find(|&len| {
+            let tail = &data[data.len() - len..];
+            OUTPUT_MARKERS
+                .iter()
+                .any(|marker| marker.len() > len && marker.starts_with(tail))
+        })
+        .unwrap_or(0)
+}
+
+fn is_ctrl_c(key: &KeyCombination) -> bool {
+    key.code == KeyCode::Char('c')
+        && key.modifiers
+            == Modifiers {
+                ctrl: true,
+                ..Modifiers::default()
+            }
+}
+
+/// The last `count` lines of `text`.
+fn last_lines(text: &str, count: usize) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    lines[lines.len().saturating_sub(count)..].join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BellConfig, Config, NotificationsConfig};
+    use crate::macros::InputMacro;
+    use crate::test_support::{SharedBuffer, lines};
+
+    const ROWS: u16 = 5;
+    const COLS: u16 = 20;
+
+    struct Harness {
+        core: ProxyCore,
+        start: Instant,
+    }
+
+    impl Harness {
+        fn new(config: ProxyConfig) -> Self {
+            let start = Instant::now();
+            Self {
+                core: ProxyCore::new(config, ROWS, COLS, start),
+                start,
+            }
+        }
+
+        fn at(&self, ms: u64) -> Instant {
+            self.start + Duration::from_millis(ms)
+        }
+
+        fn terminal_screen(&mut self) -> vt100::Parser {
+            let mut parser = vt100::Parser::new(ROWS, COLS, 0);
+            parser.process(&self.core.take_terminal_output());
+            parser
+        }
+    }
+
+    fn redraw(text: &str) -> Vec<u8> {
+        format!("\x1b[?2026h\x1b[2J\x1b[H{}\x1b[?2026l", text).into_bytes()
+    }
+
+    #[test]
+    fn test_render_waits_for_quiet_output() {
+        let mut h = Harness::new(ProxyConfig::default());
+        h.core.output(b"hello", h.at(0));
+        assert_eq!(h.core.next_deadline(), Some(h.at(RENDER_DELAY_MS)));
+        h.core.tick(h.at(1));
+        assert!(h.core.take_terminal_output().is_empty());
+        h.core.tick(h.at(RENDER_DELAY_MS));
+        let parser = h.terminal_screen();
+        assert_eq!(parser.screen().contents(), "hello");
+    }
+
+    #[test]
+    fn test_unfinished_sync_block_waits_longer() {
+        let mut h = Harness::new(ProxyConfig::default());
+        h.core.output(b"\x1b[?2026hpartial", h.at(0));
+        assert_eq!(h.core.next_deadline(), Some(h.at(SYNC_BLOCK_DELAY_MS)));
+    }
+
+    #[test]
+    fn test_full_redraw_resets_history() {
+        let mut h = Harness::new(ProxyConfig::default());
+        h.core.output(b"old\r\n", h.at(0));
+        h.core.output(&redraw("new\r\n"), h.at(1));
+        let stats = h.core.stats();
+        assert_eq!(stats.sync_blocks, 1);
+        assert_eq!(stats.full_redraws, 1);
+        assert_eq!(stats.history_lines, 2);
+    }
+
+    #[test]
+    fn test_partial_marker_len() {
+        assert_eq!(partial_marker_len(b"text"), 0);
+        assert_eq!(partial_marker_len(b"text\x1b"), 1);
+        assert_eq!(partial_marker_len(b"text\x1b[?2026"), 7);
+        assert_eq!(partial_marker_len(b"text\x1b[?2026h"), 0);
+        assert_eq!(partial_marker_len(b"\x1b[?10"), 5);
+        assert_eq!(partial_marker_len(b"\x1b[31"), 0);
+    }
+
+    #[test]
+    fn test_marker_split_across_reads() {
+        let mut h = Harness::new(ProxyConfig::default());
+        h.core.output(b"text\x1b[?10", h.at(0));
+        assert!(!h.core.in_alternate_screen());
+        h.core.output(b"49hfullscreen", h.at(1));
+        assert!(h.core.in_alternate_screen());
+
+        let mut h = Harness::new(ProxyConfig::default());
+        h.core
+            .output(b"\x1b[?2026h\x1b[2J\x1b[Hredraw\x1b[?20", h.at(0));
+        h.core.output(b"26l", h.at(1));
+        assert_eq!(h.core.stats().full_redraws, 1);
+    }
+
+    #[test]
+    fn test_finish_flushes_partial_marker() {
+        let mut h = Harness::new(ProxyConfig::default());
+        h.core.output(b"done\x1b[", h.at(0));
+        assert_eq!(h.core.output_carry, b"\x1b[");
+        h.core.finish();
+        assert!(h.core.output_carry.is_empty());
+    }
+
+    #[test]
+    fn test_alt_screen_passes_through() {
+        let mut h = Harness::new(ProxyConfig {
+            idle_timeout_ms: 0,
+            ..ProxyConfig::default()
+        });
+        h.core.output(b"\x1b[?1049hfullscreen", h.at(0));
+        assert!(h.core.in_alternate_screen());
+        assert_eq!(h.core.take_terminal_output(), b"\x1b[?1049hfullscreen");
+        assert_eq!(h.core.next_deadline(), None);
+
+        h.core.output(b"\x1b[?1049l", h.at(10));
+        assert!(!h.core.in_alternate_screen());
+        let output = h.core.take_terminal_output();
+        assert!(output.starts_with(b"\x1b[?1049l"));
+        assert_eq!(h.core.stats().alt_screen_entries, 1);
+    }
+
+    #[test]
+    fn test_alt_screen_input_is_recorded() {
+        let mut h = Harness::new(ProxyConfig::default());
+        let buffer = SharedBuffer::default();
+        h.core
+            .set_input_recorder(InputRecorder::new(Box::new(buffer.clone())).unwrap());
+        // A lone ESC waits to see if a sequence follows
+        h.core.input(b"\x1b", h.at(0));
+        h.core.output(b"\x1b[?1049h", h.at(10));
+        h.core.input(b"x", h.at(20));
+        assert_eq!(h.core.take_pty_output(), b"\x1bx");
+        let records: Vec<_> = lines(&buffer)[1..]
+            .iter()
+            .map(|record| (record["action"].clone(), record["data"].clone()))
+            .collect();
+        assert_eq!(
+            records,
+            [
+                ("forward".into(), "\x1b".into()),
+                ("forward".into(), "x".into())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lookback_caches_output() {
+        let mut h = Harness::new(ProxyConfig {
+            idle_timeout_ms: 0,
+            ..ProxyConfig::default()
+        });
+        h.core.output(b"before\r\n", h.at(0));
+        h.core.tick(h.at(10));
+        h.core.take_terminal_output();
+
+        h.core.input(&[0x1e], h.at(20));
+        assert!(h.core.in_lookback_mode());
+        assert!(h.core.take_pty_output().is_empty());
+        let shown = h.terminal_screen();
+        assert!(shown.screen().contents().contains("LOOKBACK MODE"));
+
+        h.core.output(b"during", h.at(30));
+        assert!(h.core.take_terminal_output().is_empty());
+        assert_eq!(h.core.next_deadline(), None);
+
+        h.core.input(&[0x03], h.at(40));
+        assert!(!h.core.in_lookback_mode());
+        assert!(h.core.take_winsize_sync());
+        assert!(h.core.take_pty_output().is_empty());
+        let shown = h.terminal_screen();
+        assert_eq!(shown.screen().contents(), "before\nduring");
+    }
+
+    #[test]
+    fn test_input_forwarded_and_macros() {
+        let config = ProxyConfig {
+            macros: vec![InputMacro::parse("[f5]", r"/compact\r").unwrap()],
+            ..ProxyConfig::default()
+        };
+        let mut h = Harness::new(config);
+        h.core.input(b"hi\x1b[15~", h.at(0));
+        assert_eq!(h.core.take_pty_output(), b"hi/compact\r");
+    }
+
+    #[test]
+    fn test_lone_escape_flushed_after_timeout() {
+        let mut h = Harness::new(ProxyConfig::default());
+        h.core.input(b"\x1b", h.at(0));
+        assert!(h.core.take_pty_output().is_empty());
+        let deadline = h.at(INPUT_ESCAPE_TIMEOUT_MS);
+        assert_eq!(h.core.next_deadline(), Some(deadline));
+        h.core.tick(deadline);
+        assert_eq!(h.core.take_pty_output(), b"\x1b");
+    }
+
+    #[test]
+    fn test_auto_lookback_after_idle() {
+        let config = ProxyConfig {
+            auto_lookback_timeout_ms: 1000,
+            idle_timeout_ms: 0,
+            ..ProxyConfig::default()
+        };
+        let mut h = Harness::new(config);
+        h.core.output(b"line\r\n", h.at(0));
+        h.core.tick(h.at(10));
+        h.core.take_terminal_output();
+        assert_eq!(h.core.next_deadline(), Some(h.at(1010)));
+
+        h.core.tick(h.at(1010));
+        let output = h.core.take_terminal_output();
+        assert!(output.starts_with(CLEAR_SCREEN));
+        assert_eq!(h.core.next_deadline(), None);
+    }
+
+    #[test]
+    fn test_detach_key_and_full_render() {
+        // Off by default, so the key reaches the child
+        let mut h = Harness::new(ProxyConfig::default());
+        h.core.input(b"\x1c", h.at(0));
+        assert!(!h.core.take_detach_request());
+        assert_eq!(h.core.take_pty_output(), b"\x1c");
+
+        let mut h = Harness::new(ProxyConfig {
+            detach_sequence: vec![0x1C],
+            ..ProxyConfig::default()
+        });
+        h.core.output(b"\x1b[?2004hhello\r\nworld", h.at(0));
+        h.core.tick(h.at(10));
+        h.core.take_terminal_output();
+
+        h.core.input(b"a\x1c", h.at(20));
+        assert!(h.core.take_detach_request());
+        assert!(!h.core.take_detach_request());
+        assert_eq!(h.core.take_pty_output(), b"a");
+
+        // A new terminal gets the whole screen and the child's input modes
+        h.core.full_render();
+        let output = h.core.take_terminal_output();
+        assert!(output.windows(8).any(|w| w == b"\x1b[?2004h"));
+        let mut shown = vt100::Parser::new(ROWS, COLS, 0);
+        shown.process(&output);
+        assert_eq!(shown.screen().contents(), "hello\nworld");
+    }
+
+    #[test]
+    fn test_session_events() {
+        let config = ProxyConfig {
+            auto_lookback_timeout_ms: 0,
+            idle_timeout_ms: 1000,
+            ..ProxyConfig::default()
+        };
+        let mut h = Harness::new(config);
+        assert_eq!(h.core.next_deadline(), None);
+
+        // Only bells that ring count, not OSC terminators
+        h.core.output(b"\x1b]0;title\x07done\x07", h.at(0));
+        h.core.tick(h.at(10));
+        assert_eq!(h.core.take_events(), vec![SessionEvent::Bell]);
+        assert_eq!(h.core.next_deadline(), Some(h.at(1000)));
+
+        h.core.tick(h.at(1200));
+        assert_eq!(
+            h.core.take_events(),
+            vec![SessionEvent::Idle {
+                idle: Duration::from_millis(1200),
+                active: Duration::ZERO,
+            }]
+        );
+        // Reported once until the child writes again
+        assert_eq!(h.core.next_deadline(), None);
+
+        h.core.output(b"\x1b[?1049hfull", h.at(2000));
+        h.core.output(b"\x1b[?1049l", h.at(2100));
+        assert_eq!(
+            h.core.take_events(),
+            vec![
+                SessionEvent::AltScreen(true),
+                SessionEvent::AltScreen(false)
+            ]
+        );
+        assert_eq!(h.core.next_deadline(), Some(h.at(3100)));
+    }
+
+    #[test]
+    fn test_notifications() {
+        let config = ProxyConfig {
+            auto_lookback_timeout_ms: 0,
+            idle_timeout_ms: 1000,
+            notifications: NotificationsConfig {
+                style: NotificationStyle::Osc9,
+                min_active_ms: 2000,
+                ..NotificationsConfig::default()
+            },
+            ..ProxyConfig::default()
+        };
+        let mut h = Harness::new(config);
+        let notified = |output: &[u8]| output.windows(4).any(|w| w == b"\x1b]9;");
+
+        // Echoing a key isn't work worth a notification
+        h.core.input(b"x", h.at(0));
+        h.core.output(b"x", h.at(10));
+        h.core.tick(h.at(1010));
+        assert!(!notified(&h.core.take_terminal_output()));
+
+        h.core.input(b"\r", h.at(2000));
+        h.core.output(b"\r\nthinking", h.at(2010));
+        h.core.output(b"\r\ndone", h.at(4510));
+        h.core.tick(h.at(5510));
+        assert_eq!(
+            h.core.take_events().last(),
+            Some(&SessionEvent::Idle {
+                idle: Duration::from_millis(1000),
+                active: Duration::from_millis(2500),
+            })
+        );
+        let output = h.core.take_terminal_output();
+        assert!(notified(&output));
+        assert!(output.windows(12).any(|w| w == b"after 2s of "));
+
+        h.core.output(b"\x07", h.at(6000));
+        assert!(notified(&h.core.take_terminal_output()));
+    }
+
+    #[test]
+    fn test_bell_policy() {
+        let rang = |output: &[u8]| output.iter().filter(|&&b| b == 0x07).count();
+
+        let mut h = Harness::new(ProxyConfig::default());
+        h.core.output(b"\x1b]0;title\x07done\x07\r\n", h.at(0));
+        assert_eq!(rang(&h.core.take_terminal_output()), 1);
+        // Too soon after the last one
+        h.core.output(b"\x07", h.at(100));
+        assert_eq!(rang(&h.core.take_terminal_output()), 0);
+        h.core.output(b"\x07", h.at(600));
+        assert_eq!(rang(&h.core.take_terminal_output()), 1);
+        assert_eq!(h.core.stats().bells, 2);
+
+        // History keeps the OSC terminator but not the bells
+        h.core.toggle_lookback();
+        let output = h.core.take_terminal_output();
+        assert!(output.windows(10).any(|w| w == b"\x1b]0;title\x07"));
+        assert_eq!(rang(&output), 1);
+        h.core.toggle_lookback();
+
+        // Bells on the alternate screen are handled where they are
+        h.core.output(b"\x1b[?1049hab\x07cd", h.at(1200));
+        let output = h.core.take_terminal_output();
+        assert!(output.ends_with(b"ab\x07cd"));
+
+        let config = ProxyConfig {
+            bell: BellConfig {
+                action: BellAction::Suppress,
+                min_interval_ms: 0,
+            },
+            ..ProxyConfig::default()
+        };
+        let mut h = Harness::new(config);
+        h.core.output(b"\x07\x07", h.at(0));
+        assert_eq!(rang(&h.core.take_terminal_output()), 0);
+        assert_eq!(
+            h.core.take_events(),
+            vec![SessionEvent::Bell, SessionEvent::Bell]
+        );
+    }
+
+    #[test]
+    fn test_visual_bell() {
+        let config = ProxyConfig {
+            bell: BellConfig {
+                action: BellAction::Flash,
+                ..BellConfig::default()
+            },
+            ..ProxyConfig::default()
+        };
+        let mut h = Harness::new(config);
+        h.core.output(b"\x07", h.at(0));
+        assert_eq!(h.core.take_terminal_output(), VISUAL_BELL_ON);
+        assert_eq!(h.core.next_deadline(), Some(h.at(VISUAL_BELL_MS)));
+        h.core.tick(h.at(50));
+        assert!(h.core.take_terminal_output().is_empty());
+        h.core.tick(h.at(VISUAL_BELL_MS));
+        assert_eq!(h.core.take_terminal_output(), VISUAL_BELL_OFF);
+
+        // Detaching mid-flash leaves the screen the right way round
+        h.core.output(b"\x07", h.at(1000));
+        h.core.release_terminal();
+        assert!(
+            h.core
+                .take_terminal_output()
+                .starts_with(&[VISUAL_BELL_ON, VISUAL_BELL_OFF].concat())
+        );
+        h.core.tick(h.at(1000 + VISUAL_BELL_MS));
+        assert!(h.core.take_terminal_output().is_empty());
+    }
+
+    #[test]
+    fn test_banner_until_next_render() {
+        let mut h = Harness::new(ProxyConfig::default());
+        h.core.output(b"top\r\nprompt", h.at(0));
+        h.core.tick(h.at(RENDER_DELAY_MS));
+        let mut parser = h.terminal_screen();
+
+        h.core.show_banner("hung: no output for 10m and then some");
+        parser.process(&h.core.take_terminal_output());
+        assert_eq!(parser.screen().contents(), "hung: no output for \nprompt");
+        assert_eq!(parser.screen().cursor_position(), (1, 6));
+
+        h.core.output(b"!", h.at(100));
+        h.core.tick(h.at(100 + RENDER_DELAY_MS));
+        parser.process(&h.core.take_terminal_output());
+        assert_eq!(parser.screen().contents(), "top\nprompt!");
+    }
+
+    #[test]
+    fn test_history_text_tail() {
+        let mut h = Harness::new(ProxyConfig::default());
+        for i in 0..50 {
+            h.core
+                .output(format!("\x1b[1mline {}\x1b[m\r\n", i).as_bytes(), h.at(0));
+        }
+        h.core.output(&[b'w'; 30], h.at(0));
+        let full = h.core.history_text(None);
+        let tail = h.core.history_text(Some(3));
+        // The last line wraps at 20 columns
+        assert_eq!(tail, "line 49\nwwwwwwwwwwwwwwwwwwww\nwwwwwwwwww");
+        assert!(full.ends_with(&tail));
+    }
+
+    #[test]
+    fn test_child_restarted() {
+        let mut h = Harness::new(ProxyConfig::default());
+        h.core.resize(ROWS, 80);
+        h.core
+            .output(b"first\r\n\x1b[?2004h\x1b[?1049hfull", h.at(0));
+        h.core.take_events();
+        h.core.child_restarted("claude --continue");
+        assert_eq!(h.core.take_events(), vec![SessionEvent::AltScreen(false)]);
+        assert!(!h.core.screen().bracketed_paste());
+
+        h.core.output(b"second", h.at(100));
+        let history = h.core.history_text(None);
+        assert!(history.contains("first"));
+        assert!(history.contains("--- restarted: claude --continue ---"));
+        assert!(history.ends_with("second"), "{:?}", history);
+    }
+
+    #[test]
+    fn test_activity_detection() {
+        let config = ProxyConfig {
+            auto_lookback_timeout_ms: 1000,
+            idle_timeout_ms: 1000,
+            notifications: NotificationsConfig {
+                style: NotificationStyle::Osc9,
+                ..NotificationsConfig::default()
+            },
+            detectors: Config::default().detectors(),
+            ..ProxyConfig::default()
+        };
+        let mut h = Harness::new(config);
+
+        // Thinking quietly is neither idle nor a reason for auto-lookback
+        h.core.output(b"esc to interrupt", h.at(0));
+        h.core.tick(h.at(10));
+        assert_eq!(h.core.activity(), Some(ActivityState::Working));
+        assert_eq!(h.core.next_deadline(), None);
+
+        // A permission prompt mid-stream notifies at once
+        h.core
+            .output(b"\x1b[2J\x1b[HDo you want to go?\r\n> 1. Yes", h.at(500));
+        h.core.tick(h.at(510));
+        assert_eq!(h.core.activity(), Some(ActivityState::WaitingForApproval));
+        let output = h.core.take_terminal_output();
+        assert!(output.windows(20).any(|w| w == b"Waiting for approval"));
+        assert!(h.core.next_deadline().is_some());
+
+        // The status line says so in lookback mode, given room
+        h.core.resize(ROWS, 80);
+        h.core.input(&[0x1e], h.at(600));
+        let mut shown = vt100::Parser::new(ROWS, 80, 0);
+        shown.process(&h.core.take_terminal_output());
+        assert!(
+            shown
+                .screen()
+                .contents()
+                .contains("LOOKBACK MODE (Claude is waiting for approval)")
+        );
+    }
+}
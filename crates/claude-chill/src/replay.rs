@@ -0,0 +1,705 @@
+//! Replay of asciicast recordings through the proxy's output pipeline.
+//!
+//! A recording of a child's raw output (`--record-raw`) is fed to a
+//! `ProxyCore`, so the same sync-block, history and rendering code runs as in
+//! a live session. The core is given recording time rather than wall time,
+//! so renders and other timers fire where they would have live regardless
+//! of playback speed.
+
+use crate::escape_sequences::{
+    ALT_SCREEN_ENTER, ALT_SCREEN_EXIT, CLEAR_SCREEN, CURSOR_HOME, LOOKBACK_MOUSE_DISABLE,
+};
+use crate::input_decoder::{InputDecoder, InputEvent};
+use crate::key_parser::{KeyCode, KeyCombination};
+use crate::proxy::TerminalGuard;
+use crate::proxy_core::{ProxyConfig, ProxyCore, ProxyStats};
+use anyhow::{Context, Result};
+use nix::errno::Errno;
+use nix::poll::{PollFd, PollFlags, PollTimeout, poll};
+use nix::unistd::read;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::fd::AsFd;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Seconds skipped by the seek keys.
+const SEEK_STEP_SECS: f64 = 5.0;
+const MIN_SPEED: f64 = 1.0 / 64.0;
+const MAX_SPEED: f64 = 64.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    /// Multiple of real time.
+    Factor(f64),
+    /// As fast as possible.
+    Max,
+}
+
+impl FromStr for ReplaySpeed {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("max") {
+            return Ok(ReplaySpeed::Max);
+        }
+        let number = s.strip_suffix(['x', 'X']).unwrap_or(s);
+        match number.parse::<f64>() {
+            Ok(factor) if factor.is_finite() && factor > 0.0 => Ok(ReplaySpeed::Factor(factor)),
+            _ => Err(format!(
+                "invalid speed {:?}: expected a positive number like 2 or 0.5, or \"max\"",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ReplaySpeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplaySpeed::Factor(factor) => write!(f, "{}x", factor),
+            ReplaySpeed::Max => write!(f, "max"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReplayOptions {
+    pub speed: ReplaySpeed,
+    /// Seconds into the recording to start from.
+    pub start: f64,
+    /// Replay as fast as possible without a terminal and print statistics.
+    pub headless: bool,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            speed: ReplaySpeed::Factor(1.0),
+            start: 0.0,
+            headless: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordedEvent {
+    Output(Vec<u8>),
+    Resize { cols: u16, rows: u16 },
+}
+
+/// The parts of an asciicast v2 file that matter for replay.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recording {
+    pub cols: u16,
+    pub rows: u16,
+    /// Events with their time in seconds from the start.
+    pub events: Vec<(f64, RecordedEvent)>,
+}
+
+impl Recording {
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("failed to open recording {}", path.display()))?;
+        Self::parse(BufReader::new(file))
+            .with_context(|| format!("failed to read recording {}", path.display()))
+    }
+
+    pub fn parse<R: BufRead>(reader: R) -> Result<Self> {
+        let mut lines = reader.lines().enumerate();
+        let header = loop {
+            let Some((_, line)) = lines.next() else {
+                anyhow::bail!("empty recording");
+            };
+            let line = line?;
+            if !line.trim().is_empty() {
+                break line;
+            }
+        };
+        let header: serde_json::Value = serde_json::from_str(&header).context("invalid header")?;
+        if header["version"] != 2 {
+            anyhow::bail!(
+                "unsupported asciicast version {}, expected 2",
+                header["version"]
+            );
+        }
+        let dimension = |name: &str| {
+            header[name]
+                .as_u64()
+                .and_then(|n| u16::try_from(n).ok())
+                .filter(|&n| n > 0)
+                .with_context(|| format!("header has no valid {}", name))
+        };
+        let cols = dimension("width")?;
+        let rows = dimension("height")?;
+
+        let mut events = Vec::new();
+        for (idx, line) in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (time, code, data): (f64, String, String) = serde_json::from_str(&line)
+                .with_context(|| format!("invalid event on line {}", idx + 1))?;
+            let event = match code.as_str() {
+                "o" => RecordedEvent::Output(data.into_bytes()),
+                "r" => parse_size(&data)
+                    .map(|(cols, rows)| RecordedEvent::Resize { cols, rows })
+                    .with_context(|| format!("invalid resize {:?} on line {}", data, idx + 1))?,
+                // Input and marker events don't affect output
+                _ => continue,
+            };
+            events.push((time, event));
+        }
+
+        Ok(Self { cols, rows, events })
+    }
+
+    /// Time of the last event.
+    pub fn duration(&self) -> f64 {
+        self.events.last().map(|(time, _)| *time).unwrap_or(0.0)
+    }
+}
+
+fn parse_size(size: &str) -> Option<(u16, u16)> {
+    let (cols, rows) = size.split_once('x')?;
+    Some((cols.parse().ok()?, rows.parse().ok()?))
+}
+
+/// Replay `path` on the terminal, or headless with statistics on stdout.
+pub fn run(path: &Path, config: ProxyConfig, options: &ReplayOptions) -> Result<()> {
+    let recording = Recording::load(path)?;
+    if options.headless {
+        let started = Instant::now();
+        let stats = replay_headless(&recording, config);
+        print!(
+            "{}",
+            Summary {
+                recording: &recording,
+                stats: &stats,
+                elapsed: started.elapsed(),
+            }
+        );
+        Ok(())
+    } else {
+        play(&recording, config, options)
+    }
+}
+
+/// Feed the whole recording through a proxy as fast as possible, discarding
+/// what it writes, and return its statistics.
+pub fn replay_headless(recording: &Recording, config: ProxyConfig) -> ProxyStats {
+    let mut player = Player::new(recording, config);
+    player.advance(f64::INFINITY);
+    player.core.finish();
+    player.core.stats()
+}
+
+struct Summary<'a> {
+    recording: &'a Recording,
+    stats: &'a ProxyStats,
+    elapsed: Duration,
+}
+
+impl fmt::Display for Summary<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let stats = self.stats;
+        let resizes = self
+            .recording
+            .events
+            .iter()
+            .filter(|(_, event)| matches!(event, RecordedEvent::Resize { .. }))
+            .count();
+        let ratio = if stats.output_bytes > 0 {
+            stats.terminal_bytes as f64 * 100.0 / stats.output_bytes as f64
+        } else {
+            0.0
+        };
+        writeln!(
+            f,
+            "duration:           {:.3}s (replayed in {:.3}s)",
+            self.recording.duration(),
+            self.elapsed.as_secs_f64()
+        )?;
+        writeln!(
+            f,
+            "events:             {} output, {} resize",
+            self.recording.events.len() - resizes,
+            resizes
+        )?;
+        writeln!(f, "child output:       {} bytes", stats.output_bytes)?;
+        writeln!(
+            f,
+            "terminal output:    {} bytes ({:.1}%)",
+            stats.terminal_bytes, ratio
+        )?;
+        writeln!(f, "renders:            {}", stats.renders)?;
+        writeln!(
+            f,
+            "sync blocks:        {} ({} full redraws)",
+            stats.sync_blocks, stats.full_redraws
+        )?;
+        writeln!(f, "alt screen entries: {}", stats.alt_screen_entries)?;
+        writeln!(f, "bells:              {}", stats.bells)?;
+        writeln!(
+            f,
+            "history:            {} lines, {} bytes",
+            stats.history_lines, stats.history_bytes
+        )
+    }
+}
+
+/// A proxy core fed from a recording, on a timeline where an instant stands
+/// for a position in the recording rather than wall time.
+struct Player<'a> {
+    recording: &'a Recording,
+    config: ProxyConfig,
+    core: ProxyCore,
+    origin: Instant,
+    next: usize,
+    /// Recording time the core has been brought up to.
+    position: f64,
+}
+
+impl<'a> Player<'a> {
+    fn new(recording: &'a Recording, config: ProxyConfig) -> Self {
+        let origin = Instant::now();
+        Self {
+            recording,
+            core: ProxyCore::new(config.clone(), recording.rows, recording.cols, origin),
+            config,
+            origin,
+            next: 0,
+            position: 0.0,
+        }
+    }
+
+    fn at(&self, time: f64) -> Instant {
+        self.origin + Duration::from_secs_f64(time.max(0.0))
+    }
+
+    fn time_of(&self, instant: Instant) -> f64 {
+        instant.saturating_duration_since(self.origin).as_secs_f64()
+    }
+
+    fn next_time(&self) -> Option<f64> {
+        self.recording.events.get(self.next).map(|(time, _)| *time)
+    }
+
+    fn is_done(&self) -> bool {
+        self.next >= self.recording.events.len()
+    }
+
+    /// When the core next needs attention: the next event or timer.
+    fn next_wakeup(&self) -> Option<f64> {
+        let timer = self.core.next_deadline().map(|d| self.time_of(d));
+        match (self.next_time(), timer) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Bring the core up to `target`, feeding events and running its timers
+    /// at the times they would have fired live. An infinite target feeds
+    /// every event but runs no timers after the last one.
+    fn advance(&mut self, target: f64) {
+        loop {
+            let event = self.next_time().filter(|&time| time <= target);
+            let limit = match event {
+                Some(time) => self.at(time),
+                None if target.is_finite() => self.at(target),
+                None => break,
+            };
+            while let Some(deadline) = self.core.next_deadline()
+                && deadline <= limit
+            {
+                self.core.tick(deadline);
+            }
+            if event.is_none() {
+                break;
+            }
+            self.feed_next();
+        }
+        if target.is_finite() {
+            self.position = self.position.max(target);
+        }
+    }
+
+    fn feed_next(&mut self) {
+        let Some((time, event)) = self.recording.events.get(self.next) else {
+            return;
+        };
+        let now = self.at(*time);
+        match event {
+            RecordedEvent::Output(data) => self.core.output(data, now),
+            RecordedEvent::Resize { cols, rows } => self.core.resize(*rows, *cols),
+        }
+        self.next += 1;
+        self.position = self.position.max(*time);
+    }
+
+    /// Jump to `target` and redraw the terminal to match. Seeking backwards
+    /// starts over with a fresh core since its state can't be rewound.
+    fn seek(&mut self, target: f64, out: &mut dyn Write) -> Result<()> {
+        let target = target.clamp(0.0, self.recording.duration());
+        let was_alt = self.core.in_alternate_screen();
+        if target < self.position {
+            self.core = ProxyCore::new(
+                self.config.clone(),
+                self.recording.rows,
+                self.recording.cols,
+                self.origin,
+            );
+            self.next = 0;
+            self.position = 0.0;
+        }
+        self.advance(target);
+        self.core.take_terminal_output();
+
+        let is_alt = self.core.in_alternate_screen();
+        if was_alt && !is_alt {
+            out.write_all(ALT_SCREEN_EXIT)?;
+        } else if is_alt && !was_alt {
+            out.write_all(ALT_SCREEN_ENTER)?;
+        }
+        out.write_all(CLEAR_SCREEN)?;
+        out.write_all(CURSOR_HOME)?;
+        self.core.redraw();
+        self.drain(out)
+    }
+
+    /// Write what the core queued for the terminal. There is no child, so
+    /// anything meant for the PTY is dropped, and hooks don't run.
+    fn drain(&mut self, out: &mut dyn Write) -> Result<()> {
+        self.core.take_pty_output();
+        self.core.take_events();
+        out.write_all(&self.core.take_terminal_output())?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// Recording time that advances with the wall clock at a given speed.
+struct Clock {
+    origin: Instant,
+    base: f64,
+    speed: f64,
+    paused: bool,
+}
+
+impl Clock {
+    fn new(position: f64, speed: f64) -> Self {
+        Self {
+            origin: Instant::now(),
+            base: position,
+            speed,
+            paused: false,
+        }
+    }
+
+    fn now(&self) -> f64 {
+        if self.paused {
+            self.base
+        } else {
+            self.base + self.origin.elapsed().as_secs_f64() * self.speed
+        }
+    }
+
+    fn set(&mut self, position: f64) {
+        self.base = position;
+        self.origin = Instant::now();
+    }
+
+    fn set_speed(&mut self, speed: f64) {
+        self.set(self.now());
+        self.speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+    }
+
+    fn toggle_pause(&mut self) {
+        self.set(self.now());
+        self.paused = !self.paused;
+    }
+
+    /// Wall time until the clock reaches `position`.
+    fn until(&self, position: f64) -> Option<Duration> {
+        if self.paused {
+            return None;
+        }
+        let secs = ((position - self.now()) / self.speed).max(0.0);
+        Some(Duration::from_secs_f64(secs))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Control {
+    Quit,
+    TogglePause,
+    Seek(f64),
+    Restart,
+    Faster,
+    Slower,
+}
+
+fn control_for(event: &InputEvent) -> Option<Control> {
+    let InputEvent::Key(KeyCombination { code, modifiers }) = event else {
+        return None;
+    };
+    if modifiers.ctrl {
+        return (*code == KeyCode::Char('c')).then_some(Control::Quit);
+    }
+    match code {
+        KeyCode::Char('q') => Some(Control::Quit),
+        KeyCode::Space | KeyCode::Char('p') => Some(Control::TogglePause),
+        KeyCode::Left => Some(Control::Seek(-SEEK_STEP_SECS)),
+        KeyCode::Right => Some(Control::Seek(SEEK_STEP_SECS)),
+        KeyCode::Home => Some(Control::Restart),
+        KeyCode::Up | KeyCode::Char('+') => Some(Control::Faster),
+        KeyCode::Down | KeyCode::Char('-') => Some(Control::Slower),
+        _ => None,
+    }
+}
+
+/// Play the recording on the terminal. Keys not used for playback control go
+/// to the proxy, so lookback mode works as in a live session.
+fn play(recording: &Recording, config: ProxyConfig, options: &ReplayOptions) -> Result<()> {
+    let stdin_fd = io::stdin();
+    let mut stdout = io::stdout().lock();
+    let _terminal_guard = TerminalGuard::new()?;
+
+    let mut player = Player::new(recording, config);
+    stdout.write_all(CLEAR_SCREEN)?;
+    stdout.write_all(CURSOR_HOME)?;
+    if options.start > 0.0 {
+        player.seek(options.start, &mut stdout)?;
+    }
+
+    let speed = match options.speed {
+        ReplaySpeed::Factor(factor) => factor,
+        ReplaySpeed::Max => {
+            while !player.is_done() {
+                player.feed_next();
+                player.drain(&mut stdout)?;
+            }
+            player.core.finish();
+            player.drain(&mut stdout)?;
+            return leave(&player, &mut stdout);
+        }
+    };
+    let mut clock = Clock::new(player.position, speed);
+    let mut decoder = InputDecoder::new();
+    let mut buf = [0u8; 4096];
+    // Without input, e.g. stdin redirected from a file, just play to the end
+    let mut stdin_open = true;
+
+    loop {
+        if !clock.paused {
+            player.advance(clock.now());
+            player.drain(&mut stdout)?;
+        }
+        if player.is_done() && !player.core.in_lookback_mode() {
+            player.core.finish();
+            player.drain(&mut stdout)?;
+            return leave(&player, &mut stdout);
+        }
+
+        let timeout_ms = player
+            .next_wakeup()
+            .and_then(|time| clock.until(time))
+            .map(|d| d.as_millis().min(100) as u16)
+            .unwrap_or(100);
+
+        let mut poll_fds = Vec::with_capacity(1);
+        if stdin_open {
+            poll_fds.push(PollFd::new(stdin_fd.as_fd(), PollFlags::POLLIN));
+        }
+        match poll(&mut poll_fds, PollTimeout::from(timeout_ms)) {
+            Ok(0) => continue,
+            Ok(_) => {}
+            Err(Errno::EINTR) => continue,
+            Err(e) => anyhow::bail!("poll failed: {}", e),
+        }
+        let n = match read(&stdin_fd, &mut buf) {
+            Ok(0) => {
+                stdin_open = false;
+                continue;
+            }
+            Ok(n) => n,
+            Err(Errno::EAGAIN) | Err(Errno::EINTR) => continue,
+            Err(e) => anyhow::bail!("read from stdin failed: {}", e),
+        };
+
+        // A read holds whole key sequences, so nothing needs to wait for the
+        // escape timeout
+        let mut inputs = decoder.feed(&buf[..n]);
+        inputs.extend(decoder.flush());
+        for input in inputs {
+            let control = if player.core.in_lookback_mode() {
+                None
+            } else {
+                control_for(&input.event)
+            };
+            match control {
+                Some(Control::Quit) => return leave(&player, &mut stdout),
+                Some(Control::TogglePause) => clock.toggle_pause(),
+                Some(Control::Seek(delta)) => {
+                    let target = (clock.now() + delta).max(0.0);
+                    player.seek(target, &mut stdout)?;
+                    clock.set(player.position);
+                }
+                Some(Control::Restart) => {
+                    player.seek(0.0, &mut stdout)?;
+                    clock.set(0.0);
+                }
+                Some(Control::Faster) => clock.set_speed(clock.speed * 2.0),
+                Some(Control::Slower) => clock.set_speed(clock.speed / 2.0),
+                None => {
+                    let now = player.at(clock.now());
+                    player.core.input(&input.raw, now);
+                    player.drain(&mut stdout)?;
+                }
+            }
+        }
+    }
+}
+
+/// Put the terminal back on the main screen before exiting.
+fn leave(player: &Player, out: &mut dyn Write) -> Result<()> {
+    if player.core.lookback_mouse_active() {
+        out.write_all(LOOKBACK_MOUSE_DISABLE)?;
+    }
+    if player.core.in_alternate_screen() {
+        out.write_all(ALT_SCREEN_EXIT)?;
+    }
+    out.write_all(b"\r\n")?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recording(events: &[(f64, &str)]) -> String {
+        let mut text = String::from("{\"version\": 2, \"width\": 20, \"height\": 5}\n");
+        for (time, data) in events {
+            text.push_str(&serde_json::to_string(&(time, "o", data)).unwrap());
+            text.push('\n');
+        }
+        text
+    }
+
+    fn redraw(text: &str) -> String {
+        format!("\x1b[?2026h\x1b[2J\x1b[H{}\x1b[?2026l", text)
+    }
+
+    #[test]
+    fn test_speed_parse() {
+        assert_eq!("max".parse(), Ok(ReplaySpeed::Max));
+        assert_eq!("2".parse(), Ok(ReplaySpeed::Factor(2.0)));
+        assert_eq!("0.5x".parse(), Ok(ReplaySpeed::Factor(0.5)));
+        assert!("0".parse::<ReplaySpeed>().is_err());
+        assert!("-1".parse::<ReplaySpeed>().is_err());
+        assert!("fast".parse::<ReplaySpeed>().is_err());
+    }
+
+    #[test]
+    fn test_parse_recording() {
+        let text = "{\"version\": 2, \"width\": 80, \"height\": 24}\n\
+                    [0.5, \"o\", \"hello\"]\n\
+                    [0.7, \"i\", \"x\"]\n\
+                    \n\
+                    [1.0, \"r\", \"100x30\"]\n";
+        let recording = Recording::parse(text.as_bytes()).unwrap();
+        assert_eq!((recording.cols, recording.rows), (80, 24));
+        assert_eq!(
+            recording.events,
+            vec![
+                (0.5, RecordedEvent::Output(b"hello".to_vec())),
+                (
+                    1.0,
+                    RecordedEvent::Resize {
+                        cols: 100,
+                        rows: 30
+                    }
+                ),
+            ]
+        );
+        assert_eq!(recording.duration(), 1.0);
+    }
+
+    #[test]
+    fn test_parse_recording_errors() {
+        assert!(Recording::parse("".as_bytes()).is_err());
+        assert!(
+            Recording::parse("{\"version\": 1, \"width\": 80, \"height\": 24}".as_bytes()).is_err()
+        );
+        assert!(Recording::parse("{\"version\": 2, \"width\": 80}".as_bytes()).is_err());
+        let bad_resize = "{\"version\": 2, \"width\": 80, \"height\": 24}\n[0, \"r\", \"big\"]\n";
+        assert!(Recording::parse(bad_resize.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_headless_stats() {
+        let text = recording(&[
+            (0.0, &redraw("one")),
+            (0.001, &redraw("two")),
+            (1.0, "line\r\n"),
+        ]);
+        let recording = Recording::parse(text.as_bytes()).unwrap();
+        let stats = replay_headless(&recording, ProxyConfig::default());
+        assert_eq!(stats.sync_blocks, 2);
+        assert_eq!(stats.full_redraws, 2);
+        // The two redraws are closer than the render delay, so they coalesce
+        assert_eq!(stats.renders, 2);
+        assert!(stats.terminal_bytes > 0);
+    }
+
+    #[test]
+    fn test_seek_back_matches_forward() {
+        let text = recording(&[(0.0, "one\r\n"), (1.0, "two\r\n"), (2.0, "three\r\n")]);
+        let recording = Recording::parse(text.as_bytes()).unwrap();
+        let mut sink = io::sink();
+
+        let mut player = Player::new(&recording, ProxyConfig::default());
+        player.seek(1.5, &mut sink).unwrap();
+        assert_eq!(player.next, 2);
+        player.seek(0.5, &mut sink).unwrap();
+        assert_eq!(player.next, 1);
+        assert_eq!(player.position, 0.5);
+        assert_eq!(player.core.stats().output_bytes, 5);
+    }
+
+    #[test]
+    fn test_clock_pause_and_speed() {
+        let mut clock = Clock::new(10.0, 2.0);
+        clock.toggle_pause();
+        let paused_at = clock.now();
+        assert_eq!(clock.now(), paused_at);
+        assert_eq!(clock.until(20.0), None);
+        clock.toggle_pause();
+        clock.set_speed(1000.0);
+        assert_eq!(clock.speed, MAX_SPEED);
+    }
+
+    #[test]
+    fn test_controls() {
+        let key = |code| {
+            InputEvent::Key(KeyCombination {
+                code,
+                modifiers: Default::default(),
+            })
+        };
+        assert_eq!(
+            control_for(&key(KeyCode::Space)),
+            Some(Control::TogglePause)
+        );
+        assert_eq!(
+            control_for(&key(KeyCode::Left)),
+            Some(Control::Seek(-SEEK_STEP_SECS))
+        );
+        assert_eq!(control_for(&key(KeyCode::Char('x'))), None);
+    }
+}
@@ -0,0 +1,619 @@
+//! Detachable sessions: where session sockets live and the protocol spoken
+//! over them.
+//!
+//! A proxy listens on `<session dir>/<id>.sock`. `claude-chill attach`
+//! connects, sends `Attach` with its terminal size and then forwards its
+//! keystrokes and resizes; the proxy sends back terminal output until the
+//! client is detached or the command exits. `claude-chill watch` sends
+//! `Watch` instead and only receives output.
+//!
+//! Every message is a frame: a one-byte tag, a big-endian `u32` payload
+//! length and the payload.
+
+use anyhow::{Context, Result};
+use log::debug;
+use std::fs::{self, DirBuilder};
+use std::io::{self, Read, Write};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const HEADER_LEN: usize = 5;
+/// Larger frames mean a confused or hostile peer.
+const MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+/// Output queued for a client that isn't reading; past this it's dropped.
+const MAX_CLIENT_BACKLOG: usize = 32 * 1024 * 1024;
+/// How long a departing client gets to take its last messages.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+const SOCKET_EXTENSION: &str = "sock";
+const CONTROL_EXTENSION: &str = "ctl";
+/// Names the session directory in place of the usual one.
+const SESSION_DIR_VAR: &str = "CLAUDE_CHILL_SESSION_DIR";
+
+const TAG_ATTACH: u8 = b'a';
+const TAG_INPUT: u8 = b'i';
+const TAG_RESIZE: u8 = b'r';
+const TAG_WATCH: u8 = b'w';
+const TAG_OUTPUT: u8 = b'o';
+const TAG_DETACHED: u8 = b'd';
+const TAG_EXITED: u8 = b'x';
+const TAG_REFUSED: u8 = b'f';
+
+/// A message in either direction.
+pub trait Message: Sized {
+    fn encode(&self) -> Vec<u8>;
+    fn decode(tag: u8, payload: &[u8]) -> Result<Self>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientMessage {
+    /// First message on a connection.
+    Attach {
+        rows: u16,
+        cols: u16,
+    },
+    Input(Vec<u8>),
+    Resize {
+        rows: u16,
+        cols: u16,
+    },
+    /// First message on a read-only connection.
+    Watch,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerMessage {
+    Output(Vec<u8>),
+    /// The client was detached, by the detach key or another client.
+    Detached,
+    /// The command exited with this code.
+    Exited(i32),
+    /// The session won't take this client.
+    Refused(String),
+}
+
+fn frame(tag: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.push(tag);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn size_payload(rows: u16, cols: u16) -> [u8; 4] {
+    let [r0, r1] = rows.to_be_bytes();
+    let [c0, c1] = cols.to_be_bytes();
+    [r0, r1, c0, c1]
+}
+
+fn parse_size(payload: &[u8]) -> Result<(u16, u16)> {
+    match payload {
+        [r0, r1, c0, c1] => Ok((
+            u16::from_be_bytes([*r0, *r1]),
+            u16::from_be_bytes([*c0, *c1]),
+        )),
+        _ => anyhow::bail!("bad size payload of {} bytes", payload.len()),
+    }
+}
+
+impl Message for ClientMessage {
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            ClientMessage::Attach { rows, cols } => frame(TAG_ATTACH, &size_payload(*rows, *cols)),
+            ClientMessage::Input(data) => frame(TAG_INPUT, data),
+            ClientMessage::Resize { rows, cols } => frame(TAG_RESIZE, &size_payload(*rows, *cols)),
+            ClientMessage::Watch => frame(TAG_WATCH, &[]),
+        }
+    }
+
+    fn decode(tag: u8, payload: &[u8]) -> Result<Self> {
+        Ok(match tag {
+            TAG_ATTACH => {
+                let (rows, cols) = parse_size(payload)?;
+                ClientMessage::Attach { rows, cols }
+            }
+            TAG_INPUT => ClientMessage::Input(payload.to_vec()),
+            TAG_RESIZE => {
+                let (rows, cols) = parse_size(payload)?;
+                ClientMessage::Resize { rows, cols }
+            }
+            TAG_WATCH => ClientMessage::Watch,
+            _ => anyhow::bail!("unknown client message tag {:#04x}", tag),
+        })
+    }
+}
+
+impl Message for ServerMessage {
+    fn encode(&self) -> Vec<u8> {
+        match self {
+            ServerMessage::Output(data) => frame(TAG_OUTPUT, data),
+            ServerMessage::Detached => frame(TAG_DETACHED, &[]),
+            ServerMessage::Exited(code) => frame(TAG_EXITED, &code.to_be_bytes()),
+            ServerMessage::Refused(reason) => frame(TAG_REFUSED, reason.as_bytes()),
+        }
+    }
+
+    fn decode(tag: u8, payload: &[u8]) -> Result<Self> {
+        Ok(match tag {
+            TAG_OUTPUT => ServerMessage::Output(payload.to_vec()),
+            TAG_DETACHED => ServerMessage::Detached,
+            TAG_EXITED => {
+                let code: [u8; 4] = payload.try_into().context("bad exit code payload")?;
+                ServerMessage::Exited(i32::from_be_bytes(code))
+            }
+            TAG_REFUSED => ServerMessage::Refused(String::from_utf8_lossy(payload).into_owned()),
+            _ => anyhow::bail!("unknown server message tag {:#04x}", tag),
+        })
+    }
+}
+
+/// Reassembles frames from reads that may split them anywhere.
+#[derive(Default)]
+pub struct FrameReader {
+    buffer: Vec<u8>,
+}
+
+impl FrameReader {
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// The next complete message, if one has arrived.
+    pub fn next_message<M: Message>(&mut self) -> Result<Option<M>> {
+        if self.buffer.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes(self.buffer[1..HEADER_LEN].try_into().unwrap()) as usize;
+        if len > MAX_FRAME_LEN {
+            anyhow::bail!("frame of {} bytes is too large", len);
+        }
+        if self.buffer.len() < HEADER_LEN + len {
+            return Ok(None);
+        }
+        let message = M::decode(self.buffer[0], &self.buffer[HEADER_LEN..HEADER_LEN + len]);
+        self.buffer.drain(..HEADER_LEN + len);
+        message.map(Some)
+    }
+}
+
+/// Directory holding the session sockets, private to the user.
+pub fn session_dir() -> PathBuf {
+    match std::env::var_os(SESSION_DIR_VAR) {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => match dirs::runtime_dir() {
+            Some(dir) => dir.join("claude-chill"),
+            None => {
+                std::env::temp_dir().join(format!("claude-chill-{}", unsafe { libc::getuid() }))
+            }
+        },
+    }
+}
+
+pub fn socket_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{}.{}", id, SOCKET_EXTENSION))
+}
+
+/// Where session `id` takes JSON control commands.
+pub fn control_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{}.{}", id, CONTROL_EXTENSION))
+}
+
+/// A nonblocking listener on `path`, creating `dir` private to the user.
+pub(crate) fn listen(dir: &Path, path: &Path) -> Result<UnixListener> {
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .with_context(|| format!("failed to create {}", dir.display()))?;
+    check_private(dir)?;
+    match UnixStream::connect(path) {
+        Ok(_) => anyhow::bail!("{} belongs to a running session", path.display()),
+        // Left behind by a session that died with the same id
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+            let _ = fs::remove_file(path);
+        }
+        Err(_) => {}
+    }
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("failed to listen on {}", path.display()))?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+/// Make sure `dir` is a real directory that only we can get into. In a
+/// shared temp dir someone else could have made it first, to listen in or
+/// type into our sessions.
+fn check_private(dir: &Path) -> Result<()> {
+    let metadata =
+        fs::symlink_metadata(dir).with_context(|| format!("failed to read {}", dir.display()))?;
+    anyhow::ensure!(
+        metadata.file_type().is_dir(),
+        "{} is not a directory",
+        dir.display()
+    );
+    anyhow::ensure!(
+        metadata.uid() == unsafe { libc::getuid() },
+        "{} is owned by another user",
+        dir.display()
+    );
+    let mode = metadata.mode() & 0o777;
+    anyhow::ensure!(
+        mode == 0o700,
+        "{} has mode {:o}, expected 700",
+        dir.display(),
+        mode
+    );
+    Ok(())
+}
+
+/// Ids of the sessions in `dir` that are still running, sorted. Sockets left
+/// behind by sessions that died are removed.
+pub fn list(dir: &Path) -> Result<Vec<String>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", dir.display())),
+    };
+    let mut ids = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let is_socket = entry.file_type().is_ok_and(|t| t.is_socket());
+        if !is_socket || path.extension().is_none_or(|ext| ext != SOCKET_EXTENSION) {
+            continue;
+        }
+        let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        match UnixStream::connect(&path) {
+            Ok(_) => ids.push(id.to_string()),
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                debug!("list: removing stale socket {}", path.display());
+                let _ = fs::remove_file(&path);
+            }
+            Err(e) => debug!("list: skipping {}: {}", path.display(), e),
+        }
+    }
+    ids.sort();
+    Ok(ids)
+}
+
+/// The session to attach to: `id` if given, otherwise the only one running.
+pub fn resolve(dir: &Path, id: Option<&str>) -> Result<PathBuf> {
+    if fs::symlink_metadata(dir).is_ok() {
+        check_private(dir)?;
+    }
+    if let Some(id) = id {
+        let path = socket_path(dir, id);
+        anyhow::ensure!(path.exists(), "no session {}", id);
+        return Ok(path);
+    }
+    let ids = list(dir)?;
+    match ids.as_slice() {
+        [] => anyhow::bail!("no sessions running"),
+        [id] => Ok(socket_path(dir, id)),
+        _ => anyhow::bail!("several sessions running, pick one of: {}", ids.join(", ")),
+    }
+}
+
+/// The listening end of a session. The socket is removed on drop by the
+/// process that created it.
+pub struct SessionServer {
+    listener: UnixListener,
+    id: String,
+    path: PathBuf,
+    owner: u32,
+}
+
+impl SessionServer {
+    /// Listen as session `id`, or as `id-2`, `id-3` and so on when a running
+    /// session already has it: ids are pids, which get reused while a
+    /// detached session lives on.
+    pub fn bind(dir: &Path, base: &str) -> Result<Self> {
+        let mut id = base.to_string();
+        for n in 2.. {
+            if UnixStream::connect(socket_path(dir, &id)).is_err() {
+                break;
+            }
+            id = format!("{}-{}", base, n);
+        }
+        let path = socket_path(dir, &id);
+        let listener = listen(dir, &path)?;
+        Ok(Self {
+            listener,
+            id,
+            path,
+            owner: std::process::id(),
+        })
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Take over the socket after forking, so this process cleans it up.
+    pub fn adopt(&mut self) {
+        self.owner = std::process::id();
+    }
+
+    pub fn listener(&self) -> &UnixListener {
+        &self.listener
+    }
+
+    /// Accept a waiting connection, or `None` if nobody is waiting. Its
+    /// first message should be `Attach`.
+    pub fn accept(&self) -> Result<Option<Connection>> {
+        let stream = match self.listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+            Err(e) => return Err(e).context("accept failed"),
+        };
+        stream.set_nonblocking(true)?;
+        Ok(Some(Connection {
+            stream,
+            reader: FrameReader::default(),
+            outgoing: Vec::new(),
+        }))
+    }
+}
+
+impl Drop for SessionServer {
+    fn drop(&mut self) {
+        if self.owner == std::process::id() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// The server's end of an attached client. Output is queued and written as
+/// the socket accepts it so a slow client never blocks the session.
+pub struct Connection {
+    stream: UnixStream,
+    reader: FrameReader,
+    outgoing: Vec<u8>,
+}
+
+impl Connection {
+    pub fn stream(&self) -> &UnixStream {
+        &self.stream
+    }
+
+    /// Whether output is waiting for the socket to become writable.
+    pub fn wants_write(&self) -> bool {
+        !self.outgoing.is_empty()
+    }
+
+    pub fn send(&mut self, message: &ServerMessage) -> Result<()> {
+        self.outgoing.extend_from_slice(&message.encode());
+        anyhow::ensure!(
+            self.outgoing.len() <= MAX_CLIENT_BACKLOG,
+            "client stopped reading"
+        );
+        self.flush()
+    }
+
+    /// Write as much queued output as the socket takes without blocking.
+    pub fn flush(&mut self) -> Result<()> {
+        while !self.outgoing.is_empty() {
+            match (&self.stream).write(&self.outgoing) {
+                Ok(0) => anyhow::bail!("client closed"),
+                Ok(n) => {
+                    self.outgoing.drain(..n);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e).context("write to client failed"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Send the last messages before hanging up, waiting briefly for the
+    /// client to take them.
+    pub fn close_with(mut self, message: &ServerMessage) {
+        self.outgoing.extend_from_slice(&message.encode());
+        let _ = self.stream.set_nonblocking(false);
+        let _ = self.stream.set_write_timeout(Some(CLOSE_TIMEOUT));
+        let _ = (&self.stream).write_all(&self.outgoing);
+    }
+
+    /// Messages that have arrived, or `None` once the client has gone.
+    pub fn receive(&mut self) -> Result<Option<Vec<ClientMessage>>> {
+        let mut buf = [0u8; 65536];
+        loop {
+            match (&self.stream).read(&mut buf) {
+                Ok(0) => return Ok(None),
+                Ok(n) => self.reader.push(&buf[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e).context("read from client failed"),
+            }
+        }
+        let mut messages = Vec::new();
+        while let Some(message) = self.reader.next_message()? {
+            messages.push(message);
+        }
+        Ok(Some(messages))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "claude-chill-session-test-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_messages_round_trip() {
+        let client = [
+            ClientMessage::Attach { rows: 24, cols: 80 },
+            ClientMessage::Input(b"hi\x1c".to_vec()),
+            ClientMessage::Resize {
+                rows: 300,
+                cols: 1000,
+            },
+            ClientMessage::Watch,
+        ];
+        let server = [
+            ServerMessage::Output(vec![0x1b, b'[', b'H']),
+            ServerMessage::Detached,
+            ServerMessage::Exited(-2),
+            ServerMessage::Refused("busy".to_string()),
+        ];
+        let mut reader = FrameReader::default();
+        for message in &client {
+            reader.push(&message.encode());
+        }
+        for message in &client {
+            assert_eq!(
+                reader.next_message::<ClientMessage>().unwrap().as_ref(),
+                Some(message)
+            );
+        }
+        assert_eq!(reader.next_message::<ClientMessage>().unwrap(), None);
+
+        // One byte at a time
+        for message in &server {
+            for byte in message.encode() {
+                assert_eq!(reader.next_message::<ServerMessage>().unwrap(), None);
+                reader.push(&[byte]);
+            }
+            assert_eq!(
+                reader.next_message::<ServerMessage>().unwrap().as_ref(),
+                Some(message)
+            );
+        }
+    }
+
+    #[test]
+    fn test_bad_frames() {
+        let mut reader = FrameReader::default();
+        reader.push(&frame(b'?', b""));
+        assert!(reader.next_message::<ClientMessage>().is_err());
+
+        let mut reader = FrameReader::default();
+        reader.push(&frame(TAG_RESIZE, b"abc"));
+        assert!(reader.next_message::<ClientMessage>().is_err());
+
+        let mut reader = FrameReader::default();
+        reader.push(&[TAG_OUTPUT, 0xff, 0xff, 0xff, 0xff]);
+        assert!(reader.next_message::<ServerMessage>().is_err());
+    }
+
+    #[test]
+    fn test_list_and_resolve() {
+        let dir = temp_dir("list");
+        assert!(list(&dir).unwrap().is_empty());
+        assert!(resolve(&dir, None).is_err());
+
+        let server = SessionServer::bind(&dir, "123").unwrap();
+        let stale = UnixListener::bind(socket_path(&dir, "456")).unwrap();
+        drop(stale);
+        assert!(socket_path(&dir, "456").exists());
+
+        assert_eq!(list(&dir).unwrap(), vec!["123".to_string()]);
+        assert!(!socket_path(&dir, "456").exists());
+        assert_eq!(resolve(&dir, None).unwrap(), socket_path(&dir, "123"));
+        assert!(resolve(&dir, Some("456")).is_err());
+
+        drop(server);
+        assert!(!socket_path(&dir, "123").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_bind_keeps_running_sessions() {
+        let dir = temp_dir("reuse");
+        let running = SessionServer::bind(&dir, "7").unwrap();
+        let stale = UnixListener::bind(socket_path(&dir, "7-2")).unwrap();
+        drop(stale);
+
+        // The running session keeps its id and the stale socket goes
+        let next = SessionServer::bind(&dir, "7").unwrap();
+        assert_eq!(next.id(), "7-2");
+        assert_eq!(running.id(), "7");
+        assert!(UnixStream::connect(socket_path(&dir, "7")).is_ok());
+        assert!(listen(&dir, &socket_path(&dir, "7")).is_err());
+        assert_eq!(
+            list(&dir).unwrap(),
+            vec!["7".to_string(), "7-2".to_string()]
+        );
+
+        drop(running);
+        assert_eq!(SessionServer::bind(&dir, "7").unwrap().id(), "7");
+        drop(next);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_accept_and_exchange() {
+        let dir = temp_dir("accept");
+        let server = SessionServer::bind(&dir, "1").unwrap();
+        assert!(server.accept().unwrap().is_none());
+
+        let mut client = UnixStream::connect(socket_path(&dir, "1")).unwrap();
+        client
+            .write_all(&ClientMessage::Attach { rows: 10, cols: 40 }.encode())
+            .unwrap();
+        client
+            .write_all(&ClientMessage::Input(b"x".to_vec()).encode())
+            .unwrap();
+        let mut connection = server.accept().unwrap().unwrap();
+        assert_eq!(
+            connection.receive().unwrap(),
+            Some(vec![
+                ClientMessage::Attach { rows: 10, cols: 40 },
+                ClientMessage::Input(b"x".to_vec())
+            ])
+        );
+
+        connection
+            .send(&ServerMessage::Output(b"out".to_vec()))
+            .unwrap();
+        connection.close_with(&ServerMessage::Exited(3));
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).unwrap();
+        let mut reader = FrameReader::default();
+        reader.push(&received);
+        assert_eq!(
+            reader.next_message::<ServerMessage>().unwrap(),
+            Some(ServerMessage::Output(b"out".to_vec()))
+        );
+        assert_eq!(
+            reader.next_message::<ServerMessage>().unwrap(),
+            Some(ServerMessage::Exited(3))
+        );
+        drop(server);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_refuse_shared_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir("shared");
+        fs::create_dir(&dir).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(SessionServer::bind(&dir, "1").is_err());
+        assert!(resolve(&dir, None).is_err());
+
+        // Not even through a symlink to a private one
+        let private = temp_dir("private");
+        let link = temp_dir("link");
+        SessionServer::bind(&private, "1").unwrap();
+        std::os::unix::fs::symlink(&private, &link).unwrap();
+        assert!(SessionServer::bind(&link, "2").is_err());
+
+        for dir in [dir, private, link] {
+            let _ = fs::remove_dir_all(&dir).or_else(|_| fs::remove_file(&dir));
+        }
+    }
+}
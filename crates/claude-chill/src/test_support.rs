@@ -0,0 +1,30 @@
+//! Fixtures shared by unit tests.
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+/// A writer whose output can still be read after it has been boxed up and
+/// handed to a recorder.
+#[derive(Clone, Default)]
+pub struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Everything written to `buffer`, parsed as JSON lines.
+pub fn lines(buffer: &SharedBuffer) -> Vec<serde_json::Value> {
+    let data = buffer.0.lock().unwrap().clone();
+    String::from_utf8(data)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
@@ -0,0 +1,273 @@
+//! Notices when the child looks wedged, e.g. on a hung tool: no output for a
+//! long time while not waiting at a prompt, or one CPU pegged for a long
+//! time according to `/proc`.
+//!
+//! `Watchdog` only decides; `CpuMonitor` reads `/proc` and the proxy shows
+//! the banner and sends the signal.
+
+use crate::config::WatchdogConfig;
+use crate::detector::ActivityState;
+use anyhow::{Result, anyhow};
+use nix::sys::signal::Signal;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const CPU_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Why the child looks hung.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hang {
+    /// No output for this long while not at a prompt.
+    Silent(Duration),
+    /// Busy on the CPU for this long.
+    Busy(Duration),
+}
+
+impl Hang {
+    pub fn description(self) -> String {
+        match self {
+            Hang::Silent(duration) => format!("no output for {}", format_duration(duration)),
+            Hang::Busy(duration) => format!("busy on the CPU for {}", format_duration(duration)),
+        }
+    }
+}
+
+pub struct Watchdog {
+    silence_timeout: Option<Duration>,
+    busy_timeout: Option<Duration>,
+    busy_cpu_percent: f64,
+    signal: Option<Signal>,
+    /// Whether the current quiet spell has been reported.
+    silence_reported: bool,
+    /// Start of the current run of busy CPU samples.
+    busy_since: Option<Instant>,
+    busy_reported: bool,
+}
+
+impl Watchdog {
+    pub fn new(config: &WatchdogConfig) -> Self {
+        let timeout = |ms| (ms > 0).then(|| Duration::from_millis(ms));
+        Self {
+            silence_timeout: timeout(config.silence_timeout_ms),
+            busy_timeout: timeout(config.busy_timeout_ms),
+            busy_cpu_percent: f64::from(config.busy_cpu_percent),
+            signal: config
+                .signal
+                .as_deref()
+                .and_then(|name| parse_signal(name).ok()),
+            silence_reported: false,
+            busy_since: None,
+            busy_reported: false,
+        }
+    }
+
+    /// Whether CPU usage is watched, and so worth sampling.
+    pub fn watches_cpu(&self) -> bool {
+        self.busy_timeout.is_some()
+    }
+
+    /// Signal to send the child when it looks hung, if any.
+    pub fn signal(&self) -> Option<Signal> {
+        self.signal
+    }
+
+    /// Check on the child given how long it has been quiet, what it appears
+    /// to be doing, and a new CPU sample if there is one. Each hang is
+    /// reported once, until the child recovers.
+    pub fn check(
+        &mut self,
+        now: Instant,
+        idle: Option<Duration>,
+        activity: Option<ActivityState>,
+        cpu_percent: Option<f64>,
+    ) -> Option<Hang> {
+        if let Some(percent) = cpu_percent {
+            if percent >= self.busy_cpu_percent {
+                self.busy_since.get_or_insert(now);
+            } else {
+                self.busy_since = None;
+                self.busy_reported = false;
+            }
+        }
+        if let (Some(timeout), Some(since)) = (self.busy_timeout, self.busy_since) {
+            let busy = now.saturating_duration_since(since);
+            if busy >= timeout && !self.busy_reported {
+                self.busy_reported = true;
+                return Some(Hang::Busy(busy));
+            }
+        }
+
+        let timeout = self.silence_timeout?;
+        let idle = idle?;
+        if idle < timeout {
+            self.silence_reported = false;
+            return None;
+        }
+        // Waiting on the user is no reason to worry
+        let waiting = matches!(
+            activity,
+            Some(ActivityState::IdleAtPrompt | ActivityState::WaitingForApproval)
+        );
+        if waiting || self.silence_reported {
+            return None;
+        }
+        self.silence_reported = true;
+        Some(Hang::Silent(idle))
+    }
+}
+
+/// Samples a process's CPU usage from `/proc/<pid>/stat`. Without `/proc`
+/// there are simply no samples.
+pub struct CpuMonitor {
+    stat_path: PathBuf,
+    ticks_per_sec: f64,
+    /// Time and CPU ticks of the last sample.
+    last: Option<(Instant, u64)>,
+}
+
+impl CpuMonitor {
+    pub fn new(pid: u32) -> Self {
+        let ticks_per_sec = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        Self {
+            stat_path: PathBuf::from(format!("/proc/{}/stat", pid)),
+            ticks_per_sec: if ticks_per_sec > 0 {
+                ticks_per_sec as f64
+            } else {
+                100.0
+            },
+            last: None,
+        }
+    }
+
+    /// Percent of one CPU used since the last sample, taken at most once per
+    /// `CPU_SAMPLE_INTERVAL`.
+    pub fn sample(&mut self, now: Instant) -> Option<f64> {
+        if let Some((at, _)) = self.last
+            && now.saturating_duration_since(at) < CPU_SAMPLE_INTERVAL
+        {
+            return None;
+        }
+        let ticks = parse_cpu_ticks(&fs::read_to_string(&self.stat_path).ok()?)?;
+        let (at, before) = self.last.replace((now, ticks))?;
+        let elapsed = now.saturating_duration_since(at).as_secs_f64();
+        let used = ticks.saturating_sub(before) as f64 / self.ticks_per_sec;
+        Some(used / elapsed * 100.0)
+    }
+}
+
+/// User plus system CPU ticks from the contents of `/proc/<pid>/stat`.
+fn parse_cpu_ticks(stat: &str) -> Option<u64> {
+    // The command name can contain spaces and parens, so count fields from
+    // the last `)`: state is the first after it, utime the 12th, stime the
+    // 13th
+    let mut fields = stat[stat.rfind(')')? + 1..].split_whitespace().skip(11);
+    let utime: u64 = fields.next()?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+    Some(utime + stime)
+}
+
+/// Parse a signal name such as `SIGINT`, `int` or `TERM`.
+pub fn parse_signal(name: &str) -> Result<Signal> {
+    let name = name.trim().to_ascii_uppercase();
+    let name = if name.starts_with("SIG") {
+        name
+    } else {
+        format!("SIG{}", name)
+    };
+    name.parse()
+        .map_err(|_| anyhow!("unknown signal '{}'", name))
+}
+
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs >= 60 {
+        format!("{}m", secs / 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watchdog(silence_timeout_ms: u64, busy_timeout_ms: u64) -> Watchdog {
+        Watchdog::new(&WatchdogConfig {
+            silence_timeout_ms,
+            busy_timeout_ms,
+            ..WatchdogConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_silence() {
+        let mut dog = watchdog(60_000, 0);
+        let now = Instant::now();
+        let secs = |s| Some(Duration::from_secs(s));
+        assert_eq!(dog.check(now, None, None, None), None);
+        assert_eq!(dog.check(now, secs(59), None, None), None);
+        assert_eq!(
+            dog.check(now, secs(60), Some(ActivityState::Working), None),
+            Some(Hang::Silent(Duration::from_secs(60)))
+        );
+        // Once per quiet spell
+        assert_eq!(dog.check(now, secs(120), None, None), None);
+        assert_eq!(dog.check(now, secs(1), None, None), None);
+        // Sitting at a prompt is fine
+        assert_eq!(
+            dog.check(now, secs(600), Some(ActivityState::IdleAtPrompt), None),
+            None
+        );
+        assert_eq!(
+            dog.check(now, secs(600), None, None),
+            Some(Hang::Silent(Duration::from_secs(600)))
+        );
+    }
+
+    #[test]
+    fn test_busy() {
+        let mut dog = watchdog(0, 10_000);
+        let start = Instant::now();
+        let at = |s| start + Duration::from_secs(s);
+        assert!(dog.watches_cpu());
+        assert_eq!(dog.check(at(0), None, None, Some(99.0)), None);
+        assert_eq!(dog.check(at(5), None, None, None), None);
+        assert_eq!(
+            dog.check(at(10), None, None, Some(120.0)),
+            Some(Hang::Busy(Duration::from_secs(10)))
+        );
+        assert_eq!(dog.check(at(20), None, None, Some(100.0)), None);
+        // Calming down starts over
+        assert_eq!(dog.check(at(21), None, None, Some(3.0)), None);
+        assert_eq!(dog.check(at(22), None, None, Some(100.0)), None);
+        assert_eq!(
+            dog.check(at(32), None, None, Some(100.0)),
+            Some(Hang::Busy(Duration::from_secs(10)))
+        );
+        assert!(!watchdog(0, 0).watches_cpu());
+    }
+
+    #[test]
+    fn test_parse_cpu_ticks() {
+        let stat = "1234 (node (x) y) R 1 1234 1234 0 -1 4194560 100 0 0 0 250 30 0 0 20 0";
+        assert_eq!(parse_cpu_ticks(stat), Some(280));
+        assert_eq!(parse_cpu_ticks("1234 (node) R 1"), None);
+        let mut monitor = CpuMonitor::new(std::process::id());
+        let now = Instant::now();
+        assert_eq!(monitor.sample(now), None);
+        assert_eq!(monitor.sample(now + Duration::from_millis(10)), None);
+        // Only Linux has `/proc/<pid>/stat` to sample
+        assert_eq!(
+            monitor.sample(now + CPU_SAMPLE_INTERVAL).is_some(),
+            cfg!(target_os = "linux")
+        );
+    }
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(parse_signal("SIGINT").unwrap(), Signal::SIGINT);
+        assert_eq!(parse_signal("term").unwrap(), Signal::SIGTERM);
+        assert!(parse_signal("SIGNOPE").is_err());
+    }
+}
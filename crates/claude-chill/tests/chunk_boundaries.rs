@@ -0,0 +1,283 @@
+//! Chunk-boundary invariance for everything that parses the child's output.
+//!
+//! The proxy sees whatever `read` returns, so an escape sequence can arrive
+//! split across any number of calls. Each parser here must end up in the same
+//! state, and produce the same bytes, whether it is fed the whole input at
+//! once or the same input cut at arbitrary points.
+
+use claude_chill::escape_filter::TerminalQueryFilter;
+use claude_chill::escape_sequences::{
+    ALT_SCREEN_ENTER, ALT_SCREEN_ENTER_LEGACY, ALT_SCREEN_EXIT, ALT_SCREEN_EXIT_LEGACY,
+    CLEAR_SCREEN, CLEAR_SCROLLBACK, CURSOR_HOME, SYNC_END, SYNC_START,
+};
+use claude_chill::line_buffer::LineBuffer;
+use claude_chill::proxy_core::{ProxyConfig, ProxyCore, ProxyStats};
+use claude_chill::replay::{RecordedEvent, Recording};
+use proptest::prelude::*;
+use std::path::PathBuf;
+use std::time::Instant;
+
+const ROWS: u16 = 24;
+const COLS: u16 = 80;
+const MAX_HISTORY_LINES: usize = 50;
+
+/// Sequences the core looks for in the stream rather than in a buffered
+/// sync block, so a read can split them.
+const MARKERS: [&[u8]; 6] = [
+    SYNC_START,
+    SYNC_END,
+    ALT_SCREEN_ENTER,
+    ALT_SCREEN_EXIT,
+    ALT_SCREEN_ENTER_LEGACY,
+    ALT_SCREEN_EXIT_LEGACY,
+];
+
+/// Pieces of output that exercise every parser's special cases.
+const TOKENS: &[&[u8]] = &[
+    b"hello",
+    b"line of text\r\n",
+    b"\r\n",
+    b"\n",
+    b"\x1b[31mred\x1b[0m",
+    b"\x1b[2;5H",
+    b"\x1b[K",
+    b"\xe2\x94\x82 \xe2\x9c\xbb",
+    SYNC_START,
+    SYNC_END,
+    CLEAR_SCREEN,
+    CLEAR_SCROLLBACK,
+    CURSOR_HOME,
+    ALT_SCREEN_ENTER,
+    ALT_SCREEN_EXIT,
+    ALT_SCREEN_ENTER_LEGACY,
+    ALT_SCREEN_EXIT_LEGACY,
+    b"\x1b[c",
+    b"\x1b[>0c",
+    b"\x1b[=c",
+    b"\x1b[6n",
+    b"\x1b[?6n",
+    b"\x1b]11;?\x07",
+    b"\x1b]10;?\x1b\\",
+    b"\x1b]0;title\x07",
+    b"\x1b",
+    b"\x1b[",
+    b"\x1b[?",
+];
+
+fn stream() -> impl Strategy<Value = Vec<u8>> {
+    let token = prop_oneof![
+        4 => prop::sample::select(TOKENS).prop_map(<[u8]>::to_vec),
+        1 => prop::collection::vec(any::<u8>(), 1..4),
+    ];
+    prop::collection::vec(token, 0..40).prop_map(|tokens| tokens.concat())
+}
+
+/// A stream plus sorted, distinct cut points inside it.
+fn stream_with_splits() -> impl Strategy<Value = (Vec<u8>, Vec<usize>)> {
+    stream().prop_flat_map(|data| {
+        let len = data.len();
+        let splits = prop::collection::btree_set(1..len.max(2), 0..len.min(16) + 1)
+            .prop_map(move |splits| splits.into_iter().filter(|&s| s < len).collect());
+        (Just(data), splits)
+    })
+}
+
+fn chunks<'a>(data: &'a [u8], splits: &[usize]) -> Vec<&'a [u8]> {
+    let mut chunks = Vec::with_capacity(splits.len() + 1);
+    let mut start = 0;
+    for &split in splits {
+        chunks.push(&data[start..split]);
+        start = split;
+    }
+    chunks.push(&data[start..]);
+    chunks
+}
+
+fn filter_chunks(chunks: &[&[u8]]) -> Vec<u8> {
+    let mut filter = TerminalQueryFilter::new();
+    let mut output = Vec::new();
+    for chunk in chunks {
+        output.extend_from_slice(&filter.filter(chunk));
+    }
+    output.extend_from_slice(&filter.flush());
+    output
+}
+
+#[derive(Debug, PartialEq)]
+struct LineBufferState {
+    lines: usize,
+    bytes: usize,
+    contents: Vec<u8>,
+}
+
+fn line_buffer_chunks(chunks: &[&[u8]]) -> LineBufferState {
+    let mut buffer = LineBuffer::new(MAX_HISTORY_LINES);
+    for chunk in chunks {
+        buffer.push_bytes(chunk);
+    }
+    let mut contents = Vec::new();
+    buffer.append_all(&mut contents);
+    LineBufferState {
+        lines: buffer.line_count(),
+        bytes: buffer.total_bytes(),
+        contents,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct CoreState {
+    terminal: Vec<u8>,
+    stats: ProxyStats,
+    in_alternate_screen: bool,
+    screen: Vec<u8>,
+}
+
+/// Feed output to a core without letting any timer fire in between, so the
+/// only thing that can differ between splits is how the chunks were parsed.
+fn core_chunks(chunks: &[&[u8]]) -> CoreState {
+    let config = ProxyConfig {
+        max_history_lines: MAX_HISTORY_LINES,
+        auto_lookback_timeout_ms: 0,
+        ..ProxyConfig::default()
+    };
+    let now = Instant::now();
+    let mut core = ProxyCore::new(config, ROWS, COLS, now);
+    let mut terminal = Vec::new();
+    for chunk in chunks {
+        core.output(chunk, now);
+        terminal.extend_from_slice(&core.take_terminal_output());
+    }
+    core.finish();
+    terminal.extend_from_slice(&core.take_terminal_output());
+    let mut stats = core.stats().clone();
+    // Reads are counted as they arrive, which is the one thing splits change
+    stats.output_bytes = 0;
+    CoreState {
+        terminal,
+        stats,
+        in_alternate_screen: core.in_alternate_screen(),
+        screen: core.screen().contents_formatted(),
+    }
+}
+
+/// Concatenated output of every recording in `tests/golden/`.
+fn recordings() -> Vec<(String, Vec<u8>)> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "cast"))
+        .collect();
+    paths.sort();
+    paths
+        .iter()
+        .map(|path| {
+            let recording = Recording::load(path).unwrap();
+            let mut data = Vec::new();
+            for (_, event) in recording.events {
+                if let RecordedEvent::Output(bytes) = event {
+                    data.extend_from_slice(&bytes);
+                }
+            }
+            (
+                path.file_name().unwrap().to_string_lossy().into_owned(),
+                data,
+            )
+        })
+        .collect()
+}
+
+proptest! {
+    #[test]
+    fn filter_ignores_chunk_boundaries((data, splits) in stream_with_splits()) {
+        prop_assert_eq!(filter_chunks(&chunks(&data, &splits)), filter_chunks(&[&data]));
+    }
+
+    #[test]
+    fn line_buffer_ignores_chunk_boundaries((data, splits) in stream_with_splits()) {
+        prop_assert_eq!(
+            line_buffer_chunks(&chunks(&data, &splits)),
+            line_buffer_chunks(&[&data])
+        );
+    }
+
+    #[test]
+    fn core_ignores_chunk_boundaries((data, splits) in stream_with_splits()) {
+        prop_assert_eq!(core_chunks(&chunks(&data, &splits)), core_chunks(&[&data]));
+    }
+
+    #[test]
+    fn core_ignores_byte_at_a_time_reads(data in stream()) {
+        let bytes: Vec<&[u8]> = data.chunks(1).collect();
+        prop_assert_eq!(core_chunks(&bytes), core_chunks(&[&data]));
+    }
+}
+
+#[test]
+fn every_marker_split_at_every_offset() {
+    for first in MARKERS {
+        for second in MARKERS {
+            let data = [b"a\r\n", first, b"b\r\n", second, b"c\r\n"].concat();
+            let whole = core_chunks(&[&data]);
+            for split in 1..data.len() {
+                assert_eq!(
+                    core_chunks(&[&data[..split], &data[split..]]),
+                    whole,
+                    "{:?} split at {}",
+                    String::from_utf8_lossy(&data),
+                    split
+                );
+            }
+        }
+    }
+}
+
+/// Every offset that falls inside a marker.
+fn marker_splits(data: &[u8]) -> Vec<usize> {
+    let mut splits: Vec<usize> = MARKERS
+        .iter()
+        .flat_map(|marker| {
+            data.windows(marker.len())
+                .enumerate()
+                .filter(move |(_, window)| window == marker)
+                .flat_map(move |(pos, _)| pos + 1..pos + marker.len())
+        })
+        .collect();
+    splits.sort_unstable();
+    splits.dedup();
+    splits
+}
+
+#[test]
+fn recordings_split_at_every_offset() {
+    for (name, data) in recordings() {
+        let filtered = filter_chunks(&[&data]);
+        for split in 1..data.len() {
+            assert_eq!(
+                filter_chunks(&[&data[..split], &data[split..]]),
+                filtered,
+                "{} split at {}",
+                name,
+                split
+            );
+        }
+    }
+}
+
+/// Feeding a whole recording through a core is slow enough that only splits
+/// through a marker are tried here; random splits cover the rest.
+#[test]
+fn recordings_split_inside_every_marker() {
+    for (name, data) in recordings() {
+        let core = core_chunks(&[&data]);
+        for split in marker_splits(&data) {
+            assert_eq!(
+                core_chunks(&[&data[..split], &data[split..]]),
+                core,
+                "{} split at {}",
+                name,
+                split
+            );
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! Golden-screen regression tests.
+//!
+//! Each recording in `tests/golden/` is an asciicast v2 file of a child's
+//! raw output, as written by `--record-raw`. It is fed through a `ProxyCore`
+//! in chunks, what the proxy writes to the terminal is fed into a second VT
+//! emulator, and after every frame that emulator's screen must match one fed
+//! the child's output directly. The same recording is replayed with many
+//! different chunk splits, since the proxy sees whatever `read` returns.
+//!
+//! To add a case, record a session with `claude-chill --record-raw` and drop
+//! the file into `tests/golden/`.
+
+use claude_chill::proxy_core::{ProxyConfig, ProxyCore};
+use claude_chill::replay::{RecordedEvent, Recording};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Long enough for any pending render to be due.
+const SETTLE: Duration = Duration::from_secs(1);
+const RANDOM_SPLITS: u64 = 20;
+
+/// A recording flattened into output bytes and the resizes between them.
+enum Step {
+    Output(Vec<u8>),
+    Resize { cols: u16, rows: u16 },
+}
+
+struct Golden {
+    name: String,
+    cols: u16,
+    rows: u16,
+    steps: Vec<Step>,
+    /// Original event boundaries as offsets into the concatenated output.
+    event_splits: Vec<usize>,
+}
+
+impl Golden {
+    fn load(path: &Path) -> Self {
+        let recording = Recording::load(path).unwrap();
+        let mut steps = Vec::new();
+        let mut event_splits = Vec::new();
+        let mut offset = 0;
+        for (_, event) in recording.events {
+            match event {
+                RecordedEvent::Output(data) => {
+                    offset += data.len();
+                    event_splits.push(offset);
+                    match steps.last_mut() {
+                        Some(Step::Output(bytes)) => bytes.extend_from_slice(&data),
+                        _ => steps.push(Step::Output(data)),
+                    }
+                }
+                RecordedEvent::Resize { cols, rows } => steps.push(Step::Resize { cols, rows }),
+            }
+        }
+        Self {
+            name: path.file_name().unwrap().to_string_lossy().into_owned(),
+            cols: recording.cols,
+            rows: recording.rows,
+            steps,
+            event_splits,
+        }
+    }
+
+    fn output_len(&self) -> usize {
+        self.steps
+            .iter()
+            .map(|step| match step {
+                Step::Output(data) => data.len(),
+                Step::Resize { .. } => 0,
+            })
+            .sum()
+    }
+}
+
+/// Runs one split of a recording through a core and checks every frame.
+struct Run<'a> {
+    golden: &'a Golden,
+    label: String,
+    core: ProxyCore,
+    /// The child's screen, fed the raw output.
+    child: vt100::Parser,
+    /// The user's screen, fed what the proxy wrote.
+    terminal: vt100::Parser,
+    now: Instant,
+    frames: usize,
+}
+
+impl<'a> Run<'a> {
+    fn new(golden: &'a Golden, label: String) -> Self {
+        let config = ProxyConfig {
+            auto_lookback_timeout_ms: 0,
+            ..ProxyConfig::default()
+        };
+        let now = Instant::now();
+        Self {
+            golden,
+            label,
+            core: ProxyCore::new(config, golden.rows, golden.cols, now),
+            child: vt100::Parser::new(golden.rows, golden.cols, 0),
+            terminal: vt100::Parser::new(golden.rows, golden.cols, 0),
+            now,
+            frames: 0,
+        }
+    }
+
+    /// Feed the recording, cutting output at the given offsets, and compare
+    /// screens once the proxy has rendered each chunk.
+    fn check(mut self, splits: &[usize]) {
+        let mut offset = 0;
+        let mut splits = splits.iter().copied().peekable();
+        for step in &self.golden.steps {
+            match step {
+                Step::Resize { cols, rows } => {
+                    self.child.screen_mut().set_size(*rows, *cols);
+                    self.terminal.screen_mut().set_size(*rows, *cols);
+                    self.core.resize(*rows, *cols);
+                    self.settle();
+                }
+                Step::Output(data) => {
+                    let end = offset + data.len();
+                    let mut start = offset;
+                    while start < end {
+                        let mut cut = end;
+                        while let Some(&split) = splits.peek() {
+                            if split <= start {
+                                splits.next();
+                                continue;
+                            }
+                            cut = cut.min(split);
+                            break;
+                        }
+                        self.feed(&data[start - offset..cut - offset]);
+                        start = cut;
+                    }
+                    offset = end;
+                }
+            }
+        }
+        self.core.finish();
+        self.settle();
+        assert!(self.frames > 0, "{}: no frames compared", self.label);
+    }
+
+    fn feed(&mut self, chunk: &[u8]) {
+        self.child.process(chunk);
+        self.core.output(chunk, self.now);
+        self.settle();
+    }
+
+    /// Let the proxy's render timer fire, apply its output and compare.
+    fn settle(&mut self) {
+        self.now += SETTLE;
+        self.core.tick(self.now);
+        self.terminal.process(&self.core.take_terminal_output());
+        self.compare();
+    }
+
+    fn compare(&mut self) {
+        self.frames += 1;
+        let child = self.child.screen();
+        let terminal = self.terminal.screen();
+        let (rows, cols) = child.size();
+        let child_rows: Vec<Vec<u8>> = child.rows_formatted(0, cols).collect();
+        let terminal_rows: Vec<Vec<u8>> = terminal.rows_formatted(0, cols).collect();
+        for row in 0..rows as usize {
+            assert_eq!(
+                String::from_utf8_lossy(&terminal_rows[row]),
+                String::from_utf8_lossy(&child_rows[row]),
+                "{} ({}): row {} differs at frame {}\nchild:\n{}\nterminal:\n{}",
+                self.golden.name,
+                self.label,
+                row,
+                self.frames,
+                child.contents(),
+                terminal.contents(),
+            );
+        }
+        assert_eq!(
+            terminal.cursor_position(),
+            child.cursor_position(),
+            "{} ({}): cursor differs at frame {}",
+            self.golden.name,
+            self.label,
+            self.frames
+        );
+        assert_eq!(
+            terminal.hide_cursor(),
+            child.hide_cursor(),
+            "{} ({}): cursor visibility differs at frame {}",
+            self.golden.name,
+            self.label,
+            self.frames
+        );
+    }
+}
+
+/// Small deterministic generator so failures reproduce without a seed file.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+fn random_splits(len: usize, seed: u64) -> Vec<usize> {
+    let mut rng = XorShift(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1);
+    let max_chunk = 1 + (rng.next() % 256) as usize;
+    let mut splits = Vec::new();
+    let mut pos = 0;
+    while pos < len {
+        pos += 1 + (rng.next() as usize % max_chunk);
+        splits.push(pos);
+    }
+    splits
+}
+
+fn goldens() -> Vec<Golden> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "cast"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no recordings in {}", dir.display());
+    paths.iter().map(|path| Golden::load(path)).collect()
+}
+
+#[test]
+fn golden_screens_with_recorded_splits() {
+    for golden in goldens() {
+        Run::new(&golden, "recorded".to_string()).check(&golden.event_splits);
+    }
+}
+
+#[test]
+fn golden_screens_byte_at_a_time() {
+    for golden in goldens() {
+        let splits: Vec<usize> = (1..=golden.output_len()).collect();
+        Run::new(&golden, "bytewise".to_string()).check(&splits);
+    }
+}
+
+#[test]
+fn golden_screens_with_random_splits() {
+    for golden in goldens() {
+        for seed in 0..RANDOM_SPLITS {
+            let splits = random_splits(golden.output_len(), seed);
+            Run::new(&golden, format!("seed {}", seed)).check(&splits);
+        }
+    }
+}
@@ -0,0 +1,753 @@
+//! End-to-end tests that run the `claude-chill` binary in a PTY owned by the
+//! test, type into it and read the screen back through a VT emulator.
+//!
+//! The alt screen tests use `fake-claude`, which `cargo test --workspace`
+//! builds alongside this crate.
+
+use nix::pty::{Winsize, openpty};
+use nix::unistd::{read, write};
+use std::io::{BufRead, BufReader, Write};
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, channel};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const ROWS: u16 = 24;
+const COLS: u16 = 80;
+const TIMEOUT: Duration = Duration::from_secs(10);
+const PROMPT: &str = "TEST> ";
+const LOOKBACK_KEY: &[u8] = b"\x1e";
+const CTRL_C: &[u8] = b"\x03";
+const DETACH_KEY: &[u8] = b"\x1c";
+
+/// `claude-chill` running a command in a PTY, with the screen it has drawn.
+struct Session {
+    child: Child,
+    master: OwnedFd,
+    output: Receiver<Vec<u8>>,
+    screen: vt100::Parser,
+    /// Config and session directory, so the user's config file and sessions
+    /// aren't picked up.
+    home: PathBuf,
+}
+
+impl Session {
+    fn spawn(command: &[&str]) -> Self {
+        Self::spawn_with_env(command, &[])
+    }
+
+    fn spawn_with_env(command: &[&str], env: &[(&str, &str)]) -> Self {
+        let mut args = vec!["--auto-lookback-timeout", "0", "--"];
+        args.extend_from_slice(command);
+        Self::spawn_args(&args, env)
+    }
+
+    /// `claude-chill attach` for a session started by `host`.
+    fn attach(host: &Session) -> Self {
+        Self::connect(host, "attach")
+    }
+
+    /// `claude-chill watch` for a session started by `host`.
+    fn watch(host: &Session) -> Self {
+        Self::connect(host, "watch")
+    }
+
+    fn connect(host: &Session, subcommand: &str) -> Self {
+        let id = host.child.id().to_string();
+        let session_dir = host.session_dir();
+        Self::spawn_args(
+            &[subcommand, &id],
+            &[("CLAUDE_CHILL_SESSION_DIR", session_dir.to_str().unwrap())],
+        )
+    }
+
+    fn spawn_args(args: &[&str], env: &[(&str, &str)]) -> Self {
+        let home = temp_dir("home");
+        let winsize = Winsize {
+            ws_row: ROWS,
+            ws_col: COLS,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        let pty = openpty(&winsize, None).unwrap();
+        let slave_fd = pty.slave.as_raw_fd();
+        let mut cmd = Command::new(env!("CARGO_BIN_EXE_claude-chill"));
+        cmd.args(args)
+            .env("HOME", &home)
+            .env("CLAUDE_CHILL_CONFIG_FILE", home.join("claude-chill.toml"))
+            .env("CLAUDE_CHILL_SESSION_DIR", home.join("claude-chill"))
+            .env("TERM", "xterm-256color")
+            .env("PS1", PROMPT)
+            .env("BASH_SILENCE_DEPRECATION_WARNING", "1")
+            .env_remove("CLAUDE_CHILL_LOG_FILE");
+        for (name, value) in env {
+            cmd.env(name, value);
+        }
+        let child = unsafe {
+            cmd.pre_exec(move || {
+                if libc::setsid() == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                if libc::ioctl(slave_fd, libc::TIOCSCTTY as libc::c_ulong, 0) == -1 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                for fd in 0..3 {
+                    if libc::dup2(slave_fd, fd) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            })
+            .spawn()
+            .unwrap()
+        };
+        drop(pty.slave);
+
+        let reader = pty.master.try_clone().unwrap();
+        let (sender, output) = channel();
+        thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            // Ends with EIO once the last process holding the PTY exits
+            while let Ok(n) = read(reader.as_fd(), &mut buf) {
+                if n == 0 || sender.send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Self {
+            child,
+            master: pty.master,
+            output,
+            screen: vt100::Parser::new(ROWS, COLS, 0),
+            home,
+        }
+    }
+
+    fn send(&mut self, data: &[u8]) {
+        let mut written = 0;
+        while written < data.len() {
+            written += write(&self.master, &data[written..]).unwrap();
+        }
+    }
+
+    /// Apply output as it arrives until `done` holds for the screen.
+    fn wait_for(&mut self, what: &str, done: impl Fn(&vt100::Screen) -> bool) {
+        let deadline = Instant::now() + TIMEOUT;
+        while !done(self.screen.screen()) {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.output.recv_timeout(remaining) {
+                Ok(data) => self.screen.process(&data),
+                Err(RecvTimeoutError::Timeout) => panic!(
+                    "timed out waiting for {}; screen:\n{}",
+                    what,
+                    self.screen.screen().contents()
+                ),
+                Err(RecvTimeoutError::Disconnected) => panic!(
+                    "proxy exited while waiting for {}; screen:\n{}",
+                    what,
+                    self.screen.screen().contents()
+                ),
+            }
+        }
+    }
+
+    fn wait_for_text(&mut self, text: &str) {
+        self.wait_for(&format!("{:?}", text), |screen| {
+            screen.contents().contains(text)
+        });
+    }
+
+    /// Wait for the prompt on the cursor's row, i.e. the shell is idle.
+    fn wait_for_prompt(&mut self) {
+        self.wait_for("prompt", |screen| {
+            let (row, _) = screen.cursor_position();
+            screen
+                .rows(0, COLS)
+                .nth(row as usize)
+                .is_some_and(|line| line.starts_with(PROMPT.trim_end()))
+        });
+    }
+
+    /// Run a shell command and wait for the prompt after its output.
+    fn run(&mut self, command: &str, expected: &str) {
+        self.send(format!("{}\r", command).as_bytes());
+        self.wait_for_text(expected);
+        self.wait_for_prompt();
+    }
+
+    fn enter_lookback(&mut self) {
+        self.send(LOOKBACK_KEY);
+        self.wait_for_text("LOOKBACK MODE");
+    }
+
+    fn exit_lookback(&mut self, key: &[u8]) {
+        self.send(key);
+        self.wait_for("lookback to end", |screen| {
+            !screen.contents().contains("LOOKBACK MODE")
+        });
+    }
+
+    fn resize(&mut self, rows: u16, cols: u16) {
+        let winsize = Winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        // The kernel sends SIGWINCH to claude-chill, the PTY's session leader
+        let ret = unsafe {
+            libc::ioctl(
+                self.master.as_raw_fd(),
+                libc::TIOCSWINSZ as libc::c_ulong,
+                &winsize,
+            )
+        };
+        assert_ne!(ret, -1, "TIOCSWINSZ failed");
+        self.screen.screen_mut().set_size(rows, cols);
+    }
+
+    /// Where the sessions started by this one live.
+    fn session_dir(&self) -> PathBuf {
+        self.home.join("claude-chill")
+    }
+
+    /// Send a control socket request and return the reply.
+    fn control(&self, request: &str) -> serde_json::Value {
+        let path = self.session_dir().join(format!("{}.ctl", self.child.id()));
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        stream.write_all(b"\n").unwrap();
+        let mut reply = String::new();
+        BufReader::new(&stream).read_line(&mut reply).unwrap();
+        serde_json::from_str(&reply).unwrap()
+    }
+
+    fn wait_exit(&mut self) -> i32 {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            // Keep draining so the proxy never blocks writing to us
+            while self.output.try_recv().is_ok() {}
+            if let Some(status) = self.child.try_wait().unwrap() {
+                return status.code().expect("claude-chill killed by a signal");
+            }
+            assert!(Instant::now() < deadline, "claude-chill did not exit");
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = std::fs::remove_dir_all(&self.home);
+    }
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "claude-chill-test-{}-{}-{}",
+        std::process::id(),
+        name,
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn bash() -> Session {
+    let mut session = Session::spawn(&["/bin/bash", "--norc", "--noprofile"]);
+    session.wait_for_prompt();
+    session
+}
+
+/// `fake-claude` sits next to `claude-chill` when the workspace is built.
+fn fake_claude() -> PathBuf {
+    let path = Path::new(env!("CARGO_BIN_EXE_claude-chill")).with_file_name("fake-claude");
+    assert!(
+        path.exists(),
+        "{} not found; run the tests with `cargo test --workspace`",
+        path.display()
+    );
+    path
+}
+
+fn write_scenario(contents: &str) -> PathBuf {
+    let path = temp_dir("scenario").join("scenario.toml");
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+/// A config file, for `CLAUDE_CHILL_CONFIG_FILE`.
+fn write_config(contents: &str) -> PathBuf {
+    let path = temp_dir("config").join("claude-chill.toml");
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+#[test]
+fn shell_output_and_clean_exit() {
+    let mut session = bash();
+    session.run("echo hello", "hello");
+    session.send(b"exit\r");
+    assert_eq!(session.wait_exit(), 0);
+}
+
+#[test]
+fn exit_code_is_propagated() {
+    let mut session = Session::spawn(&["/bin/sh", "-c", "exit 3"]);
+    assert_eq!(session.wait_exit(), 3);
+}
+
+#[test]
+fn child_killed_by_signal_exits_with_128_plus_signal() {
+    let mut session = Session::spawn(&["/bin/sh", "-c", "kill -TERM $$"]);
+    assert_eq!(session.wait_exit(), 128 + libc::SIGTERM);
+}
+
+#[test]
+fn command_from_config() {
+    let cwd = temp_dir("cwd");
+    let config = write_config(&format!(
+        r#"
+        command = "/bin/sh"
+        args = ["-c", "echo chill=$CLAUDE_CHILL greeting=$GREETING doomed=$DOOMED; pwd; exit 4"]
+        cwd = "{}"
+
+        [env]
+        set = {{ GREETING = "hello" }}
+        unset = ["DOOMED"]
+        "#,
+        cwd.display()
+    ));
+    let mut session = Session::spawn_args(
+        &[],
+        &[
+            ("CLAUDE_CHILL_CONFIG_FILE", config.to_str().unwrap()),
+            ("DOOMED", "still here"),
+        ],
+    );
+    session.wait_for_text("chill=1 greeting=hello doomed=\n");
+    session.wait_for_text(cwd.file_name().unwrap().to_str().unwrap());
+    assert_eq!(session.wait_exit(), 4);
+    std::fs::remove_dir_all(config.parent().unwrap()).unwrap();
+    std::fs::remove_dir_all(&cwd).unwrap();
+}
+
+#[test]
+fn command_named_like_a_subcommand() {
+    let bin = temp_dir("bin");
+    let script = bin.join("list");
+    std::fs::write(&script, "#!/bin/sh\necho listed \"$@\"\n").unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap());
+    let mut session = Session::spawn_args(&["--", "list", "-n", "1"], &[("PATH", &path)]);
+    session.wait_for_text("listed -n 1");
+    assert_eq!(session.wait_exit(), 0);
+    std::fs::remove_dir_all(&bin).unwrap();
+}
+
+#[test]
+fn no_command_is_a_usage_error() {
+    let mut session = Session::spawn_args(&[], &[]);
+    session.wait_for_text("no command given");
+    assert_eq!(session.wait_exit(), 2);
+}
+
+#[test]
+fn settings_from_the_environment() {
+    let mut session = Session::spawn_args(
+        &[],
+        &[
+            ("CLAUDE_CHILL_COMMAND", "/bin/sh"),
+            ("CLAUDE_CHILL_ARGS", r#"["-c", "echo from env; exit 5"]"#),
+        ],
+    );
+    session.wait_for_text("from env");
+    assert_eq!(session.wait_exit(), 5);
+}
+
+#[test]
+fn config_show_names_sources() {
+    let config = write_config("[restart]\nenabled = true\n");
+    let mut session = Session::spawn_args(
+        &["config", "show"],
+        &[
+            ("CLAUDE_CHILL_CONFIG_FILE", config.to_str().unwrap()),
+            ("CLAUDE_CHILL_WATCHDOG__SILENCE_TIMEOUT_MS", "60000"),
+        ],
+    );
+    session.wait_for_text("watchdog.silence_timeout_ms = 60000");
+    let contents = session.screen.screen().contents();
+    assert!(
+        contents.contains("# env CLAUDE_CHILL_WATCHDOG__SILENCE_TIMEOUT_MS"),
+        "{}",
+        contents
+    );
+    assert!(contents.contains("restart.enabled = true"));
+    assert!(contents.contains(&format!("# {}", config.display())));
+    assert!(contents.contains("restart.max_restarts = 3"));
+    assert_eq!(session.wait_exit(), 0);
+    std::fs::remove_dir_all(config.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn failed_command_is_restarted() {
+    let config = write_config(
+        r#"
+        [restart]
+        enabled = true
+        countdown_ms = 100
+        args = ["-c", "echo second run"]
+        "#,
+    );
+    let mut session = Session::spawn_with_env(
+        &["/bin/sh", "-c", "echo first run; exit 3"],
+        &[("CLAUDE_CHILL_CONFIG_FILE", config.to_str().unwrap())],
+    );
+    session.wait_for_text("second run");
+    let contents = session.screen.screen().contents();
+    assert!(contents.contains("first run"), "{}", contents);
+    assert!(contents.contains("restarted: /bin/sh -c echo second run"));
+    assert_eq!(session.wait_exit(), 0);
+    std::fs::remove_dir_all(config.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn restart_can_be_cancelled() {
+    let config = write_config("[restart]\nenabled = true\ncountdown_ms = 10000\n");
+    let mut session = Session::spawn_with_env(
+        &["/bin/sh", "-c", "exit 3"],
+        &[("CLAUDE_CHILL_CONFIG_FILE", config.to_str().unwrap())],
+    );
+    session.wait_for_text("restarting in 10s");
+    session.send(b"x");
+    assert_eq!(session.wait_exit(), 3);
+    std::fs::remove_dir_all(config.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn ctrl_c_reaches_the_child() {
+    let mut session = Session::spawn(&["/bin/sh", "-c", "echo ready; sleep 30"]);
+    session.wait_for_text("ready");
+    session.send(CTRL_C);
+    assert_eq!(session.wait_exit(), 128 + libc::SIGINT);
+}
+
+#[test]
+fn lookback_enter_and_exit() {
+    let mut session = bash();
+    for line in ["line1", "line2", "line3"] {
+        session.run(&format!("echo {}", line), line);
+    }
+    session.enter_lookback();
+    // History is replayed above the banner
+    assert!(session.screen.screen().contents().contains("line2"));
+    session.exit_lookback(LOOKBACK_KEY);
+    session.run("echo after_lookback", "after_lookback");
+    session.send(b"exit\r");
+    assert_eq!(session.wait_exit(), 0);
+}
+
+#[test]
+fn lookback_exit_with_ctrl_c() {
+    let mut session = bash();
+    session.run("echo test_output", "test_output");
+    session.enter_lookback();
+    session.exit_lookback(CTRL_C);
+    // Ctrl+C only left lookback; the shell never saw it
+    session.run("echo still_works", "still_works");
+    session.send(b"exit\r");
+    assert_eq!(session.wait_exit(), 0);
+}
+
+#[test]
+fn lookback_in_dumb_terminal() {
+    let mut session =
+        Session::spawn_with_env(&["/bin/bash", "--norc", "--noprofile"], &[("TERM", "dumb")]);
+    session.wait_for_prompt();
+    session.run("echo dumb_test", "dumb_test");
+    session.enter_lookback();
+    session.exit_lookback(LOOKBACK_KEY);
+    session.run("echo after_dumb_lookback", "after_dumb_lookback");
+}
+
+#[test]
+fn alt_screen_passes_keys_through_and_restores_main_screen() {
+    let scenario = write_scenario(
+        r#"
+        [[step]]
+        type = "conversation"
+        turns = 3
+
+        [[step]]
+        type = "redraw"
+
+        [[step]]
+        type = "print"
+        text = "\u001b[?1049h\u001b[H\u001b[2JEDITOR"
+
+        # The lookback key must reach the child while it owns the screen
+        [[step]]
+        type = "wait_input"
+        key = "\u001e"
+
+        [[step]]
+        type = "print"
+        text = "GOT KEY"
+
+        [[step]]
+        type = "wait_input"
+        key = "q"
+
+        [[step]]
+        type = "print"
+        text = "\u001b[?1049l"
+
+        [[step]]
+        type = "wait_input"
+        key = "x"
+
+        [[step]]
+        type = "exit"
+        code = 7
+        "#,
+    );
+    let fake_claude = fake_claude();
+    let mut session = Session::spawn(&[
+        fake_claude.to_str().unwrap(),
+        "--delay",
+        "0",
+        scenario.to_str().unwrap(),
+    ]);
+    session.wait_for_text("EDITOR");
+    assert!(session.screen.screen().alternate_screen());
+
+    session.send(LOOKBACK_KEY);
+    session.wait_for_text("GOT KEY");
+    assert!(!session.screen.screen().contents().contains("LOOKBACK"));
+
+    session.send(b"q");
+    session.wait_for("main screen", |screen| {
+        !screen.alternate_screen() && screen.contents().contains("Question 2")
+    });
+
+    // Lookback works again once the alt screen is gone
+    session.enter_lookback();
+    session.exit_lookback(LOOKBACK_KEY);
+
+    session.send(b"x");
+    assert_eq!(session.wait_exit(), 7);
+    let _ = std::fs::remove_dir_all(scenario.parent().unwrap());
+}
+
+#[test]
+fn full_redraws_end_on_the_same_screen() {
+    let scenario = write_scenario(
+        r#"
+        [[step]]
+        type = "conversation"
+        turns = 200
+
+        [[step]]
+        type = "redraw"
+        frames = 5
+
+        [[step]]
+        type = "stream"
+        text = "streamed answer"
+        interval_ms = 0
+
+        [[step]]
+        type = "print"
+        text = "\r\nDONE"
+
+        [[step]]
+        type = "wait_input"
+        "#,
+    );
+    let fake_claude = fake_claude();
+    let mut session = Session::spawn(&[
+        fake_claude.to_str().unwrap(),
+        "--delay",
+        "0",
+        scenario.to_str().unwrap(),
+    ]);
+    session.wait_for_text("DONE");
+    let contents = session.screen.screen().contents();
+    assert!(contents.contains("Question 199 about the code"));
+    assert!(contents.contains("⏺ streamed answer"));
+    assert_eq!(contents.matches("╭").count(), 1);
+    session.send(b"\r");
+    assert_eq!(session.wait_exit(), 0);
+    let _ = std::fs::remove_dir_all(scenario.parent().unwrap());
+}
+
+#[test]
+fn resize_is_forwarded_to_the_child() {
+    let mut session = bash();
+    session.run("stty size", &format!("{} {}", ROWS, COLS));
+    session.resize(30, 100);
+    session.run("stty size", "30 100");
+    session.send(b"exit\r");
+    assert_eq!(session.wait_exit(), 0);
+}
+
+#[test]
+fn detach_and_attach() {
+    let mut host = Session::spawn_with_env(
+        &["/bin/bash", "--norc", "--noprofile"],
+        &[("CLAUDE_CHILL_DETACH_KEY", "[ctrl][backslash]")],
+    );
+    host.wait_for_prompt();
+    host.run("X=kept; echo before_detach", "before_detach");
+
+    // The session is in use by the terminal it started in
+    let mut refused = Session::attach(&host);
+    refused.wait_for_text("refused");
+    assert_eq!(refused.wait_exit(), 1);
+
+    host.send(DETACH_KEY);
+    host.wait_for_text("detached from session");
+    assert_eq!(host.wait_exit(), 0);
+
+    let mut client = Session::attach(&host);
+    client.wait_for_text("before_detach");
+    client.run("echo $X", "kept");
+    client.send(DETACH_KEY);
+    client.wait_for_text("[detached]");
+    assert_eq!(client.wait_exit(), 0);
+
+    let mut client = Session::attach(&host);
+    client.wait_for_text("kept");
+    client.send(b"exit 7\r");
+    // The session is still the command's parent after detaching
+    assert_eq!(client.wait_exit(), 7);
+}
+
+#[test]
+fn hangup_keeps_the_session() {
+    let mut host = bash();
+    host.run("echo before_hangup", "before_hangup");
+    let pid = nix::unistd::Pid::from_raw(host.child.id() as i32);
+    nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGHUP).unwrap();
+
+    let mut client = Session::attach(&host);
+    client.wait_for_text("before_hangup");
+    client.resize(30, 100);
+    client.run("stty size", "30 100");
+    client.send(b"exit 7\r");
+    assert_eq!(client.wait_exit(), 7);
+    assert_eq!(host.wait_exit(), 7);
+}
+
+#[test]
+fn session_goes_down_with_a_killed_original() {
+    let mut host = bash();
+    let socket = host.session_dir().join(format!("{}.sock", host.child.id()));
+    assert!(UnixStream::connect(&socket).is_ok());
+    host.child.kill().unwrap();
+    host.child.wait().unwrap();
+    let deadline = Instant::now() + TIMEOUT;
+    while UnixStream::connect(&socket).is_ok() {
+        assert!(Instant::now() < deadline, "session outlived the original");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn no_session_process_without_detaching() {
+    // A session dir others can get into means no session socket
+    let shared = temp_dir("shared");
+    std::fs::set_permissions(&shared, std::fs::Permissions::from_mode(0o777)).unwrap();
+    let mut session = Session::spawn_with_env(
+        &["/bin/sh", "-c", "echo parent=$PPID"],
+        &[("CLAUDE_CHILL_SESSION_DIR", shared.to_str().unwrap())],
+    );
+    session.wait_for_text(&format!("parent={}", session.child.id()));
+    assert_eq!(session.wait_exit(), 0);
+    std::fs::remove_dir_all(&shared).unwrap();
+}
+
+#[test]
+fn viewers_watch_but_cannot_type() {
+    let mut host = bash();
+    host.run("echo before_watch", "before_watch");
+
+    let mut viewer = Session::watch(&host);
+    viewer.wait_for_text("before_watch");
+    host.run("echo shared", "shared");
+    viewer.wait_for_text("shared");
+
+    viewer.send(b"echo from_viewer\r");
+    host.run("echo after_viewer", "after_viewer");
+    viewer.wait_for_text("after_viewer");
+    assert!(!host.screen.screen().contents().contains("from_viewer"));
+
+    // A second viewer starts from the whole screen
+    let mut late = Session::watch(&host);
+    late.wait_for_text("after_viewer");
+    assert!(late.screen.screen().contents().contains("before_watch"));
+
+    viewer.send(b"q");
+    assert_eq!(viewer.wait_exit(), 0);
+    host.run("echo still_watched", "still_watched");
+    late.wait_for_text("still_watched");
+
+    host.send(b"exit\r");
+    assert_eq!(host.wait_exit(), 0);
+    late.wait_for_text("[session ended]");
+    assert_eq!(late.wait_exit(), 0);
+}
+
+#[test]
+fn control_socket_queries_and_drives_the_session() {
+    let mut host = bash();
+    host.run("echo \"$CLAUDE_CHILL_CONTROL\"", ".ctl");
+
+    let state = host.control(r#"{"id": 1, "cmd": "get-state"}"#);
+    assert_eq!(state["id"], 1);
+    assert_eq!(state["ok"], true);
+    assert_eq!(state["mode"], "normal");
+    assert_eq!(state["attached"], "terminal");
+    assert_eq!(state["rows"], ROWS);
+
+    let reply = host.control(r#"{"cmd": "send-input", "data": "echo via_control\r"}"#);
+    assert_eq!(reply["ok"], true);
+    host.wait_for_text("via_control");
+    host.wait_for_prompt();
+
+    let screen = host.control(r#"{"cmd": "get-screen-text"}"#);
+    assert!(
+        screen["text"]
+            .as_str()
+            .unwrap()
+            .contains("echo via_control")
+    );
+    let history = host.control(r#"{"cmd": "dump-history", "lines": 3}"#);
+    assert!(history["history"].as_str().unwrap().contains("via_control"));
+
+    let reply = host.control(r#"{"cmd": "toggle-lookback"}"#);
+    assert_eq!(reply["lookback"], true);
+    host.wait_for_text("LOOKBACK MODE");
+    let reply = host.control(r#"{"cmd": "toggle-lookback"}"#);
+    assert_eq!(reply["lookback"], false);
+
+    let reply = host.control(r#"{"cmd": "resize", "rows": 0, "cols": 10}"#);
+    assert_eq!(reply["ok"], false);
+    let reply = host.control(r#"{"cmd": "resize", "rows": 20, "cols": 60}"#);
+    assert_eq!(reply["ok"], true);
+    host.screen.screen_mut().set_size(20, 60);
+    host.run("stty size", "20 60");
+
+    host.send(b"exit\r");
+    assert_eq!(host.wait_exit(), 0);
+}
@@ -0,0 +1,218 @@
+//! Imitates Claude Code's terminal output so claude-chill can be tested and
+//! demonstrated without Claude or a network connection.
+//!
+//! The output is driven by a scenario file; see `scenarios/` for examples.
+
+mod scenario;
+mod session;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use nix::sys::termios::{SetArg, Termios, cfmakeraw, tcgetattr, tcsetattr};
+use nix::unistd::{isatty, read};
+use scenario::{Scenario, Step};
+use session::Session;
+use std::io::{self, Stdout};
+use std::os::fd::{AsFd, AsRawFd};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::thread::sleep;
+use std::time::Duration;
+
+/// How long to wait for the terminal to answer a query before moving on.
+const QUERY_REPLY_TIMEOUT_MS: i32 = 100;
+
+#[derive(Parser)]
+#[command(
+    name = "fake-claude",
+    about = "Imitates Claude Code's terminal output for testing claude-chill"
+)]
+struct Cli {
+    /// Scenario file to play (default: a short demo)
+    scenario: Option<PathBuf>,
+
+    /// Multiply every delay by this factor, e.g. 0 to run flat out
+    #[arg(long, default_value_t = 1.0)]
+    delay: f64,
+}
+
+struct Runner {
+    session: Session<Stdout>,
+    delay: f64,
+}
+
+impl Runner {
+    fn run(&mut self, steps: &[Step]) -> Result<Option<i32>> {
+        for step in steps {
+            self.session.set_width(terminal_width());
+            match step {
+                Step::Conversation { turns } => self.session.add_turns(*turns),
+                Step::Redraw {
+                    frames,
+                    interval_ms,
+                } => {
+                    for frame in 0..*frames {
+                        if frame > 0 {
+                            self.pause(*interval_ms);
+                        }
+                        self.session.redraw()?;
+                    }
+                }
+                Step::Spinner {
+                    frames,
+                    interval_ms,
+                    label,
+                } => {
+                    for frame in 0..*frames {
+                        self.session.spinner_frame(frame, label)?;
+                        self.pause(*interval_ms);
+                    }
+                    self.session.stop_spinner()?;
+                }
+                Step::Stream {
+                    text,
+                    chunk,
+                    interval_ms,
+                } => {
+                    let chars: Vec<char> = text.chars().collect();
+                    for piece in chars.chunks((*chunk).max(1)) {
+                        self.session.stream(&piece.iter().collect::<String>())?;
+                        self.pause(*interval_ms);
+                    }
+                    self.session.finish_response()?;
+                }
+                Step::Query { query } => {
+                    self.session.write(query.sequence())?;
+                    discard_reply();
+                }
+                Step::AltScreen { text, duration_ms } => {
+                    self.session.write(b"\x1b[?1049h\x1b[H\x1b[2J")?;
+                    self.session.write(text.as_bytes())?;
+                    self.pause(*duration_ms);
+                    self.session.write(b"\x1b[?1049l")?;
+                }
+                Step::Bell {} => self.session.write(b"\x07")?,
+                Step::Print { text } => self.session.write(text.as_bytes())?,
+                Step::Sleep { ms } => self.pause(*ms),
+                Step::WaitInput { key } => wait_input(key.as_deref())?,
+                Step::Exit { code } => return Ok(Some(*code)),
+            }
+        }
+        Ok(None)
+    }
+
+    fn pause(&self, ms: u64) {
+        sleep(Duration::from_millis(ms).mul_f64(self.delay));
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let scenario = match &cli.scenario {
+        Some(path) => Scenario::load(path),
+        None => Scenario::parse(scenario::DEMO),
+    };
+    let scenario = match scenario {
+        Ok(scenario) => scenario,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            return ExitCode::from(2);
+        }
+    };
+
+    // Claude reads keys as they're typed, so the proxy forwards them raw
+    let original = match setup_raw_mode() {
+        Ok(original) => original,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let mut runner = Runner {
+        session: Session::new(io::stdout(), terminal_width()),
+        delay: cli.delay.max(0.0),
+    };
+    let result = runner.run(&scenario.steps);
+    let _ = runner.session.write(b"\r\n");
+    if let Some(original) = original {
+        let _ = tcsetattr(io::stdin(), SetArg::TCSANOW, &original);
+    }
+
+    match result {
+        Ok(code) => ExitCode::from(code.unwrap_or(0) as u8),
+        Err(e) => {
+            eprintln!("{:#}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn setup_raw_mode() -> Result<Option<Termios>> {
+    let stdin = io::stdin();
+    if !isatty(&stdin).unwrap_or(false) {
+        return Ok(None);
+    }
+    let original = tcgetattr(&stdin).context("tcgetattr failed")?;
+    let mut raw = original.clone();
+    cfmakeraw(&mut raw);
+    tcsetattr(&stdin, SetArg::TCSANOW, &raw).context("tcsetattr failed")?;
+    Ok(Some(original))
+}
+
+fn terminal_width() -> u16 {
+    let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+    let ret = unsafe {
+        libc::ioctl(
+            io::stdout().as_raw_fd(),
+            libc::TIOCGWINSZ as libc::c_ulong,
+            &mut ws,
+        )
+    };
+    if ret == -1 || ws.ws_col == 0 {
+        80
+    } else {
+        ws.ws_col
+    }
+}
+
+/// Whether stdin has input within `timeout_ms`.
+fn stdin_ready(timeout_ms: i32) -> bool {
+    let mut fds = libc::pollfd {
+        fd: io::stdin().as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut fds, 1, timeout_ms) > 0 }
+}
+
+/// Read and drop a query's reply so it isn't mistaken for typing.
+fn discard_reply() {
+    let mut buf = [0u8; 256];
+    while stdin_ready(QUERY_REPLY_TIMEOUT_MS) {
+        match read(io::stdin().as_fd(), &mut buf) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+    }
+}
+
+fn wait_input(key: Option<&str>) -> Result<()> {
+    let key = key.filter(|key| !key.is_empty());
+    let mut typed = Vec::new();
+    let mut buf = [0u8; 256];
+    loop {
+        // Unbuffered, so nothing read here is hidden from `stdin_ready`
+        let n = read(io::stdin().as_fd(), &mut buf).context("failed to read stdin")?;
+        if n == 0 {
+            return Ok(());
+        }
+        typed.extend_from_slice(&buf[..n]);
+        match key {
+            None => return Ok(()),
+            Some(key) if typed.windows(key.len()).any(|w| w == key.as_bytes()) => {
+                return Ok(());
+            }
+            Some(_) => {}
+        }
+    }
+}
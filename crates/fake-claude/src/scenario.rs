@@ -0,0 +1,236 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+
+/// A script of output behaviors, read from a TOML file of `[[step]]` tables
+/// run in order.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(rename = "step", default)]
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Step {
+    /// Add finished turns to the conversation without drawing anything.
+    Conversation {
+        turns: usize,
+    },
+    /// Clear the screen and reprint the whole conversation in a sync block.
+    Redraw {
+        #[serde(default = "default_frames")]
+        frames: usize,
+        #[serde(default)]
+        interval_ms: u64,
+    },
+    /// Animate the spinner line below the conversation.
+    Spinner {
+        frames: usize,
+        #[serde(default = "default_interval_ms")]
+        interval_ms: u64,
+        #[serde(default = "default_label")]
+        label: String,
+    },
+    /// Stream a response a few characters at a time, redrawing the live area
+    /// after each chunk. The response joins the conversation when done.
+    Stream {
+        text: String,
+        #[serde(default = "default_chunk")]
+        chunk: usize,
+        #[serde(default = "default_interval_ms")]
+        interval_ms: u64,
+    },
+    /// Ask the terminal something it answers on stdin.
+    Query {
+        query: Query,
+    },
+    /// Switch to the alternate screen, show some text and switch back.
+    AltScreen {
+        text: String,
+        duration_ms: u64,
+    },
+    Bell {},
+    /// Write text as-is; escape sequences are written as `\u001b` in TOML.
+    Print {
+        text: String,
+    },
+    Sleep {
+        ms: u64,
+    },
+    /// Block until input arrives, or until `key` has been typed if given.
+    WaitInput {
+        #[serde(default)]
+        key: Option<String>,
+    },
+    Exit {
+        code: i32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Query {
+    /// Primary device attributes, `CSI c`.
+    Da,
+    /// Secondary device attributes, `CSI > c`.
+    Da2,
+    /// Cursor position report, `CSI 6 n`.
+    Dsr,
+    /// Foreground color, `OSC 10 ; ?`.
+    OscForeground,
+    /// Background color, `OSC 11 ; ?`.
+    OscBackground,
+}
+
+impl Query {
+    pub fn sequence(self) -> &'static [u8] {
+        match self {
+            Query::Da => b"\x1b[c",
+            Query::Da2 => b"\x1b[>c",
+            Query::Dsr => b"\x1b[6n",
+            Query::OscForeground => b"\x1b]10;?\x1b\\",
+            Query::OscBackground => b"\x1b]11;?\x1b\\",
+        }
+    }
+}
+
+fn default_frames() -> usize {
+    1
+}
+
+fn default_interval_ms() -> u64 {
+    80
+}
+
+fn default_label() -> String {
+    "Thinking…".to_string()
+}
+
+fn default_chunk() -> usize {
+    4
+}
+
+impl Scenario {
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read scenario {}", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("invalid scenario {}", path.display()))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+}
+
+/// Played when no scenario file is given: a short session showing each
+/// behavior once.
+pub const DEMO: &str = r#"
+[[step]]
+type = "conversation"
+turns = 40
+
+[[step]]
+type = "redraw"
+
+[[step]]
+type = "query"
+query = "da"
+
+[[step]]
+type = "spinner"
+frames = 12
+
+[[step]]
+type = "stream"
+text = "Here is a response that streams in a few characters at a time, the way Claude prints its answers."
+
+[[step]]
+type = "bell"
+
+[[step]]
+type = "alt_screen"
+text = "An editor would be here."
+duration_ms = 500
+
+[[step]]
+type = "redraw"
+frames = 3
+interval_ms = 100
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_demo_parses() {
+        let scenario = Scenario::parse(DEMO).unwrap();
+        assert_eq!(scenario.steps.len(), 8);
+        assert_eq!(scenario.steps[0], Step::Conversation { turns: 40 });
+    }
+
+    #[test]
+    fn test_defaults() {
+        let scenario = Scenario::parse(
+            r#"
+            [[step]]
+            type = "redraw"
+
+            [[step]]
+            type = "stream"
+            text = "hi"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            scenario.steps,
+            vec![
+                Step::Redraw {
+                    frames: 1,
+                    interval_ms: 0
+                },
+                Step::Stream {
+                    text: "hi".to_string(),
+                    chunk: 4,
+                    interval_ms: 80
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_queries_and_escapes() {
+        let scenario = Scenario::parse(
+            r#"
+            [[step]]
+            type = "query"
+            query = "osc_background"
+
+            [[step]]
+            type = "print"
+            text = "\u001b[31mred"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            scenario.steps,
+            vec![
+                Step::Query {
+                    query: Query::OscBackground
+                },
+                Step::Print {
+                    text: "\x1b[31mred".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(Scenario::parse("[[step]]\ntype = \"dance\"").is_err());
+        assert!(Scenario::parse("[[step]]\ntype = \"bell\"\nloud = true").is_err());
+        assert!(Scenario::parse("[[step]]\ntype = \"sleep\"").is_err());
+    }
+}
@@ -0,0 +1,215 @@
+//! Draws a conversation the way Claude Code does: everything above the
+//! prompt is printed once, the live area at the bottom (the response being
+//! streamed, the spinner and the input box) is erased and reprinted in a sync
+//! block on every update, and now and then the whole screen is cleared and
+//! the entire conversation printed again.
+
+use std::io::{Result, Write};
+
+const SYNC_START: &[u8] = b"\x1b[?2026h";
+const SYNC_END: &[u8] = b"\x1b[?2026l";
+const SPINNER: [&str; 6] = ["·", "✢", "✳", "✶", "✻", "✽"];
+const LINES_PER_TURN: usize = 5;
+
+pub struct Session<W: Write> {
+    out: W,
+    cols: u16,
+    conversation: Vec<String>,
+    response: String,
+    spinner: Option<String>,
+    /// Lines of live area on screen, with the cursor on the last one.
+    live_height: usize,
+}
+
+impl<W: Write> Session<W> {
+    pub fn new(out: W, cols: u16) -> Self {
+        Self {
+            out,
+            cols,
+            conversation: Vec::new(),
+            response: String::new(),
+            spinner: None,
+            live_height: 0,
+        }
+    }
+
+    pub fn set_width(&mut self, cols: u16) {
+        self.cols = cols.max(10);
+    }
+
+    pub fn add_turns(&mut self, turns: usize) {
+        let start = self.conversation.len() / LINES_PER_TURN;
+        for turn in start..start + turns {
+            self.conversation.extend([
+                format!("\x1b[1m>\x1b[0m Question {} about the code", turn),
+                String::new(),
+                format!(
+                    "\x1b[38;5;174m⏺\x1b[39m Answer {} explaining what changed and why",
+                    turn
+                ),
+                format!(
+                    "  \x1b[38;5;244m⎿\x1b[39m  Read \x1b[1msrc/module_{}.rs\x1b[0m ({} lines)",
+                    turn,
+                    40 + turn % 200
+                ),
+                String::new(),
+            ]);
+        }
+    }
+
+    /// Clear the screen and print the whole conversation and live area.
+    pub fn redraw(&mut self) -> Result<()> {
+        let mut frame = Vec::new();
+        frame.extend_from_slice(SYNC_START);
+        frame.extend_from_slice(b"\x1b[2J\x1b[3J\x1b[H");
+        for line in &self.conversation {
+            frame.extend_from_slice(line.as_bytes());
+            frame.extend_from_slice(b"\r\n");
+        }
+        let live = self.live_lines();
+        frame.extend_from_slice(live.join("\r\n").as_bytes());
+        frame.extend_from_slice(SYNC_END);
+        self.live_height = live.len();
+        self.write(&frame)
+    }
+
+    pub fn spinner_frame(&mut self, frame: usize, label: &str) -> Result<()> {
+        self.spinner = Some(format!(
+            "\x1b[38;5;174m{} {}\x1b[39m \x1b[38;5;244m({}s · esc to interrupt)\x1b[39m",
+            SPINNER[frame % SPINNER.len()],
+            label,
+            frame / 10
+        ));
+        self.draw_live(&[])
+    }
+
+    pub fn stop_spinner(&mut self) -> Result<()> {
+        self.spinner = None;
+        self.draw_live(&[])
+    }
+
+    pub fn stream(&mut self, text: &str) -> Result<()> {
+        self.response.push_str(text);
+        self.draw_live(&[])
+    }
+
+    /// Move the streamed response out of the live area into the conversation.
+    pub fn finish_response(&mut self) -> Result<()> {
+        let mut lines = self.response_lines();
+        lines.push(String::new());
+        self.response.clear();
+        self.draw_live(&lines)?;
+        self.conversation.extend(lines);
+        Ok(())
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.out.write_all(data)?;
+        self.out.flush()
+    }
+
+    /// Erase the live area and print it again, after any lines that are
+    /// leaving it for good.
+    fn draw_live(&mut self, finished: &[String]) -> Result<()> {
+        let mut frame = Vec::new();
+        frame.extend_from_slice(SYNC_START);
+        if self.live_height > 0 {
+            frame.push(b'\r');
+            if self.live_height > 1 {
+                frame.extend_from_slice(format!("\x1b[{}A", self.live_height - 1).as_bytes());
+            }
+        }
+        frame.extend_from_slice(b"\x1b[J");
+        for line in finished {
+            frame.extend_from_slice(line.as_bytes());
+            frame.extend_from_slice(b"\r\n");
+        }
+        let live = self.live_lines();
+        frame.extend_from_slice(live.join("\r\n").as_bytes());
+        frame.extend_from_slice(SYNC_END);
+        self.live_height = live.len();
+        self.write(&frame)
+    }
+
+    fn live_lines(&self) -> Vec<String> {
+        let mut lines = self.response_lines();
+        lines.push(String::new());
+        lines.extend(self.spinner.clone());
+        let inner = self.cols as usize - 2;
+        lines.push(format!("\x1b[38;5;244m╭{}╮\x1b[39m", "─".repeat(inner)));
+        lines.push(format!(
+            "\x1b[38;5;244m│\x1b[39m > {}\x1b[38;5;244m│\x1b[39m",
+            " ".repeat(inner - 3)
+        ));
+        lines.push(format!("\x1b[38;5;244m╰{}╯\x1b[39m", "─".repeat(inner)));
+        lines
+    }
+
+    /// The response so far, wrapped to the terminal width.
+    fn response_lines(&self) -> Vec<String> {
+        if self.response.is_empty() {
+            return Vec::new();
+        }
+        let width = self.cols as usize - 2;
+        let chars: Vec<char> = self.response.chars().collect();
+        chars
+            .chunks(width)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let text: String = chunk.iter().collect();
+                if i == 0 {
+                    format!("\x1b[38;5;174m⏺\x1b[39m {}", text)
+                } else {
+                    format!("  {}", text)
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen(session: &Session<Vec<u8>>, rows: u16, cols: u16) -> vt100::Parser {
+        let mut parser = vt100::Parser::new(rows, cols, 0);
+        parser.process(&session.out);
+        parser
+    }
+
+    #[test]
+    fn test_redraw_is_one_full_sync_block() {
+        let mut session = Session::new(Vec::new(), 40);
+        session.add_turns(3);
+        session.redraw().unwrap();
+        assert!(session.out.starts_with(b"\x1b[?2026h\x1b[2J\x1b[3J\x1b[H"));
+        assert!(session.out.ends_with(SYNC_END));
+        let parser = screen(&session, 24, 40);
+        let contents = parser.screen().contents();
+        assert!(contents.contains("Question 2 about the code"));
+        assert!(contents.contains("│ >"));
+    }
+
+    #[test]
+    fn test_stream_rewrites_live_area_in_place() {
+        let mut session = Session::new(Vec::new(), 40);
+        session.add_turns(1);
+        session.redraw().unwrap();
+        session.spinner_frame(0, "Thinking…").unwrap();
+        session.stop_spinner().unwrap();
+        for word in ["Hello ", "there, ", "world"] {
+            session.stream(word).unwrap();
+        }
+        session.finish_response().unwrap();
+        let parser = screen(&session, 24, 40);
+        let contents = parser.screen().contents();
+        assert_eq!(contents.matches("Hello there, world").count(), 1);
+        assert_eq!(contents.matches("╭").count(), 1);
+        assert!(!contents.contains("Thinking"));
+        // The finished response stays above the prompt box
+        let rows: Vec<String> = parser.screen().rows(0, 40).collect();
+        let response = rows.iter().position(|r| r.contains("Hello")).unwrap();
+        let prompt = rows.iter().position(|r| r.contains("│ >")).unwrap();
+        assert!(response < prompt);
+    }
+}